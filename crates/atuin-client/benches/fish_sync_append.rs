@@ -0,0 +1,153 @@
+//! Syncing a batch of new entries into a fish history file of realistic size
+//!
+//! Every write reads and indexes the whole file for dedup, so how the cost of a sync grows with
+//! the file, and what batching saves, only shows up end to end. Compare numbers for changes to
+//! dedup, trimming or batching with
+//!
+//! ```text
+//! cargo bench -p atuin-client --bench fish_sync_append
+//! ```
+
+use std::path::Path;
+
+use atuin_client::fish_sync::{CommandEntry, FishSyncOptions, FishSyncer};
+use divan::counter::BytesCount;
+use time::OffsetDateTime;
+
+fn main() {
+    // SAFETY: nothing else is running yet
+    unsafe { std::env::set_var(atuin_client::fish_sync::sandbox::SANDBOX_ENV, "1") };
+
+    divan::main();
+}
+
+/// Entries already in the file
+const EXISTING: &[usize] = &[1_000, 10_000, 100_000];
+
+/// New entries in the batch being synced
+const BATCH: usize = 100;
+
+/// Batch sizes for [`batch_size`], synced into a file of [`BATCH_EXISTING`] entries
+const BATCH_SIZES: &[usize] = &[10, 100, 1_000];
+
+const BATCH_EXISTING: usize = 10_000;
+
+/// Entries [`dedup_index_tail`] reads from the end of the file
+const TAIL_ENTRIES: usize = 1_000;
+
+/// A file as fish leaves it, `existing` entries long
+fn existing_file(existing: usize) -> String {
+    (0..existing)
+        .map(|i| format!("- cmd:cargo build --package crate-{i}\n  when:{i}\n"))
+        .collect()
+}
+
+fn new_entries(existing: usize, count: usize) -> Vec<CommandEntry> {
+    (0..count)
+        .map(|i| {
+            let when = OffsetDateTime::from_unix_timestamp((existing + i) as i64).unwrap();
+            CommandEntry::new(format!("git commit -m 'change {i}'"), when)
+                .with_uuid(format!("{:032x}", existing + i))
+        })
+        .collect()
+}
+
+/// How many bytes syncing the batch adds to the file, so results can be read as throughput
+fn bytes_written(dir: &Path, existing: &str, entries: &[CommandEntry]) -> u64 {
+    let path = dir.join("measure");
+    fs_err::write(&path, existing).unwrap();
+
+    FishSyncer::open(&path, FishSyncOptions::default())
+        .unwrap()
+        .append(entries)
+        .unwrap();
+
+    fs_err::metadata(&path).unwrap().len() - existing.len() as u64
+}
+
+fn bench_sync(
+    bencher: divan::Bencher,
+    existing: usize,
+    count: usize,
+    write: fn(&FishSyncer, &[CommandEntry]),
+) {
+    let dir = tempfile::tempdir().unwrap();
+    let content = existing_file(existing);
+    let entries = new_entries(existing, count);
+    let path = dir.path().join("fish_history");
+
+    bencher
+        .counter(BytesCount::new(bytes_written(
+            dir.path(),
+            &content,
+            &entries,
+        )))
+        .with_inputs(|| {
+            fs_err::write(&path, &content).unwrap();
+            FishSyncer::open(&path, FishSyncOptions::default()).unwrap()
+        })
+        .bench_local_refs(|syncer| write(syncer, divan::black_box(&entries)));
+}
+
+/// The whole batch in one write, as a sync of downloaded entries does
+#[divan::bench(args = EXISTING, sample_count = 20)]
+fn batched(bencher: divan::Bencher, existing: usize) {
+    bench_sync(bencher, existing, BATCH, |syncer, entries| {
+        syncer.append(entries).unwrap();
+    });
+}
+
+/// Batches of growing size in one write each, which should cost little more than the bytes
+/// written once the file has been read
+#[divan::bench(args = BATCH_SIZES, sample_count = 20)]
+fn batch_size(bencher: divan::Bencher, count: usize) {
+    bench_sync(bencher, BATCH_EXISTING, count, |syncer, entries| {
+        syncer.append(entries).unwrap();
+    });
+}
+
+/// One write per entry, as when every entry is synced on its own
+#[divan::bench(args = EXISTING, sample_count = 20)]
+fn per_entry(bencher: divan::Bencher, existing: usize) {
+    bench_sync(bencher, existing, BATCH, |syncer, entries| {
+        for entry in entries {
+            syncer.append(std::slice::from_ref(entry)).unwrap();
+        }
+    });
+}
+
+/// Reading the file to look for one uuid, the least a dedup check can do
+#[divan::bench(args = EXISTING, sample_count = 20)]
+fn contains_uuid(bencher: divan::Bencher, existing: usize) {
+    let dir = tempfile::tempdir().unwrap();
+    let syncer =
+        FishSyncer::open(dir.path().join("fish_history"), FishSyncOptions::default()).unwrap();
+    fs_err::write(syncer.path(), existing_file(existing)).unwrap();
+
+    bencher.bench_local(|| syncer.contains(divan::black_box("0")).unwrap());
+}
+
+/// Reading the file once into the index every dedup check uses, uuids and commands together. It
+/// should cost no more than two [`contains_uuid`] scans, one for uuids and one for commands, and
+/// then answers for any number of entries
+#[divan::bench(args = EXISTING, sample_count = 20)]
+fn dedup_index(bencher: divan::Bencher, existing: usize) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("fish_history");
+    fs_err::write(&path, existing_file(existing)).unwrap();
+
+    bencher.bench_local(|| atuin_client::fish_sync::load_dedup_index(divan::black_box(&path)));
+}
+
+/// Indexing only the newest entries, reading the file from the end, which should take the same
+/// time whatever the file's length
+#[divan::bench(args = EXISTING, sample_count = 20)]
+fn dedup_index_tail(bencher: divan::Bencher, existing: usize) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("fish_history");
+    fs_err::write(&path, existing_file(existing)).unwrap();
+
+    bencher.bench_local(|| {
+        atuin_client::fish_sync::load_dedup_index_tail(divan::black_box(&path), TAIL_ENTRIES)
+    });
+}
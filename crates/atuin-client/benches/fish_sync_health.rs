@@ -0,0 +1,38 @@
+//! `atuin fish-sync ok` runs on every shell startup, so it has to stay cheap
+
+use atuin_client::fish_sync::{self, meta::FishSyncMeta};
+use atuin_client::settings::{FishSync, Settings};
+
+fn main() {
+    // SAFETY: nothing else is running yet
+    unsafe { std::env::set_var(atuin_client::fish_sync::sandbox::SANDBOX_ENV, "1") };
+
+    divan::main();
+}
+
+#[divan::bench]
+fn health(bencher: divan::Bencher) {
+    let dir = tempfile::tempdir().unwrap();
+    let sibling = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+
+    let settings = Settings {
+        db_path: sibling("history.db"),
+        record_store_path: sibling("records.db"),
+        fish_sync: FishSync {
+            enabled: true,
+            history_path: sibling("fish_history"),
+            ..FishSync::default()
+        },
+        ..Settings::default()
+    };
+
+    // a meta file as left behind by a typical sync
+    let fish: String = (0..5000)
+        .map(|i| format!("- cmd:command {i}\n  when:{i}\n"))
+        .collect();
+    let mut meta = FishSyncMeta::default();
+    meta.record_sync(100, &fish);
+    meta.save(&FishSyncMeta::path(&settings)).unwrap();
+
+    bencher.bench(|| fish_sync::health(divan::black_box(&settings)));
+}
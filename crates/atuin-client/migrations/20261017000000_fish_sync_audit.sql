@@ -0,0 +1,8 @@
+-- Which code path wrote each fish history entry, when fish_sync.audit is on
+create table if not exists fish_sync_audit (
+	id text not null,
+	source text not null,
+	timestamp integer not null
+);
+
+create index if not exists idx_fish_sync_audit_source on fish_sync_audit(source);
@@ -131,6 +131,25 @@ pub async fn latest_version() -> Result<Version> {
     Ok(version)
 }
 
+/// Whether a connection to the sync server can be opened within `timeout`
+///
+/// A cheap way to notice we're offline before a real request waits out the full network timeout.
+/// It says nothing about whether the server is healthy.
+pub async fn server_reachable(sync_addr: &str, timeout: Duration) -> bool {
+    let Ok(url) = Url::parse(sync_addr) else {
+        return false;
+    };
+
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return false;
+    };
+
+    matches!(
+        tokio::time::timeout(timeout, tokio::net::TcpStream::connect((host, port))).await,
+        Ok(Ok(_))
+    )
+}
+
 pub fn ensure_version(response: &Response) -> Result<bool> {
     let version = response.headers().get(ATUIN_HEADER_VERSION);
 
@@ -459,3 +478,24 @@ impl<'a> Client<'a> {
         Ok((email_sent, verified))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_server_reachable() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        assert!(server_reachable(&addr, Duration::from_secs(5)).await);
+
+        // nothing listens there any more. An unroutable address would be the obvious choice, but
+        // sandboxes with a transparent proxy accept connections to anything
+        drop(listener);
+        let start = std::time::Instant::now();
+        assert!(!server_reachable(&addr, Duration::from_millis(50)).await);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert!(!server_reachable("not a url", Duration::from_millis(50)).await);
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     borrow::Cow,
+    collections::HashMap,
     env,
     path::{Path, PathBuf},
     str::FromStr,
@@ -102,6 +103,16 @@ pub trait Database: Send + Sync + 'static {
     ) -> Result<Vec<History>>;
     async fn range(&self, from: OffsetDateTime, to: OffsetDateTime) -> Result<Vec<History>>;
 
+    /// The newest `limit` entries, excluding deleted ones, returned oldest first.
+    ///
+    /// Ties on timestamp are broken by id, so the result is stable across calls.
+    async fn list_newest(&self, limit: usize) -> Result<Vec<History>>;
+
+    /// Up to `limit` entries at or after `since`, excluding deleted ones, returned oldest first.
+    ///
+    /// Ties on timestamp are broken by id, so the result is stable across calls.
+    async fn list_oldest_since(&self, since: OffsetDateTime, limit: usize) -> Result<Vec<History>>;
+
     async fn update(&self, h: &History) -> Result<()>;
     async fn history_count(&self, include_deleted: bool) -> Result<i64>;
 
@@ -132,6 +143,25 @@ pub trait Database: Send + Sync + 'static {
     async fn stats(&self, h: &History) -> Result<HistoryStats>;
 
     async fn get_dups(&self, before: i64, dupkeep: u32) -> Result<Vec<History>>;
+
+    /// Record that `source` wrote these history ids to the fish history file
+    async fn save_fish_audit(
+        &self,
+        ids: &[String],
+        source: &str,
+        timestamp: OffsetDateTime,
+    ) -> Result<()>;
+
+    /// How many fish history writes each source made, most first
+    async fn fish_audit_by_source(&self) -> Result<Vec<(String, i64)>>;
+
+    /// The hostname of each of these history ids, for looking many up at once. Ids that aren't in
+    /// the database are left out.
+    async fn hostnames_for_ids(&self, ids: &[String]) -> Result<HashMap<String, String>>;
+
+    /// The history with each of these ids, deleted or not, for loading many at once. Ids that
+    /// aren't in the database are left out.
+    async fn load_many(&self, ids: &[String]) -> Result<Vec<History>>;
 }
 
 // Intended for use on a developer machine and not a sync server.
@@ -379,6 +409,39 @@ impl Database for Sqlite {
         Ok(res)
     }
 
+    async fn list_newest(&self, limit: usize) -> Result<Vec<History>> {
+        debug!("listing newest {} history entries", limit);
+
+        let mut res = sqlx::query(
+            "select * from history where deleted_at is null
+                order by timestamp desc, id desc limit ?1",
+        )
+        .bind(limit as i64)
+        .map(Self::query_history)
+        .fetch_all(&self.pool)
+        .await?;
+
+        res.reverse();
+
+        Ok(res)
+    }
+
+    async fn list_oldest_since(&self, since: OffsetDateTime, limit: usize) -> Result<Vec<History>> {
+        debug!("listing {} history entries since {:?}", limit, since);
+
+        let res = sqlx::query(
+            "select * from history where deleted_at is null and timestamp >= ?1
+                order by timestamp asc, id asc limit ?2",
+        )
+        .bind(since.unix_timestamp_nanos() as i64)
+        .bind(limit as i64)
+        .map(Self::query_history)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(res)
+    }
+
     async fn last(&self) -> Result<Option<History>> {
         let res = sqlx::query(
             "select * from history where duration >= 0 order by timestamp desc limit 1",
@@ -819,8 +882,83 @@ impl Database for Sqlite {
 
         Ok(res)
     }
+
+    async fn save_fish_audit(
+        &self,
+        ids: &[String],
+        source: &str,
+        timestamp: OffsetDateTime,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for id in ids {
+            sqlx::query("insert into fish_sync_audit(id, source, timestamp) values(?1, ?2, ?3)")
+                .bind(id.as_str())
+                .bind(source)
+                .bind(timestamp.unix_timestamp_nanos() as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn fish_audit_by_source(&self) -> Result<Vec<(String, i64)>> {
+        let res = sqlx::query_as(
+            "select source, count(1) as writes from fish_sync_audit group by source order by writes desc, source",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(res)
+    }
+
+    async fn hostnames_for_ids(&self, ids: &[String]) -> Result<HashMap<String, String>> {
+        let mut hostnames = HashMap::with_capacity(ids.len());
+
+        for chunk in ids.chunks(ID_LOOKUP_CHUNK) {
+            let query = format!(
+                "select id, hostname from history where id in ({})",
+                vec!["?"; chunk.len()].join(", ")
+            );
+
+            let mut query = sqlx::query_as::<_, (String, String)>(&query);
+            for id in chunk {
+                query = query.bind(id.as_str());
+            }
+
+            hostnames.extend(query.fetch_all(&self.pool).await?);
+        }
+
+        Ok(hostnames)
+    }
+
+    async fn load_many(&self, ids: &[String]) -> Result<Vec<History>> {
+        let mut histories = Vec::with_capacity(ids.len());
+
+        for chunk in ids.chunks(ID_LOOKUP_CHUNK) {
+            let query = format!(
+                "select * from history where id in ({})",
+                vec!["?"; chunk.len()].join(", ")
+            );
+
+            let mut query = sqlx::query(&query);
+            for id in chunk {
+                query = query.bind(id.as_str());
+            }
+
+            histories.extend(query.map(Self::query_history).fetch_all(&self.pool).await?);
+        }
+
+        Ok(histories)
+    }
 }
 
+/// How many ids go into one `in (...)` list, well under SQLite's limit on bound parameters
+const ID_LOOKUP_CHUNK: usize = 500;
+
 trait SqlBuilderExt {
     fn fuzzy_condition<S: ToString, T: ToString>(
         &mut self,
@@ -935,6 +1073,105 @@ mod test {
         db.save(&captured).await
     }
 
+    async fn new_history_at(db: &impl Database, id: &str, cmd: &str, ts: i64) -> Result<()> {
+        let history = History {
+            id: id.to_string().into(),
+            timestamp: OffsetDateTime::from_unix_timestamp(ts).unwrap(),
+            duration: 1,
+            exit: 0,
+            command: cmd.to_string(),
+            cwd: "/home/ellie".to_string(),
+            session: "beep boop".to_string(),
+            hostname: "booop".to_string(),
+            deleted_at: None,
+        };
+
+        db.save(&history).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_list_newest_order_and_limit() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        // inserted out of order, with a timestamp tie between b and c
+        new_history_at(&db, "d", "four", 40).await.unwrap();
+        new_history_at(&db, "a", "one", 10).await.unwrap();
+        new_history_at(&db, "c", "three", 20).await.unwrap();
+        new_history_at(&db, "b", "two", 20).await.unwrap();
+
+        let mut deleted = db.load("d").await.unwrap().unwrap();
+        deleted.deleted_at = Some(OffsetDateTime::now_utc());
+        db.update(&deleted).await.unwrap();
+
+        let newest = db.list_newest(2).await.unwrap();
+        let commands: Vec<&str> = newest.iter().map(|h| h.command.as_str()).collect();
+        assert_eq!(commands, vec!["two", "three"]);
+
+        let all = db.list_newest(10).await.unwrap();
+        let commands: Vec<&str> = all.iter().map(|h| h.command.as_str()).collect();
+        assert_eq!(commands, vec!["one", "two", "three"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_hostnames_for_ids() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        // enough rows to take several chunks
+        let histories: Vec<History> = (0..3_000)
+            .map(|i| History {
+                id: format!("{i:032x}").into(),
+                timestamp: OffsetDateTime::from_unix_timestamp(i).unwrap(),
+                duration: 1,
+                exit: 0,
+                command: format!("command {i}"),
+                cwd: "/home/ellie".to_string(),
+                session: "beep boop".to_string(),
+                hostname: format!("host{}:ellie", i % 3),
+                deleted_at: None,
+            })
+            .collect();
+        db.save_bulk(&histories).await.unwrap();
+
+        let mut ids: Vec<String> = (0..3_000).step_by(2).map(|i| format!("{i:032x}")).collect();
+        ids.push("not-a-history".to_string());
+        ids.push(format!("{:032x}", 10_000));
+
+        let hostnames = db.hostnames_for_ids(&ids).await.unwrap();
+
+        assert_eq!(hostnames.len(), 1_500);
+        assert_eq!(hostnames[&format!("{:032x}", 0)], "host0:ellie");
+        assert_eq!(hostnames[&format!("{:032x}", 2_998)], "host1:ellie");
+        assert!(!hostnames.contains_key("not-a-history"));
+
+        assert!(db.hostnames_for_ids(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_list_oldest_since_order_and_limit() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        new_history_at(&db, "d", "four", 40).await.unwrap();
+        new_history_at(&db, "a", "one", 10).await.unwrap();
+        new_history_at(&db, "c", "three", 20).await.unwrap();
+        new_history_at(&db, "b", "two", 20).await.unwrap();
+
+        let since = OffsetDateTime::from_unix_timestamp(20).unwrap();
+
+        let oldest = db.list_oldest_since(since, 2).await.unwrap();
+        let commands: Vec<&str> = oldest.iter().map(|h| h.command.as_str()).collect();
+        assert_eq!(commands, vec!["two", "three"]);
+
+        let all = db.list_oldest_since(since, 10).await.unwrap();
+        let commands: Vec<&str> = all.iter().map(|h| h.command.as_str()).collect();
+        assert_eq!(commands, vec!["two", "three", "four"]);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_search_prefix() {
         let mut db = Sqlite::new("sqlite::memory:", test_local_timeout())
@@ -0,0 +1,44 @@
+//! Which code path wrote each fish history entry
+//!
+//! With `fish_sync.audit` on, every entry Atuin writes carries an `atuin-src` comment naming the
+//! code path that wrote it, and each write is recorded in the history database. When one command
+//! shows up in the file many times over, that says which writer keeps adding it.
+
+use std::fmt;
+
+/// The code path that wrote an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteSource {
+    /// The daemon, writing entries it downloaded from the server
+    Daemon,
+
+    /// The CLI, writing entries `atuin sync` or an automatic sync downloaded
+    Cli,
+
+    /// Seeding the file with the newest history from other machines
+    Bootstrap,
+
+    /// A single entry written on its own
+    Downloaded,
+
+    /// Rebuilding the file from the database
+    Rebuild,
+}
+
+impl WriteSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Daemon => "daemon",
+            Self::Cli => "cli",
+            Self::Bootstrap => "bootstrap",
+            Self::Downloaded => "downloaded",
+            Self::Rebuild => "rebuild",
+        }
+    }
+}
+
+impl fmt::Display for WriteSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
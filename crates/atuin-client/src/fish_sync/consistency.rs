@@ -0,0 +1,275 @@
+//! Cross-checking the history index, the record store and the fish history file
+//!
+//! The three are expected to differ: only other machines' history is written to fish, some of it
+//! is filtered out, and the fish file is trimmed to a cap. Some differences can't be explained that
+//! way though, like fish holding more of Atuin's entries than the index has history. [`check`]
+//! counts all three and labels each difference as one or the other.
+//!
+//! The fish count comes from the fish sync state, which is only recounted when the file changed
+//! since the last write.
+
+use std::fmt;
+
+use eyre::Result;
+
+use super::meta::FishSyncMeta;
+use crate::settings::Settings;
+
+/// Whether a difference between the counts is explained by how fish sync works
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Note {
+    Expected(String),
+    Anomaly(String),
+}
+
+impl Note {
+    pub fn is_anomaly(&self) -> bool {
+        matches!(self, Self::Anomaly(_))
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Expected(note) => write!(f, "expected: {note}"),
+            Self::Anomaly(note) => write!(f, "anomaly: {note}"),
+        }
+    }
+}
+
+/// History counts from the index, the record store and the fish history file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consistency {
+    /// History in the local database, deleted or not
+    pub index: u64,
+
+    /// History records in the record store
+    pub store: u64,
+
+    /// Entries Atuin wrote to the fish history file, or `None` with fish sync off
+    pub fish: Option<u64>,
+
+    /// All entries in the fish history file, Atuin's or not
+    pub fish_total: u64,
+
+    /// How many of Atuin's entries the state recorded at the last write
+    pub recorded: Option<u64>,
+
+    /// What the fish history file is trimmed to, 0 for no limit
+    pub cap: usize,
+}
+
+impl Consistency {
+    /// Whether the fish history file is full, so older entries are being trimmed
+    pub fn capped(&self) -> bool {
+        self.fish.is_some() && self.cap > 0 && self.fish_total >= self.cap as u64
+    }
+
+    /// Explain each difference between the counts
+    pub fn notes(&self) -> Vec<Note> {
+        let mut notes = Vec::new();
+
+        if self.store < self.index {
+            notes.push(Note::Anomaly(format!(
+                "{} history in the index is missing from the store, `atuin sync` will add it",
+                group(self.index - self.store)
+            )));
+        } else if self.store > self.index {
+            notes.push(Note::Expected(format!(
+                "the store has {} more records than the index, deletions are records too",
+                group(self.store - self.index)
+            )));
+        }
+
+        let Some(fish) = self.fish else {
+            return notes;
+        };
+
+        if fish > self.index {
+            notes.push(Note::Anomaly(format!(
+                "fish history has {} more of Atuin's entries than there is history, they are likely duplicates: run `atuin fish-sync dedupe`",
+                group(fish - self.index)
+            )));
+        }
+
+        if let Some(recorded) = self.recorded
+            && fish < recorded
+            && !self.capped()
+        {
+            notes.push(Note::Anomaly(format!(
+                "{} of Atuin's entries have gone from fish history since the last sync, it was truncated or rewritten outside Atuin",
+                group(recorded - fish)
+            )));
+        }
+
+        if fish < self.index {
+            if self.capped() {
+                notes.push(Note::Expected(format!(
+                    "fish history is at its cap of {}, so older entries are trimmed",
+                    group(self.cap as u64)
+                )));
+            }
+
+            notes.push(Note::Expected(
+                "only other machines' history is written to fish, less anything filtered out"
+                    .to_string(),
+            ));
+        }
+
+        notes
+    }
+}
+
+impl fmt::Display for Consistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "index: {} / store: {} / fish: ",
+            group(self.index),
+            group(self.store)
+        )?;
+
+        match self.fish {
+            Some(fish) => write!(f, "{}", group(fish))?,
+            None => write!(f, "off")?,
+        }
+
+        if self.capped() {
+            write!(f, " (capped at {})", group(self.cap as u64))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Count Atuin's entries in the fish history file, and compare them with the index and store
+pub fn check(settings: &Settings, index: u64, store: u64) -> Result<Consistency> {
+    let mut consistency = Consistency {
+        index,
+        store,
+        fish: None,
+        fish_total: 0,
+        recorded: None,
+        cap: 0,
+    };
+
+    if !settings.fish_sync.enabled {
+        return Ok(consistency);
+    }
+
+    let path = FishSyncMeta::path(settings);
+    let fish_path = super::resolve_history_path(settings)?;
+
+    consistency.recorded = FishSyncMeta::load(&path)
+        .ok()
+        .and_then(|meta| meta.atuin_entries);
+
+    let meta = FishSyncMeta::load_or_rebuild(&path, &fish_path)?;
+
+    consistency.fish = Some(match meta.atuin_entries {
+        Some(entries) => entries,
+        // state from before Atuin's entries were counted
+        None => super::count_synced_entries(&fish_path)? as u64,
+    });
+    consistency.fish_total = meta.fish_entries;
+    consistency.cap = super::effective_max_entries(settings, super::fish_history_max());
+
+    Ok(consistency)
+}
+
+/// `52310` as `52,310`
+fn group(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(index: u64, store: u64, fish: u64) -> Consistency {
+        Consistency {
+            index,
+            store,
+            fish: Some(fish),
+            fish_total: fish,
+            recorded: Some(fish),
+            cap: 0,
+        }
+    }
+
+    #[test]
+    fn test_group() {
+        assert_eq!(group(0), "0");
+        assert_eq!(group(999), "999");
+        assert_eq!(group(1000), "1,000");
+        assert_eq!(group(52_310), "52,310");
+        assert_eq!(group(1_234_567), "1,234,567");
+    }
+
+    #[test]
+    fn test_line() {
+        let consistency = Consistency {
+            fish_total: 10_000,
+            cap: 10_000,
+            ..counts(52_310, 52_310, 9_800)
+        };
+
+        assert_eq!(
+            consistency.to_string(),
+            "index: 52,310 / store: 52,310 / fish: 9,800 (capped at 10,000)"
+        );
+
+        let off = Consistency {
+            fish: None,
+            ..counts(3, 3, 0)
+        };
+        assert_eq!(off.to_string(), "index: 3 / store: 3 / fish: off");
+    }
+
+    #[test]
+    fn test_expected_differences() {
+        let capped = Consistency {
+            fish_total: 10_000,
+            cap: 10_000,
+            recorded: Some(9_900),
+            ..counts(52_310, 52_310, 9_800)
+        };
+
+        let notes = capped.notes();
+        assert_eq!(notes.len(), 2);
+        assert!(!notes.iter().any(Note::is_anomaly), "{notes:?}");
+
+        assert!(counts(10, 10, 10).notes().is_empty());
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let missing_from_store = counts(10, 8, 5).notes();
+        assert!(missing_from_store[0].is_anomaly());
+
+        let duplicated = counts(10, 10, 25).notes();
+        assert!(duplicated.iter().any(Note::is_anomaly));
+        assert!(duplicated[0].to_string().contains("dedupe"));
+
+        let truncated = Consistency {
+            recorded: Some(8),
+            ..counts(10, 10, 3)
+        };
+        assert!(
+            truncated
+                .notes()
+                .iter()
+                .any(|note| note.is_anomaly() && note.to_string().contains("outside Atuin"))
+        );
+    }
+}
@@ -0,0 +1,453 @@
+//! Entries read back out of a fish history file
+//!
+//! Importing from fish and comparing fish against Atuin both need to turn what's in the file into
+//! [`History`]. Doing that in one place keeps the defaults for what fish doesn't record
+//! consistent.
+
+use eyre::{Result, bail, eyre};
+use serde::Serialize;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::format::{ATUIN_UUID_KEY, metadata_line};
+use super::syncer::{RawEntry, escape_fish_cmd, split_entries, unescape_fish_cmd};
+use crate::history::History;
+
+/// Namespace for the ids of history imported from fish entries Atuin didn't write
+const IMPORT_NAMESPACE: Uuid = Uuid::from_u128(0xc39e_b390_45bc_47f4_b6be_45a3_1ff3_f94f);
+
+/// Exit code given to history imported from fish, which doesn't record one
+pub const IMPORTED_EXIT: i64 = -1;
+
+/// Duration given to history imported from fish, which doesn't record one
+pub const IMPORTED_DURATION: i64 = -1;
+
+/// How much of a malformed line a [`ParseIssue`] quotes
+const EXCERPT_CHARS: usize = 60;
+
+/// What's wrong with a part of a fish history file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseIssueKind {
+    /// Text before the first entry, which fish ignores
+    OutsideEntry,
+
+    /// An entry whose `- cmd:` line has no command
+    EmptyCommand,
+
+    /// An entry without a `when:` line
+    MissingTimestamp,
+
+    /// A `when:` line that isn't a unix timestamp
+    InvalidTimestamp,
+
+    /// A line inside an entry that isn't indented like its fields
+    UnexpectedLine,
+}
+
+impl std::fmt::Display for ParseIssueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::OutsideEntry => "text before the first entry",
+            Self::EmptyCommand => "entry has no command",
+            Self::MissingTimestamp => "entry has no timestamp",
+            Self::InvalidTimestamp => "timestamp isn't a number",
+            Self::UnexpectedLine => "line isn't part of an entry",
+        })
+    }
+}
+
+/// Something malformed in a fish history file, and where it is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParseIssue {
+    /// Line number, counting from 1
+    pub line: usize,
+
+    /// Byte offset of the start of the line
+    pub offset: usize,
+
+    pub kind: ParseIssueKind,
+
+    /// The start of the line, to find it by
+    pub excerpt: String,
+}
+
+impl ParseIssue {
+    pub(crate) fn new(kind: ParseIssueKind, offset: usize, line: usize, text: &str) -> Self {
+        let mut excerpt: String = text.chars().take(EXCERPT_CHARS).collect();
+        if excerpt.len() < text.len() {
+            excerpt.push_str("...");
+        }
+
+        Self {
+            line,
+            offset,
+            kind,
+            excerpt,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}: {:?}", self.line, self.kind, self.excerpt)
+    }
+}
+
+/// One entry in a fish history file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FishHistoryEntry {
+    /// The command, unescaped
+    pub command: String,
+
+    /// When the command ran, as a unix timestamp. `None` if the entry has no valid `when:` line.
+    pub when: Option<i64>,
+
+    /// The id of the Atuin history this entry was written from, if Atuin wrote it
+    pub uuid: Option<String>,
+
+    /// The paths fish recorded the command as using, as written
+    pub paths: Vec<String>,
+
+    /// Comment lines other tools added to the entry, verbatim
+    pub comments: Vec<String>,
+}
+
+impl FishHistoryEntry {
+    /// Every entry in the contents of a fish history file, in file order
+    ///
+    /// Only a line that starts with `- cmd:` begins an entry, with or without a space after the
+    /// colon, so a command that contains that text is still one entry. Fields fish doesn't know
+    /// are skipped, and entries without Atuin's comments are read like any other.
+    pub fn parse(content: &str) -> Vec<Self> {
+        split_entries(content).1.iter().map(Self::from).collect()
+    }
+
+    /// Everything malformed in the contents of a fish history file, in file order
+    ///
+    /// [`FishHistoryEntry::parse`] skips over all of these, so they only matter to someone looking
+    /// for why an entry is missing or wrong.
+    pub fn issues(content: &str) -> Vec<ParseIssue> {
+        let (preamble, entries) = split_entries(content);
+
+        let mut offset = 0;
+        let mut issues = Vec::new();
+        for (i, line) in preamble.split_inclusive('\n').enumerate() {
+            if !line.trim().is_empty() {
+                issues.push(ParseIssue::new(
+                    ParseIssueKind::OutsideEntry,
+                    offset,
+                    i + 1,
+                    line.trim_end(),
+                ));
+            }
+            offset += line.len();
+        }
+
+        issues.extend(entries.into_iter().flat_map(|entry| entry.issues));
+        issues.sort_by_key(|issue| issue.offset);
+        issues
+    }
+
+    /// Render entries as fish writes them, followed by Atuin's comment and any others
+    ///
+    /// Entries fish wrote come out exactly as they went into [`FishHistoryEntry::parse`].
+    pub fn serialize(entries: &[Self]) -> String {
+        let mut content = String::new();
+
+        for entry in entries {
+            content.push_str(&format!("- cmd: {}\n", escape_fish_cmd(&entry.command)));
+
+            if let Some(when) = entry.when {
+                content.push_str(&format!("  when: {when}\n"));
+            }
+
+            if !entry.paths.is_empty() {
+                content.push_str("  paths:\n");
+                for path in &entry.paths {
+                    content.push_str(&format!("    - {path}\n"));
+                }
+            }
+
+            if let Some(uuid) = &entry.uuid {
+                content.push_str(&metadata_line(ATUIN_UUID_KEY, uuid));
+            }
+
+            for comment in &entry.comments {
+                content.push_str(comment);
+                content.push('\n');
+            }
+        }
+
+        content
+    }
+
+    /// Convert this entry to Atuin history
+    ///
+    /// Fish doesn't record exit codes or durations, so those are set to [`IMPORTED_EXIT`] and
+    /// [`IMPORTED_DURATION`], and the cwd to `unknown`, as for any other import. Entries Atuin
+    /// wrote keep the id of the history they came from. Anything else gets an id derived from the
+    /// command, timestamp and `hostname`, so importing the same file twice yields the same ids.
+    ///
+    /// Fails if the entry has no timestamp, or one outside what Atuin can store.
+    pub fn to_history(&self, hostname: &str, session: &str) -> Result<History> {
+        let when = self
+            .when
+            .ok_or_else(|| eyre!("fish entry has no timestamp: {}", self.command))?;
+
+        if when < 0 {
+            bail!("fish entry has a timestamp before 1970: {when}");
+        }
+
+        let timestamp = OffsetDateTime::from_unix_timestamp(when)
+            .map_err(|_| eyre!("fish entry has an out of range timestamp: {when}"))?;
+
+        let mut history: History = History::import()
+            .timestamp(timestamp)
+            .command(self.command.clone())
+            .exit(IMPORTED_EXIT)
+            .duration(IMPORTED_DURATION)
+            .session(session)
+            .hostname(hostname)
+            .build()
+            .into();
+
+        history.id = self
+            .uuid
+            .clone()
+            .unwrap_or_else(|| self.derived_id(hostname))
+            .into();
+
+        Ok(history)
+    }
+
+    fn derived_id(&self, hostname: &str) -> String {
+        let name = format!(
+            "{}\0{}\0{hostname}",
+            self.command,
+            self.when.unwrap_or_default()
+        );
+
+        Uuid::new_v5(&IMPORT_NAMESPACE, name.as_bytes())
+            .as_simple()
+            .to_string()
+    }
+}
+
+impl From<&RawEntry<'_>> for FishHistoryEntry {
+    fn from(raw: &RawEntry<'_>) -> Self {
+        Self {
+            command: unescape_fish_cmd(raw.cmd),
+            when: raw.when,
+            uuid: raw.uuid.map(str::to_string),
+            paths: raw.paths.iter().map(|p| p.to_string()).collect(),
+            comments: raw.comments.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, when: Option<i64>) -> FishHistoryEntry {
+        FishHistoryEntry {
+            command: command.to_string(),
+            when,
+            uuid: None,
+            paths: Vec::new(),
+            comments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_to_history() {
+        let history = entry("echo a\nb", Some(1_700_000_000))
+            .to_history("host:user", "fish")
+            .unwrap();
+
+        assert_eq!(history.command, "echo a\nb");
+        assert_eq!(history.timestamp.unix_timestamp(), 1_700_000_000);
+        assert_eq!(history.exit, IMPORTED_EXIT);
+        assert_eq!(history.duration, IMPORTED_DURATION);
+        assert_eq!(history.cwd, "unknown");
+        assert_eq!(history.session, "fish");
+        assert_eq!(history.hostname, "host:user");
+    }
+
+    #[test]
+    fn test_derived_id_is_deterministic() {
+        let a = entry("ls", Some(1))
+            .to_history("host:user", "fish")
+            .unwrap();
+        let b = entry("ls", Some(1))
+            .to_history("host:user", "other")
+            .unwrap();
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.id.0.len(), 32);
+
+        // any of command, timestamp or host changes the id
+        let others = [
+            entry("ls -l", Some(1)).to_history("host:user", "fish"),
+            entry("ls", Some(2)).to_history("host:user", "fish"),
+            entry("ls", Some(1)).to_history("other:user", "fish"),
+        ];
+        for other in others {
+            assert_ne!(other.unwrap().id, a.id);
+        }
+    }
+
+    #[test]
+    fn test_atuin_entries_keep_their_id() {
+        let mut e = entry("ls", Some(1));
+        e.uuid = Some("0190b1a27c4e70008000000000000001".to_string());
+
+        let history = e.to_history("host:user", "fish").unwrap();
+        assert_eq!(history.id.0, "0190b1a27c4e70008000000000000001");
+    }
+
+    #[test]
+    fn test_invalid_timestamps() {
+        assert!(entry("ls", None).to_history("h", "s").is_err());
+        assert!(entry("ls", Some(-5)).to_history("h", "s").is_err());
+        assert!(entry("ls", Some(i64::MAX)).to_history("h", "s").is_err());
+    }
+
+    /// As fish 3.7 writes its history, paths and escapes included
+    const NATIVE: &str = concat!(
+        "- cmd: cd ~/src\n",
+        "  when: 1700000000\n",
+        "- cmd: cat notes.txt\n",
+        "  when: 1700000005\n",
+        "  paths:\n",
+        "    - notes.txt\n",
+        "- cmd: echo 'a\\nb' \\\\ c\n",
+        "  when: 1700000010\n",
+        "- cmd: grep -- '- cmd: when: 1' fish_history\n",
+        "  when: 1700000020\n",
+    );
+
+    #[test]
+    fn test_parse() {
+        let entries = FishHistoryEntry::parse(NATIVE);
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1].command, "cat notes.txt");
+        assert_eq!(entries[1].paths, vec!["notes.txt"]);
+        assert_eq!(entries[2].command, "echo 'a\nb' \\ c");
+        assert_eq!(entries[3].command, "grep -- '- cmd: when: 1' fish_history");
+        assert_eq!(entries[3].when, Some(1_700_000_020));
+        assert!(entries.iter().all(|e| e.uuid.is_none()));
+    }
+
+    #[test]
+    fn test_native_entries_round_trip() {
+        assert_eq!(
+            FishHistoryEntry::serialize(&FishHistoryEntry::parse(NATIVE)),
+            NATIVE
+        );
+    }
+
+    #[test]
+    fn test_parse_tolerates_other_spellings() {
+        let content = concat!(
+            "- cmd:ls\n",
+            "  when:1\n",
+            "  # atuin-uuid:0190b1a27c4e70008000000000000001\n",
+            "  # fork-host:laptop\n",
+            "- cmd: pwd\n",
+            "  when: 2\n",
+            "  exit: 0\n",
+            "  paths:\n",
+            "    - .\n",
+            "  future: field\n",
+        );
+        let entries = FishHistoryEntry::parse(content);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command, "ls");
+        assert_eq!(entries[0].when, Some(1));
+        assert_eq!(
+            entries[0].uuid.as_deref(),
+            Some("0190b1a27c4e70008000000000000001")
+        );
+        assert_eq!(entries[0].comments, vec!["  # fork-host:laptop"]);
+        assert_eq!(entries[1].when, Some(2));
+        assert_eq!(entries[1].paths, vec!["."]);
+
+        // rendered the way fish writes, but nothing is lost
+        let serialized = FishHistoryEntry::serialize(&entries);
+        assert!(serialized.starts_with("- cmd: ls\n  when: 1\n"));
+        assert_eq!(FishHistoryEntry::parse(&serialized), entries);
+    }
+
+    #[test]
+    fn test_well_formed_files_have_no_issues() {
+        assert!(FishHistoryEntry::issues(NATIVE).is_empty());
+        assert!(FishHistoryEntry::issues("").is_empty());
+    }
+
+    /// `count` well formed entries, with `bad` spliced in after the first `at`
+    fn fixture(count: usize, at: usize, bad: &str) -> String {
+        let mut content = String::new();
+        for i in 0..count {
+            if i == at {
+                content.push_str(bad);
+            }
+            content.push_str(&format!("- cmd: echo {i}\n  when: {}\n", 1_700_000_000 + i));
+        }
+        if at >= count {
+            content.push_str(bad);
+        }
+        content
+    }
+
+    #[test]
+    fn test_issue_at_start() {
+        let content = fixture(50, 0, "garbage\n");
+        let issues = FishHistoryEntry::issues(&content);
+
+        assert_eq!(
+            issues,
+            vec![ParseIssue {
+                line: 1,
+                offset: 0,
+                kind: ParseIssueKind::OutsideEntry,
+                excerpt: "garbage".to_string(),
+            }]
+        );
+        assert_eq!(FishHistoryEntry::parse(&content).len(), 50);
+    }
+
+    #[test]
+    fn test_issue_in_middle() {
+        let content = fixture(50, 25, "- cmd: ls\n  when: yesterday\n");
+        let issues = FishHistoryEntry::issues(&content);
+
+        // 25 entries of two lines each come first
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 52);
+        assert_eq!(issues[0].kind, ParseIssueKind::InvalidTimestamp);
+        assert_eq!(issues[0].excerpt, "  when: yesterday");
+        assert_eq!(&content[issues[0].offset..][..17], "  when: yesterday");
+    }
+
+    #[test]
+    fn test_issues_at_end() {
+        let long = "x".repeat(100);
+        let content = fixture(50, 50, &format!("- cmd:\n  when: 1\n- cmd: ls\n{long}\n"));
+        let issues = FishHistoryEntry::issues(&content);
+
+        let found: Vec<_> = issues.iter().map(|i| (i.line, i.kind)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (101, ParseIssueKind::EmptyCommand),
+                (103, ParseIssueKind::MissingTimestamp),
+                (104, ParseIssueKind::UnexpectedLine),
+            ]
+        );
+        assert_eq!(issues[2].excerpt, format!("{}...", "x".repeat(60)));
+        assert_eq!(&content[issues[2].offset..], format!("{long}\n"));
+    }
+}
@@ -0,0 +1,176 @@
+//! The metadata Atuin adds to fish history entries
+//!
+//! Fish ignores comment lines inside an entry, so that's where Atuin records what it needs to
+//! recognise its own entries later. Existing files are only ever recognised by these lines, so
+//! changing how they're written breaks dedup for everyone who upgrades. Everything that writes or
+//! reads them, tests included, goes through this module.
+//!
+//! ```text
+//! - cmd:git status
+//!   when:1737097200
+//!   # atuin-uuid:0190b1a27c4e70008000000000000001
+//! ```
+
+/// The key of the comment holding the id of the Atuin history an entry was written from
+pub const ATUIN_UUID_KEY: &str = "atuin-uuid";
+
+/// The key of the comment naming the code path that wrote an entry, with `fish_sync.audit` on
+pub const ATUIN_SRC_KEY: &str = "atuin-src";
+
+/// Longest metadata value Atuin writes, in characters. Longer values are cut short.
+pub const MAX_METADATA_VALUE: usize = 128;
+
+/// Longest comment line the parser associates with an entry, in bytes
+///
+/// Anything longer is corrupt or hostile, and isn't worth carrying along with every entry.
+pub const MAX_COMMENT_LINE: usize = 1024;
+
+/// Render a metadata comment line, including its trailing newline
+///
+/// This is the only spelling Atuin writes. The value is cut to [`MAX_METADATA_VALUE`] characters,
+/// and control characters in it become `_`, so it can never spill onto another line.
+pub fn metadata_line(key: &str, value: &str) -> String {
+    let value: String = value
+        .chars()
+        .take(MAX_METADATA_VALUE)
+        .map(|c| if c.is_control() { '_' } else { c })
+        .collect();
+
+    format!("  # {key}:{value}\n")
+}
+
+/// Whether `id` could be the id of Atuin history, and so is safe to write as an `atuin-uuid`
+///
+/// Ids are uuids, in either spelling. Anything with characters no uuid has, or far too long to
+/// be one, came from a corrupt row.
+pub fn is_valid_id(id: &str) -> bool {
+    (1..=64).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Whether a line inside an entry is a comment, whoever added it
+pub fn is_comment_line(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+/// Split a metadata comment line into its key and value
+///
+/// Besides the spelling [`metadata_line`] writes, this accepts any indentation and spaces around
+/// the `#` and the value, in case the file was edited by hand. Only `atuin-` keys count, so
+/// ordinary comments are never mistaken for metadata.
+pub fn parse_metadata_line(line: &str) -> Option<(&str, &str)> {
+    let comment = line.trim_start().strip_prefix('#')?.trim_start();
+    let (key, value) = comment.split_once(':')?;
+
+    if !key.starts_with("atuin-") || key.contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some((key, value.trim()))
+}
+
+/// The id in a `atuin-uuid` comment line, as written
+///
+/// Ids that [`is_valid_id`] rejects are ignored, so a corrupt comment doesn't tie an entry to
+/// history it can't belong to.
+pub fn parse_uuid_line(line: &str) -> Option<&str> {
+    parse_metadata_line(line)
+        .filter(|(key, _)| *key == ATUIN_UUID_KEY)
+        .map(|(_, value)| value)
+        .filter(|value| is_valid_id(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fish_sync::{FishSyncOptions, FishSyncer};
+    use crate::test_support::FishFileBuilder;
+
+    const SIMPLE: &str = "0190b1a27c4e70008000000000000001";
+    const HYPHENATED: &str = "0190b1a2-7c4e-7000-8000-000000000001";
+
+    #[test]
+    fn test_metadata_line_round_trip() {
+        let line = metadata_line(ATUIN_UUID_KEY, SIMPLE);
+        assert_eq!(line, format!("  # atuin-uuid:{SIMPLE}\n"));
+        assert_eq!(parse_uuid_line(line.trim_end_matches('\n')), Some(SIMPLE));
+    }
+
+    #[test]
+    fn test_metadata_values_are_bounded() {
+        let line = metadata_line("atuin-host", &"h".repeat(10_000));
+        assert_eq!(line.len(), "  # atuin-host:\n".len() + MAX_METADATA_VALUE);
+
+        let line = metadata_line("atuin-host", "laptop\n- cmd:rm -rf ~\r");
+        assert_eq!(line, "  # atuin-host:laptop_- cmd:rm -rf ~_\n");
+    }
+
+    #[test]
+    fn test_valid_ids() {
+        assert!(is_valid_id(SIMPLE));
+        assert!(is_valid_id(HYPHENATED));
+
+        for id in [
+            "",
+            "0190b1a2 7c4e",
+            "0190b1a2:7c4e",
+            "id\n",
+            &"a".repeat(65),
+        ] {
+            assert!(!is_valid_id(id), "{id:?}");
+        }
+
+        for id in ["0190b1a2 7c4e", "0190b1a2:7c4e", &"a".repeat(10_000)] {
+            assert_eq!(parse_uuid_line(&format!("  # atuin-uuid:{id}")), None);
+        }
+    }
+
+    #[test]
+    fn test_ordinary_comments_are_not_metadata() {
+        for line in [
+            "  # just a note",
+            "  # atuin uuid:abc",
+            "  # uuid:abc",
+            "  when:1",
+            "- cmd:# atuin-uuid:abc",
+        ] {
+            assert_eq!(parse_metadata_line(line), None, "{line:?}");
+        }
+    }
+
+    /// Every spelling of the uuid comment that some release wrote, or that a hand-edited file
+    /// might contain, must keep being recognised
+    #[test]
+    fn test_compatible_uuid_lines() {
+        let table = [
+            // written by the current release
+            (metadata_line(ATUIN_UUID_KEY, SIMPLE), SIMPLE),
+            // written before ids were canonicalised
+            (format!("  # atuin-uuid:{HYPHENATED}\n"), HYPHENATED),
+            // hand edited
+            (format!("    #  atuin-uuid: {SIMPLE} \n"), SIMPLE),
+            (format!("\t# atuin-uuid:{HYPHENATED}\n"), HYPHENATED),
+        ];
+
+        let dir = tempfile::tempdir().unwrap();
+
+        for (i, (line, uuid)) in table.iter().enumerate() {
+            assert_eq!(
+                parse_uuid_line(line.trim_end_matches('\n')),
+                Some(*uuid),
+                "{line:?}"
+            );
+
+            let path = dir.path().join(format!("fish_history_{i}"));
+            FishFileBuilder::new()
+                .native("ls", 1)
+                .raw(line)
+                .native("pwd", 2)
+                .write(&path);
+
+            let syncer = FishSyncer::open(&path, FishSyncOptions::default()).unwrap();
+            assert!(syncer.contains(SIMPLE).unwrap(), "{line:?}");
+            assert!(syncer.contains(HYPHENATED).unwrap(), "{line:?}");
+            assert!(!syncer.contains("abc").unwrap(), "{line:?}");
+        }
+    }
+}
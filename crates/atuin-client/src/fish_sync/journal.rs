@@ -0,0 +1,366 @@
+//! An optional record of every change Atuin makes to the fish history file
+//!
+//! With `fish_sync.journal_path` set, each append, trim, clean, dedupe, reconcile, repair or
+//! rebuild adds one JSON line to the journal, saying what it did and what the file hashed to
+//! afterwards. When the file turns out different from what Atuin expected, the journal says
+//! whether Atuin touched it since, and how.
+//!
+//! Appends happen on every sync, so ones close together share a line rather than each getting
+//! their own. The journal is rotated to `<journal>.1` once it reaches [`MAX_JOURNAL_BYTES`], so
+//! it never holds more than twice that.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use eyre::{Context, Result};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::meta::hash_contents;
+use crate::settings::Settings;
+
+/// How large the journal gets before it's rotated
+pub const MAX_JOURNAL_BYTES: u64 = 1024 * 1024;
+
+/// Appends this many seconds after the one that started a line are added to that line
+pub const APPEND_COALESCE_SECS: i64 = 60;
+
+/// What Atuin did to the fish history file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Append,
+    Trim,
+    Sort,
+    Clean,
+    Dedupe,
+    Remove,
+    Clear,
+    Reconcile,
+    Repair,
+    Rebuild,
+    Import,
+}
+
+/// One line of the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// When the operation happened, in seconds since the epoch. For coalesced appends, when the
+    /// first of them happened.
+    pub time: i64,
+
+    pub operation: Operation,
+
+    /// Entries written
+    pub added: u64,
+
+    /// Entries removed
+    pub removed: u64,
+
+    /// Entries kept but changed: moved, given back their command, tagged with an id, or stripped
+    /// of Atuin's comments. For a clean, the comment lines removed.
+    pub changed: u64,
+
+    /// How much the file grew, negative when it shrank
+    pub bytes_delta: i64,
+
+    /// [`hash_contents`] of the whole file after the operation
+    pub file_hash: String,
+
+    /// How many operations the line stands for, more than one only for coalesced appends
+    pub count: u64,
+}
+
+impl JournalEntry {
+    /// An entry for an operation that just left the file holding `contents`
+    pub fn new(operation: Operation, contents: &[u8]) -> Self {
+        Self::hashed(operation, hash_contents(contents))
+    }
+
+    /// Like [`JournalEntry::new`], for a file whose [`hash_contents`] is already known
+    pub fn hashed(operation: Operation, file_hash: String) -> Self {
+        Self {
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+            operation,
+            added: 0,
+            removed: 0,
+            changed: 0,
+            bytes_delta: 0,
+            file_hash,
+            count: 1,
+        }
+    }
+
+    pub fn added(self, added: usize) -> Self {
+        Self {
+            added: added as u64,
+            ..self
+        }
+    }
+
+    pub fn removed(self, removed: usize) -> Self {
+        Self {
+            removed: removed as u64,
+            ..self
+        }
+    }
+
+    pub fn changed(self, changed: usize) -> Self {
+        Self {
+            changed: changed as u64,
+            ..self
+        }
+    }
+
+    pub fn bytes_delta(self, bytes_delta: i64) -> Self {
+        Self {
+            bytes_delta,
+            ..self
+        }
+    }
+
+    /// This line with `later` folded into it, if both are appends close enough together
+    fn coalesce(self, later: &Self) -> Option<Self> {
+        if self.operation != Operation::Append
+            || later.operation != Operation::Append
+            || later.time - self.time >= APPEND_COALESCE_SECS
+        {
+            return None;
+        }
+
+        Some(Self {
+            added: self.added + later.added,
+            bytes_delta: self.bytes_delta + later.bytes_delta,
+            file_hash: later.file_hash.clone(),
+            count: self.count + later.count,
+            ..self
+        })
+    }
+}
+
+/// The journal file at one path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journal {
+    path: PathBuf,
+    max_bytes: u64,
+}
+
+impl Journal {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes: MAX_JOURNAL_BYTES,
+        }
+    }
+
+    /// The journal `fish_sync.journal_path` names, unless it's empty
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        let path = &settings.fish_sync.journal_path;
+        (!path.is_empty()).then(|| Self::new(path))
+    }
+
+    /// Rotate once the journal reaches `max_bytes`, rather than [`MAX_JOURNAL_BYTES`]
+    pub fn with_max_bytes(self, max_bytes: u64) -> Self {
+        Self { max_bytes, ..self }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where the journal goes when it's rotated
+    pub fn rotated_path(&self) -> PathBuf {
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        self.path.with_file_name(format!("{name}.1"))
+    }
+
+    /// Add `entry` to the journal, or fold it into the last line if both are appends close
+    /// together
+    pub fn write(&self, entry: &JournalEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs_err::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open {}", self.path.display()))?;
+        file.lock_exclusive()
+            .context("failed to acquire lock on fish sync journal")?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .context("failed to read fish sync journal")?;
+
+        // the last line starts after the newline before the one that ends it
+        let last_start = contents
+            .trim_end_matches('\n')
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let coalesced = serde_json::from_str::<JournalEntry>(contents[last_start..].trim_end())
+            .ok()
+            .and_then(|last| last.coalesce(entry));
+
+        let (mut offset, line) = match coalesced {
+            Some(line) => (last_start as u64, line),
+            None => (contents.len() as u64, entry.clone()),
+        };
+
+        let mut line = serde_json::to_string(&line)? + "\n";
+        let mut rotated = None;
+
+        // a coalesced line grows in place, so the journal may pass the cap by that much
+        if offset == contents.len() as u64 && offset >= self.max_bytes {
+            fs_err::rename(&self.path, self.rotated_path())?;
+
+            // held until the new line is written, so a writer waiting on it only gets it once
+            // the rotation is done, and adds its line to the rotated journal at worst
+            rotated = Some(std::mem::replace(
+                &mut file,
+                OpenOptions::new()
+                    .create(true)
+                    .truncate(true)
+                    .write(true)
+                    .open(&self.path)
+                    .with_context(|| format!("failed to open {}", self.path.display()))?,
+            ));
+            offset = 0;
+        } else if offset == contents.len() as u64
+            && !contents.is_empty()
+            && !contents.ends_with('\n')
+        {
+            // don't run the new line into a torn one
+            line.insert(0, '\n');
+        }
+
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(line.as_bytes())
+            .context("failed to write fish sync journal")?;
+        drop(rotated);
+
+        Ok(())
+    }
+}
+
+/// Journal an operation on the fish history file, if there's a journal
+///
+/// The change to the history file already happened, so failing to journal it is logged rather
+/// than returned.
+pub fn record(journal: Option<&Journal>, entry: impl FnOnce() -> Result<JournalEntry>) {
+    let Some(journal) = journal else {
+        return;
+    };
+
+    if let Err(e) = entry().and_then(|entry| journal.write(&entry)) {
+        log::warn!(
+            "failed to write fish sync journal {}: {e:#}",
+            journal.path().display()
+        );
+    }
+}
+
+/// Every line of the journal at `path`, skipping any that don't parse
+pub fn read(path: &Path) -> Result<Vec<JournalEntry>> {
+    Ok(fs_err::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append(time: i64, added: usize, contents: &str) -> JournalEntry {
+        JournalEntry {
+            time,
+            ..JournalEntry::new(Operation::Append, contents.as_bytes())
+                .added(added)
+                .bytes_delta(contents.len() as i64)
+        }
+    }
+
+    #[test]
+    fn test_appends_close_together_share_a_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(dir.path().join("journal.jsonl"));
+
+        journal.write(&append(100, 2, "ab")).unwrap();
+        journal.write(&append(130, 3, "abc")).unwrap();
+
+        let trim = JournalEntry {
+            time: 140,
+            ..JournalEntry::new(Operation::Trim, b"c")
+                .removed(4)
+                .bytes_delta(-2)
+        };
+        journal.write(&trim).unwrap();
+
+        // one after the trim starts a new line, as does one too long after the last
+        journal.write(&append(150, 1, "cd")).unwrap();
+        journal.write(&append(250, 1, "cde")).unwrap();
+
+        let lines = read(journal.path()).unwrap();
+        assert_eq!(lines.len(), 4);
+
+        assert_eq!(lines[0].time, 100);
+        assert_eq!(lines[0].added, 5);
+        assert_eq!(lines[0].bytes_delta, 5);
+        assert_eq!(lines[0].count, 2);
+        assert_eq!(lines[0].file_hash, hash_contents(b"abc"));
+
+        assert_eq!(lines[1], trim);
+        assert_eq!(lines[2].count, 1);
+        assert_eq!(lines[3].file_hash, hash_contents(b"cde"));
+
+        let contents = fs_err::read_to_string(journal.path()).unwrap();
+        assert!(contents.contains(r#""operation":"trim""#), "{contents}");
+        assert_eq!(contents.lines().count(), 4);
+    }
+
+    #[test]
+    fn test_rotates_at_size_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(dir.path().join("journal.jsonl")).with_max_bytes(300);
+
+        let removal = |time| JournalEntry {
+            time,
+            ..JournalEntry::new(Operation::Dedupe, b"").removed(1)
+        };
+
+        for time in 0..10 {
+            journal.write(&removal(time)).unwrap();
+        }
+
+        let current = read(journal.path()).unwrap();
+        let rotated = read(&journal.rotated_path()).unwrap();
+
+        // the newest lines are in the journal, the ones before them in the rotated file
+        assert!(!current.is_empty() && !rotated.is_empty());
+        assert_eq!(current.last().unwrap().time, 9);
+        assert_eq!(rotated.last().unwrap().time + 1, current[0].time);
+        assert!(fs_err::metadata(journal.path()).unwrap().len() <= 300 + 200);
+
+        // a later rotation replaces the rotated file rather than keeping every generation
+        assert!(current.len() + rotated.len() < 10);
+    }
+
+    #[test]
+    fn test_unreadable_last_line_is_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(dir.path().join("journal.jsonl"));
+
+        fs_err::write(journal.path(), "torn").unwrap();
+        journal.write(&append(100, 1, "a")).unwrap();
+
+        let contents = fs_err::read_to_string(journal.path()).unwrap();
+        assert!(contents.starts_with("torn\n{"), "{contents}");
+        assert_eq!(read(journal.path()).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,456 @@
+//! Coordination between processes writing to the fish history file
+//!
+//! Each write already locks the fish file, but a sync writes many batches and trims in between.
+//! When the CLI and the daemon do that at the same time, their batches interleave and each trims
+//! away what the other just wrote. Holding this lock for the whole run keeps them apart.
+//!
+//! Whoever takes the lock writes a [`LockHolder`] into the lock file, so a process kept waiting
+//! can say who it's waiting for, and `atuin fish-sync unlock --force` can tell a stuck holder from
+//! one that has already exited.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use eyre::{Context, Result, bail};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::settings::Settings;
+
+const LOCK_FILENAME: &str = "fish_sync.lock";
+
+/// How often [`LockPolicy::WaitFor`] checks whether the lock is free
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What to do when another process is already writing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Wait for it to finish
+    Wait,
+
+    /// Wait at most this long, then fail with a [`LockTimeout`]
+    WaitFor(Duration),
+
+    /// Give up, leaving the writes to the other process
+    Skip,
+}
+
+impl LockPolicy {
+    /// [`LockPolicy::WaitFor`] when there's a limit, [`LockPolicy::Wait`] when there isn't
+    pub fn wait(max: Option<Duration>) -> Self {
+        max.map_or(Self::Wait, Self::WaitFor)
+    }
+}
+
+/// The process holding the lock, as it recorded itself in the lock file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+
+    /// When it took the lock, as a unix timestamp
+    pub started: i64,
+
+    /// What it's doing, as passed to [`ShellSyncLock::acquire`]
+    pub operation: String,
+}
+
+impl LockHolder {
+    fn current(operation: &str) -> Self {
+        Self {
+            pid: std::process::id(),
+            started: OffsetDateTime::now_utc().unix_timestamp(),
+            operation: operation.to_string(),
+        }
+    }
+
+    /// How long it has held the lock
+    pub fn held_for(&self) -> Duration {
+        let secs = OffsetDateTime::now_utc().unix_timestamp() - self.started;
+        Duration::from_secs(secs.max(0) as u64)
+    }
+
+    /// Whether the process is still running. Where that can't be checked, it's assumed to be.
+    pub fn is_alive(&self) -> bool {
+        #[cfg(unix)]
+        {
+            let Some(pid) = i32::try_from(self.pid)
+                .ok()
+                .and_then(rustix::process::Pid::from_raw)
+            else {
+                return false;
+            };
+
+            // EPERM means it's running, as another user
+            !matches!(
+                rustix::process::test_kill_process(pid),
+                Err(rustix::io::Errno::SRCH)
+            )
+        }
+
+        #[cfg(not(unix))]
+        {
+            true
+        }
+    }
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pid {} ({}), held for {}",
+            self.pid,
+            self.operation,
+            humantime::format_duration(self.held_for())
+        )
+    }
+}
+
+/// The lock stayed held for longer than [`LockPolicy::WaitFor`] allowed
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "gave up waiting {} for the fish sync lock, held by {}. If that process is stuck, stop it, or run `atuin fish-sync unlock --force` once it has exited",
+    humantime::format_duration(*.waited),
+    .holder.as_ref().map_or_else(|| "another process".to_string(), ToString::to_string)
+)]
+pub struct LockTimeout {
+    pub holder: Option<LockHolder>,
+    pub waited: Duration,
+}
+
+/// What [`ShellSyncLock::force_unlock`] did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unlocked {
+    /// Nothing held the lock
+    NotHeld,
+
+    /// The lock file was removed, out from under a holder that had exited
+    Removed(LockHolder),
+}
+
+/// Held for as long as a process is writing to the fish history file
+#[derive(Debug)]
+pub struct ShellSyncLock {
+    _file: File,
+}
+
+impl ShellSyncLock {
+    pub fn path(settings: &Settings) -> PathBuf {
+        Path::new(&settings.db_path).with_file_name(LOCK_FILENAME)
+    }
+
+    /// Take the lock on behalf of `actor`, which is recorded as the holder's operation
+    ///
+    /// Returns `None` if the policy is [`LockPolicy::Skip`] and another process holds the lock.
+    pub fn acquire(settings: &Settings, actor: &str, policy: LockPolicy) -> Result<Option<Self>> {
+        let path = Self::path(settings);
+        let file = open(&path)?;
+
+        if try_lock(&file)? {
+            return Self::held(file, actor).map(Some);
+        }
+
+        let holder = Self::holder(settings);
+        let holder_desc = holder
+            .as_ref()
+            .map_or_else(|| "another process".to_string(), ToString::to_string);
+
+        match policy {
+            LockPolicy::Skip => {
+                log::info!("{actor}: {holder_desc} is writing fish history, skipping");
+                Ok(None)
+            }
+            LockPolicy::Wait => {
+                log::info!("{actor}: {holder_desc} is writing fish history, waiting for it");
+                file.lock_exclusive()
+                    .context("failed to acquire fish sync lock")?;
+
+                Self::held(file, actor).map(Some)
+            }
+            LockPolicy::WaitFor(max) => {
+                log::info!(
+                    "{actor}: {holder_desc} is writing fish history, waiting up to {}",
+                    humantime::format_duration(max)
+                );
+
+                let start = Instant::now();
+                while start.elapsed() < max {
+                    std::thread::sleep(POLL_INTERVAL.min(max));
+
+                    if try_lock(&file)? {
+                        return Self::held(file, actor).map(Some);
+                    }
+                }
+
+                Err(LockTimeout {
+                    holder: Self::holder(settings),
+                    waited: max,
+                }
+                .into())
+            }
+        }
+    }
+
+    /// Who last took the lock, if they recorded it. Only meaningful while the lock is held.
+    pub fn holder(settings: &Settings) -> Option<LockHolder> {
+        let contents = fs_err::read_to_string(Self::path(settings)).ok()?;
+        serde_json::from_str(contents.trim()).ok()
+    }
+
+    /// Remove the lock file if what holds it has exited, so the next writer starts afresh
+    ///
+    /// The lock is released when its holder exits, so this is only needed when that didn't
+    /// happen, such as on a network filesystem. A holder that's still running, or that didn't
+    /// record itself, is never removed.
+    pub fn force_unlock(settings: &Settings) -> Result<Unlocked> {
+        let path = Self::path(settings);
+        if !path.exists() {
+            return Ok(Unlocked::NotHeld);
+        }
+
+        let file = open(&path)?;
+        if try_lock(&file)? {
+            return Ok(Unlocked::NotHeld);
+        }
+
+        let Some(holder) = Self::holder(settings) else {
+            bail!(
+                "the fish sync lock is held by a process that didn't record itself, not removing it"
+            );
+        };
+
+        if holder.is_alive() {
+            bail!("the fish sync lock is held by {holder}, which is still running. Stop it first");
+        }
+
+        fs_err::remove_file(&path).context("failed to remove fish sync lock")?;
+        log::warn!("removed the fish sync lock held by {holder}, which has exited");
+
+        Ok(Unlocked::Removed(holder))
+    }
+
+    /// Record this process as the holder of the lock it just took
+    fn held(mut file: File, actor: &str) -> Result<Self> {
+        let holder = serde_json::to_string(&LockHolder::current(actor))?;
+
+        // the lock works without it, so failing to record the holder only costs diagnostics
+        if let Err(e) = file.set_len(0).and_then(|()| writeln!(file, "{holder}")) {
+            log::debug!("failed to record the fish sync lock holder: {e}");
+        }
+
+        Ok(Self { _file: file })
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    fs_err::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map(File::from)
+        .context("failed to open fish sync lock")
+}
+
+/// Take the lock if it's free, returning whether it was
+fn try_lock(file: &File) -> Result<bool> {
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => Ok(false),
+        Err(e) => Err(e).context("failed to acquire fish sync lock"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Instant;
+
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::fish_sync::{CommandEntry, FishSyncOptions, FishSyncer};
+    use crate::test_support::{assert_file_parses, count_entries, fish_settings};
+
+    #[test]
+    fn test_skip_while_held() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = fish_settings(&dir.path().join("fish_history"));
+
+        let held = ShellSyncLock::acquire(&settings, "cli", LockPolicy::Wait)
+            .unwrap()
+            .unwrap();
+        assert!(
+            ShellSyncLock::acquire(&settings, "daemon", LockPolicy::Skip)
+                .unwrap()
+                .is_none()
+        );
+
+        drop(held);
+        assert!(
+            ShellSyncLock::acquire(&settings, "daemon", LockPolicy::Skip)
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_wait_for_names_the_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = fish_settings(&dir.path().join("fish_history"));
+
+        let _held = ShellSyncLock::acquire(&settings, "fish-sync rebuild", LockPolicy::Wait)
+            .unwrap()
+            .unwrap();
+
+        let err = ShellSyncLock::acquire(
+            &settings,
+            "gc",
+            LockPolicy::WaitFor(Duration::from_millis(100)),
+        )
+        .unwrap_err();
+
+        let timeout = err.downcast_ref::<LockTimeout>().unwrap();
+        let holder = timeout.holder.as_ref().unwrap();
+        assert_eq!(holder.pid, std::process::id());
+        assert_eq!(holder.operation, "fish-sync rebuild");
+        assert!(
+            err.to_string()
+                .contains(&format!("pid {} (fish-sync rebuild)", std::process::id())),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_force_unlock_refuses_a_live_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = fish_settings(&dir.path().join("fish_history"));
+
+        assert_eq!(
+            ShellSyncLock::force_unlock(&settings).unwrap(),
+            Unlocked::NotHeld
+        );
+
+        let _held = ShellSyncLock::acquire(&settings, "sync", LockPolicy::Wait)
+            .unwrap()
+            .unwrap();
+
+        let err = ShellSyncLock::force_unlock(&settings).unwrap_err();
+        assert!(err.to_string().contains("still running"), "{err}");
+        assert!(
+            ShellSyncLock::acquire(&settings, "daemon", LockPolicy::Skip)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_force_unlock_takes_over_from_a_dead_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = fish_settings(&dir.path().join("fish_history"));
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let dead = LockHolder {
+            pid: child.id(),
+            started: 1_700_000_000,
+            operation: "sync".to_string(),
+        };
+        assert!(!dead.is_alive());
+
+        // a lock that outlived its holder, the way a network filesystem can leave one
+        let stuck = open(&ShellSyncLock::path(&settings)).unwrap();
+        stuck.try_lock_exclusive().unwrap();
+        fs_err::write(
+            ShellSyncLock::path(&settings),
+            serde_json::to_string(&dead).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            ShellSyncLock::force_unlock(&settings).unwrap(),
+            Unlocked::Removed(dead)
+        );
+
+        let lock = ShellSyncLock::acquire(&settings, "sync", LockPolicy::Skip).unwrap();
+        assert!(lock.is_some());
+        assert_eq!(
+            ShellSyncLock::holder(&settings).unwrap().pid,
+            std::process::id()
+        );
+    }
+
+    #[test]
+    fn test_writers_are_serialized() {
+        let dir = tempfile::tempdir().unwrap();
+        let fish_path = dir.path().join("fish_history");
+        let settings = Arc::new(fish_settings(&fish_path));
+        let spans = Arc::new(Mutex::new(Vec::new()));
+
+        // two actors, each writing several batches and trimming after every one
+        let actors: Vec<_> = ["cli", "daemon"]
+            .into_iter()
+            .enumerate()
+            .map(|(actor_idx, actor)| {
+                let settings = settings.clone();
+                let spans = spans.clone();
+                let fish_path = fish_path.clone();
+
+                thread::spawn(move || {
+                    let _lock = ShellSyncLock::acquire(&settings, actor, LockPolicy::Wait)
+                        .unwrap()
+                        .unwrap();
+                    let start = Instant::now();
+
+                    let syncer = FishSyncer::open(
+                        &fish_path,
+                        FishSyncOptions {
+                            max_entries: 100,
+                            ..FishSyncOptions::default()
+                        },
+                    )
+                    .unwrap();
+
+                    for batch in 0..5 {
+                        let entries: Vec<_> = (0..20)
+                            .map(|i| {
+                                let n = actor_idx * 1000 + batch * 20 + i;
+                                CommandEntry::new(
+                                    format!("{actor} {n}"),
+                                    OffsetDateTime::from_unix_timestamp(n as i64).unwrap(),
+                                )
+                            })
+                            .collect();
+                        syncer.append(&entries).unwrap();
+                        thread::yield_now();
+                    }
+
+                    spans.lock().unwrap().push((start, Instant::now()));
+                })
+            })
+            .collect();
+
+        for actor in actors {
+            actor.join().unwrap();
+        }
+
+        let mut spans = spans.lock().unwrap().clone();
+        spans.sort();
+        assert_eq!(spans.len(), 2);
+        assert!(spans[0].1 <= spans[1].0, "writers overlapped");
+
+        // no interleaving, so the final trims leave a single writer's entries behind
+        assert_file_parses(&fish_path);
+        assert_eq!(count_entries(&fish_path).unwrap(), 100);
+
+        let content = fs_err::read_to_string(&fish_path).unwrap();
+        let cli = content.matches("- cmd:cli ").count();
+        let daemon = content.matches("- cmd:daemon ").count();
+        assert!(cli == 100 || daemon == 100);
+    }
+}
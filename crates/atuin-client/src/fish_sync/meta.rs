@@ -0,0 +1,679 @@
+//! Persistent counters for fish sync
+//!
+//! These live in a small JSON file next to the history database, so anything that only wants
+//! to report on fish sync (stats, status) can do so without opening SQLite or parsing the fish
+//! history file.
+//!
+//! The file is always replaced atomically, so a crash leaves either the old state or the new one,
+//! never a torn mix. It also records a hash of the fish history file as of the last write, and its
+//! size and modification time then, so a file that hasn't changed isn't read to tell. Fish
+//! keeps appending to its history between our writes, so a mismatch is expected and not an error.
+//! The rule whenever the two disagree, or the state can't be read at all, is that the fish file
+//! wins: the state is rebuilt from it with [`FishSyncMeta::load_or_rebuild`]. The exception is a
+//! state file from a newer Atuin, which is refused rather than rebuilt over, see [`versioned`].
+//!
+//! [`versioned`]: super::versioned
+
+use std::path::{Path, PathBuf};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::syncer::{FileStamp, known_contents, read_file, remember_contents, split_entries};
+use super::versioned::{ATUIN_VERSION, Format, NewerFormat};
+use crate::history::History;
+use crate::settings::Settings;
+
+const META_FILENAME: &str = "fish_sync_meta.json";
+
+/// Version 0 is every state file from before formats were versioned. All of its fields were added
+/// with defaults, so those files read the same once they're marked as version 1. Version 1 kept
+/// pending downloads as record store ids, which were never found as history, so upgrading to 2,
+/// which keeps history ids, drops them.
+const META_FORMAT: Format = Format {
+    name: "fish sync state",
+    current: 2,
+    migrations: &[
+        |_| Ok(()),
+        |fields| {
+            fields.remove("pending_downloads");
+            Ok(())
+        },
+    ],
+};
+
+/// Growth warnings are repeated at most this often
+const GROWTH_WARNING_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FishSyncMeta {
+    /// The format of the state file, see [`Format`]
+    pub version: u32,
+
+    /// The Atuin release that last saved the state
+    pub written_by: Option<String>,
+
+    /// Total number of entries ever written to the fish history file
+    pub total_written: u64,
+
+    /// Number of entries in the fish history file, as of the last write
+    pub fish_entries: u64,
+
+    /// How many of those entries Atuin wrote, as of the last write. Unset in state files from
+    /// before this was recorded.
+    pub atuin_entries: Option<u64>,
+
+    /// Number of duplicate entries removed from the fish history file
+    pub duplicates_removed: u64,
+
+    /// Unix timestamp of the last sync that wrote to the fish history file
+    pub last_sync: Option<i64>,
+
+    /// Unix timestamp of the last `atuin sync --startup` that ran to completion
+    pub last_startup_sync: Option<i64>,
+
+    /// Unix timestamp of the last sync that failed, cleared by the next one that doesn't
+    pub last_failure: Option<i64>,
+
+    /// Why the last failed sync failed
+    pub last_error: Option<String>,
+
+    /// Bumped on every save
+    pub generation: u64,
+
+    /// Hash of the fish history file as of the last write, from [`hash_contents`]
+    pub fish_hash: Option<String>,
+
+    /// Size and modification time of the fish history file when `fish_hash` was taken, so a file
+    /// that hasn't changed since isn't read again to check it
+    pub(crate) fish_stamp: Option<FileStamp>,
+
+    /// The last entry an unfinished bootstrap wrote, so a restarted one can carry on from there
+    pub bootstrap_cursor: Option<BootstrapCursor>,
+
+    /// Ids of downloaded history a stopped sync didn't get to, written first by the next one
+    pub pending_downloads: Vec<String>,
+
+    /// Unix timestamp of the last warning that the fish history file is growing too large
+    pub last_growth_warning: Option<i64>,
+
+    /// When fish sessions were last notified of new entries, in unix milliseconds
+    pub last_notify_ms: Option<i64>,
+
+    /// A notification was throttled, and still needs sending
+    pub notify_pending: bool,
+}
+
+/// Where a bootstrap got to, as a position in [`super::write_order`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BootstrapCursor {
+    /// Unix timestamp in nanoseconds
+    pub timestamp: i128,
+    pub hostname: String,
+    pub id: String,
+}
+
+impl From<&History> for BootstrapCursor {
+    fn from(history: &History) -> Self {
+        Self {
+            timestamp: history.timestamp.unix_timestamp_nanos(),
+            hostname: history.hostname.clone(),
+            id: history.id.0.clone(),
+        }
+    }
+}
+
+/// A stable hash of a fish history file's contents (64 bit FNV-1a, as hex)
+pub fn hash_contents(content: &[u8]) -> String {
+    format!("{:016x}", fnv1a(FNV_OFFSET, content))
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Carry on an FNV-1a hash over `more` bytes, so appending to a file needn't hash it all again
+fn fnv1a(hash: u64, more: &[u8]) -> u64 {
+    more.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// What the sync state records of a fish history file's contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ContentSummary {
+    hash: u64,
+    entries: u64,
+    atuin_entries: u64,
+}
+
+impl ContentSummary {
+    pub(crate) fn of(fish: &str) -> Self {
+        Self {
+            hash: FNV_OFFSET,
+            entries: 0,
+            atuin_entries: 0,
+        }
+        .appended(fish)
+    }
+
+    /// The same file, with `appended` added to the end of it
+    pub(crate) fn appended(&self, appended: &str) -> Self {
+        let entries = split_entries(appended).1;
+
+        Self {
+            hash: fnv1a(self.hash, appended.as_bytes()),
+            entries: self.entries + entries.len() as u64,
+            atuin_entries: self.atuin_entries
+                + entries.iter().filter(|e| e.uuid.is_some()).count() as u64,
+        }
+    }
+
+    /// The same hash as [`hash_contents`] of the whole file
+    pub(crate) fn hash(&self) -> String {
+        format!("{:016x}", self.hash)
+    }
+}
+
+/// What the fish history file at `path` holds, and its stamp if there's a file there
+///
+/// It's only read when Atuin doesn't already know, from having written it last.
+pub(crate) fn fish_contents(path: &Path) -> Result<(Option<FileStamp>, ContentSummary)> {
+    if let Some((stamp, contents)) = known_contents(path) {
+        return Ok((Some(stamp), contents));
+    }
+
+    // taken first, so anything written while it's read makes the stamp stale, not the contents
+    let stamp = FileStamp::of_path(path);
+    let fish = match read_file(path) {
+        Ok(fish) => fish,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok((stamp, ContentSummary::of(&String::from_utf8_lossy(&fish))))
+}
+
+impl FishSyncMeta {
+    pub fn path(settings: &Settings) -> PathBuf {
+        Path::new(&settings.db_path).with_file_name(META_FILENAME)
+    }
+
+    /// Load the counters, treating a missing file as "nothing synced yet"
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs_err::read_to_string(path)?;
+
+        META_FORMAT.load(path, &contents)
+    }
+
+    /// Load the state, starting over if it's unreadable, for a write that's about to refresh
+    /// everything derived from the fish history file anyway
+    ///
+    /// Rebuilding it from the file first would count what was just written to it twice.
+    pub(crate) fn load_for_write(path: &Path) -> Result<Self> {
+        match Self::load(path) {
+            Err(e) if !e.is::<NewerFormat>() => {
+                log::warn!("{e}, starting fish sync state over");
+                Ok(Self::default())
+            }
+            loaded => loaded,
+        }
+    }
+
+    /// Load the state, rebuilding it from the fish history file if it's unreadable or stale
+    ///
+    /// The fish file is only read when it's changed since the state was saved, and Atuin didn't
+    /// change it itself.
+    pub fn load_or_rebuild(path: &Path, fish_path: &Path) -> Result<Self> {
+        let mut meta = match Self::load(path) {
+            Ok(meta) if meta.fish_hash.is_none() => return Ok(meta),
+            Ok(meta)
+                if meta.fish_stamp.is_some()
+                    && meta.fish_stamp == FileStamp::of_path(fish_path) =>
+            {
+                return Ok(meta);
+            }
+            Ok(meta) => meta,
+            Err(e) if e.is::<NewerFormat>() => return Err(e),
+            Err(e) => {
+                log::warn!("{e}, rebuilding fish sync state from the fish history file");
+                Self::default()
+            }
+        };
+
+        let (stamp, contents) = fish_contents(fish_path)?;
+
+        if meta.fish_hash.as_deref() == Some(&contents.hash()) {
+            meta.fish_stamp = stamp;
+            return Ok(meta);
+        }
+
+        if meta.fish_hash.is_some() {
+            log::debug!("fish history changed since the last sync, refreshing its state");
+        }
+
+        meta.rebuild_from(&contents, stamp);
+
+        Ok(meta)
+    }
+
+    /// Refresh everything derived from the fish history file
+    fn rebuild_from(&mut self, contents: &ContentSummary, stamp: Option<FileStamp>) {
+        self.refresh(contents, stamp);
+
+        // counters lost along with a torn state file are at least what's still in the file
+        self.total_written = self.total_written.max(contents.atuin_entries);
+    }
+
+    /// Take the counts and hash of the fish history file from `contents`
+    fn refresh(&mut self, contents: &ContentSummary, stamp: Option<FileStamp>) {
+        self.fish_entries = contents.entries;
+        self.atuin_entries = Some(contents.atuin_entries);
+        self.fish_hash = Some(contents.hash());
+        self.fish_stamp = stamp;
+    }
+
+    /// Let appends to the fish file at `fish_path` keep what this state knows of it up to date,
+    /// if it hasn't changed since the state was saved
+    pub(crate) fn share_contents(&self, fish_path: &Path) {
+        let (Some(hash), Some(atuin_entries), Some(stamp)) =
+            (&self.fish_hash, self.atuin_entries, self.fish_stamp)
+        else {
+            return;
+        };
+
+        if FileStamp::of_path(fish_path) != Some(stamp) {
+            return;
+        }
+
+        if let Ok(hash) = u64::from_str_radix(hash, 16) {
+            let contents = ContentSummary {
+                hash,
+                entries: self.fish_entries,
+                atuin_entries,
+            };
+            remember_contents(fish_path, stamp, contents);
+        }
+    }
+
+    /// Atomically replace the state on disk, bumping the generation
+    ///
+    /// Fails rather than write over the state of a newer Atuin.
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        META_FORMAT.ensure_writable(path)?;
+
+        self.generation += 1;
+        self.version = META_FORMAT.current;
+        self.written_by = Some(ATUIN_VERSION.to_string());
+
+        let temp = self.write_temp(path)?;
+        fs_err::rename(temp, path)?;
+
+        Ok(())
+    }
+
+    /// The first half of [`FishSyncMeta::save`]: write the new state next to the old one
+    fn write_temp(&self, path: &Path) -> Result<PathBuf> {
+        let temp = path.with_extension("json.tmp");
+        let contents = serde_json::to_string(self)?;
+
+        let mut file = fs_err::File::create(&temp)?;
+        std::io::Write::write_all(&mut file, contents.as_bytes())?;
+        file.sync_all()?;
+
+        Ok(temp)
+    }
+
+    /// Record a sync that wrote `written` entries, leaving the fish file with `fish` in it
+    pub fn record_sync(&mut self, written: u64, fish: &str) {
+        self.record_sync_of(written, &ContentSummary::of(fish), None);
+    }
+
+    /// Like [`FishSyncMeta::record_sync`], from a summary of the file as it was at `stamp`
+    pub(crate) fn record_sync_of(
+        &mut self,
+        written: u64,
+        contents: &ContentSummary,
+        stamp: Option<FileStamp>,
+    ) {
+        // counters lost along with a torn state file are at least what's still in the file
+        self.total_written = (self.total_written + written).max(contents.atuin_entries);
+        self.refresh(contents, stamp);
+        self.last_sync = Some(OffsetDateTime::now_utc().unix_timestamp());
+    }
+
+    /// Record a rewrite that removed entries, `duplicates` of them as copies of others, leaving the
+    /// fish file with `contents` in it
+    pub(crate) fn record_rewrite(
+        &mut self,
+        contents: &ContentSummary,
+        stamp: Option<FileStamp>,
+        duplicates: u64,
+    ) {
+        self.duplicates_removed += duplicates;
+        self.refresh(contents, stamp);
+    }
+
+    /// Record how the latest sync went, returning whether anything changed
+    pub fn record_outcome(&mut self, error: Option<String>, now: OffsetDateTime) -> bool {
+        match error {
+            Some(error) => {
+                self.last_failure = Some(now.unix_timestamp());
+                self.last_error = Some(error);
+                true
+            }
+            None if self.last_failure.is_some() => {
+                self.last_failure = None;
+                self.last_error = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether a growth warning is due at `now`, recording it if so. At most one is due a day.
+    pub fn growth_warning_due(&mut self, now: OffsetDateTime) -> bool {
+        let now = now.unix_timestamp();
+
+        // a clock that went backwards counts as due, as for startup syncs
+        if self
+            .last_growth_warning
+            .is_some_and(|last| (0..GROWTH_WARNING_INTERVAL_SECS).contains(&(now - last)))
+        {
+            return false;
+        }
+
+        self.last_growth_warning = Some(now);
+        true
+    }
+
+    pub fn last_sync(&self) -> Option<OffsetDateTime> {
+        self.last_sync
+            .and_then(|ts| OffsetDateTime::from_unix_timestamp(ts).ok())
+    }
+
+    /// Whether a startup sync should run, given it runs at most once per `interval_mins`
+    pub fn startup_sync_due(&self, interval_mins: u64, now: OffsetDateTime) -> bool {
+        let Some(last) = self.last_startup_sync else {
+            return true;
+        };
+
+        let elapsed = now.unix_timestamp() - last;
+
+        // a marker from the future means the clock moved, so don't trust it
+        elapsed < 0 || elapsed >= i64::try_from(interval_mins * 60).unwrap_or(i64::MAX)
+    }
+
+    pub fn record_startup_sync(&mut self, now: OffsetDateTime) {
+        self.last_startup_sync = Some(now.unix_timestamp());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fish_sync::versioned;
+    use crate::test_support::FishFileBuilder;
+
+    #[test]
+    fn test_load_missing_is_default() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let meta = FishSyncMeta::load(&temp_dir.path().join(META_FILENAME)).unwrap();
+
+        assert_eq!(meta, FishSyncMeta::default());
+        assert!(meta.last_sync().is_none());
+    }
+
+    #[test]
+    fn test_record_sync_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(META_FILENAME);
+
+        let mut meta = FishSyncMeta::default();
+        meta.record_sync(3, &FishFileBuilder::new().many_native(10, 0).build());
+        meta.record_sync(2, &FishFileBuilder::new().many_native(12, 0).build());
+        meta.save(&path).unwrap();
+
+        let loaded = FishSyncMeta::load(&path).unwrap();
+        assert_eq!(loaded.total_written, 5);
+        assert_eq!(loaded.fish_entries, 12);
+        assert_eq!(loaded.generation, 1);
+        assert!(loaded.last_sync().is_some());
+    }
+
+    /// The write sequence is: append to the fish file, write the new state to a temp file, then
+    /// rename it over the old state. Crash after each step and check the state recovers.
+    #[test]
+    fn test_recovers_from_crash_at_each_step() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(META_FILENAME);
+        let fish_path = temp_dir.path().join("fish_history");
+
+        let before = FishFileBuilder::new().atuin("ls", 1, "a").build();
+        let after = FishFileBuilder::new()
+            .atuin("ls", 1, "a")
+            .atuin("pwd", 2, "b")
+            .build();
+
+        let reset = || {
+            fs_err::write(&fish_path, &before).unwrap();
+            let mut meta = FishSyncMeta::default();
+            meta.record_sync(1, &before);
+            meta.save(&path).unwrap();
+            let _ = fs_err::remove_file(path.with_extension("json.tmp"));
+        };
+
+        // crash after appending to the fish file, before touching the state
+        reset();
+        fs_err::write(&fish_path, &after).unwrap();
+        let meta = FishSyncMeta::load_or_rebuild(&path, &fish_path).unwrap();
+        assert_eq!(meta.fish_entries, 2);
+        assert_eq!(meta.total_written, 2);
+        assert_eq!(meta.fish_hash, Some(hash_contents(after.as_bytes())));
+
+        // crash after writing the temp file, before renaming it
+        reset();
+        fs_err::write(&fish_path, &after).unwrap();
+        let mut next = FishSyncMeta::load(&path).unwrap();
+        next.record_sync(1, &after);
+        next.write_temp(&path).unwrap();
+        let meta = FishSyncMeta::load_or_rebuild(&path, &fish_path).unwrap();
+        assert_eq!(meta.fish_entries, 2);
+        assert_eq!(meta.generation, 1);
+
+        // and the leftover temp file doesn't get in the way of the next save
+        let mut meta = meta;
+        meta.save(&path).unwrap();
+        assert_eq!(FishSyncMeta::load(&path).unwrap().generation, 2);
+
+        // a torn state file, as left by anything that didn't write it atomically
+        reset();
+        fs_err::write(&fish_path, &after).unwrap();
+        fs_err::write(&path, r#"{"total_written":2,"fish_ent"#).unwrap();
+        let meta = FishSyncMeta::load_or_rebuild(&path, &fish_path).unwrap();
+        assert_eq!(meta.fish_entries, 2);
+        assert_eq!(meta.total_written, 2);
+
+        // the whole sequence completed
+        reset();
+        fs_err::write(&fish_path, &after).unwrap();
+        let mut next = FishSyncMeta::load(&path).unwrap();
+        next.record_sync(1, &after);
+        next.save(&path).unwrap();
+        let meta = FishSyncMeta::load_or_rebuild(&path, &fish_path).unwrap();
+        // checking the hash also notes the file's stamp, so it needn't be read again next time
+        assert_eq!(meta.fish_stamp, FileStamp::of_path(&fish_path));
+        assert_eq!(
+            FishSyncMeta {
+                fish_stamp: None,
+                ..meta.clone()
+            },
+            next
+        );
+        assert_eq!(meta.generation, 2);
+    }
+
+    /// As `atuin stats` first recorded it
+    const COUNTERS_ONLY: &str =
+        r#"{"total_written":12,"fish_entries":40,"duplicates_removed":3,"last_sync":1700000000}"#;
+
+    /// With a bootstrap and a download both interrupted part way, from before formats were
+    /// versioned
+    const UNVERSIONED: &str = r#"{
+        "total_written": 500,
+        "fish_entries": 800,
+        "duplicates_removed": 0,
+        "last_sync": 1700000000,
+        "last_startup_sync": null,
+        "last_failure": null,
+        "last_error": null,
+        "generation": 7,
+        "fish_hash": "cbf29ce484222325",
+        "bootstrap_cursor": {
+            "timestamp": 1700000000123456789,
+            "hostname": "laptop:user",
+            "id": "0190b1a27c4e70008000000000000001"
+        },
+        "pending_downloads": [
+            "0190b1a2-7c4e-7000-8000-000000000002",
+            "0190b1a2-7c4e-7000-8000-000000000003"
+        ],
+        "last_growth_warning": null,
+        "last_notify_ms": null,
+        "notify_pending": true
+    }"#;
+
+    #[test]
+    fn test_migrates_historical_formats() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(META_FILENAME);
+
+        fs_err::write(&path, COUNTERS_ONLY).unwrap();
+        let meta = FishSyncMeta::load(&path).unwrap();
+        assert_eq!(meta.version, META_FORMAT.current);
+        assert_eq!(meta.written_by.as_deref(), Some(ATUIN_VERSION));
+        assert_eq!(
+            (
+                meta.total_written,
+                meta.fish_entries,
+                meta.duplicates_removed
+            ),
+            (12, 40, 3)
+        );
+        assert_eq!(meta.last_sync, Some(1_700_000_000));
+
+        fs_err::write(&path, UNVERSIONED).unwrap();
+        let meta = FishSyncMeta::load(&path).unwrap();
+        assert_eq!(meta.version, META_FORMAT.current);
+        assert_eq!(meta.generation, 7);
+        assert!(meta.notify_pending);
+
+        // where a restarted bootstrap carries on from mustn't be lost
+        assert_eq!(
+            meta.bootstrap_cursor,
+            Some(BootstrapCursor {
+                timestamp: 1_700_000_000_123_456_789,
+                hostname: "laptop:user".to_string(),
+                id: "0190b1a27c4e70008000000000000001".to_string(),
+            })
+        );
+
+        // but pending downloads were record ids, which never led to any history
+        assert!(meta.pending_downloads.is_empty());
+
+        // upgraded in place, with the original kept
+        assert_eq!(
+            fs_err::read_to_string(versioned::backup_path(&path, 0)).unwrap(),
+            COUNTERS_ONLY
+        );
+        assert_eq!(FishSyncMeta::load(&path).unwrap(), meta);
+    }
+
+    #[test]
+    fn test_newer_state_is_left_alone() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(META_FILENAME);
+        let fish_path = temp_dir.path().join("fish_history");
+        fs_err::write(
+            &fish_path,
+            FishFileBuilder::new().atuin("ls", 1, "a").build(),
+        )
+        .unwrap();
+
+        let newer = r#"{"version":99,"written_by":"99.0.0","total_written":5}"#;
+        fs_err::write(&path, newer).unwrap();
+
+        assert!(FishSyncMeta::load(&path).is_err());
+        let err = FishSyncMeta::load_or_rebuild(&path, &fish_path).unwrap_err();
+        assert!(err.to_string().contains("99.0.0"), "{err}");
+        assert!(FishSyncMeta::default().save(&path).is_err());
+
+        assert_eq!(fs_err::read_to_string(&path).unwrap(), newer);
+    }
+
+    #[test]
+    fn test_record_outcome() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let mut meta = FishSyncMeta::default();
+
+        assert!(!meta.record_outcome(None, now));
+
+        assert!(meta.record_outcome(Some("disk full".to_string()), now));
+        assert_eq!(meta.last_failure, Some(1_700_000_000));
+        assert_eq!(meta.last_error.as_deref(), Some("disk full"));
+
+        assert!(meta.record_outcome(None, now));
+        assert_eq!(meta.last_failure, None);
+        assert_eq!(meta.last_error, None);
+    }
+
+    #[test]
+    fn test_startup_sync_due() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let mut meta = FishSyncMeta::default();
+
+        // never ran
+        assert!(meta.startup_sync_due(60, now));
+
+        meta.record_startup_sync(now);
+        assert!(!meta.startup_sync_due(60, now));
+        assert!(!meta.startup_sync_due(60, now + time::Duration::minutes(59)));
+        assert!(meta.startup_sync_due(60, now + time::Duration::minutes(60)));
+
+        // clock went backwards
+        assert!(meta.startup_sync_due(60, now - time::Duration::minutes(1)));
+
+        // 0 means every time
+        assert!(meta.startup_sync_due(0, now));
+    }
+
+    #[test]
+    fn test_growth_warning_due_once_a_day() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let mut meta = FishSyncMeta::default();
+
+        assert!(meta.growth_warning_due(now));
+        assert!(!meta.growth_warning_due(now));
+        assert!(!meta.growth_warning_due(now + time::Duration::hours(23)));
+        assert!(meta.growth_warning_due(now + time::Duration::hours(24)));
+        assert!(!meta.growth_warning_due(now + time::Duration::hours(25)));
+    }
+
+    #[test]
+    fn test_startup_marker_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(META_FILENAME);
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+
+        let mut meta = FishSyncMeta::default();
+        meta.record_startup_sync(now);
+        meta.save(&path).unwrap();
+
+        let loaded = FishSyncMeta::load(&path).unwrap();
+        assert!(!loaded.startup_sync_due(60, now + time::Duration::minutes(1)));
+    }
+}
@@ -0,0 +1,160 @@
+//! In-memory sync metrics, keyed by shell target
+//!
+//! Fish is the only target today, but everything that reports on shell sync goes through this
+//! map so that adding another shell is a matter of recording under a new name.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+/// Target name used for the fish history file
+pub const FISH_TARGET: &str = "fish";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct TargetMetrics {
+    /// Entries written to the target's history file
+    pub written: u64,
+
+    /// Entries that were considered but not written
+    pub skipped: u64,
+
+    /// Of the skipped entries, those older than everything in an already full history file
+    pub too_old: u64,
+
+    /// Entries held back by the write rate limit and written together in a later write
+    pub deferred: u64,
+
+    /// Entries that failed to write
+    pub errors: u64,
+
+    /// When the target last completed a batch without errors
+    pub last_success: Option<OffsetDateTime>,
+}
+
+impl TargetMetrics {
+    /// Fold a single batch into the running totals
+    pub fn merge(&mut self, batch: &TargetMetrics) {
+        self.written += batch.written;
+        self.skipped += batch.skipped;
+        self.too_old += batch.too_old;
+        self.deferred += batch.deferred;
+        self.errors += batch.errors;
+
+        if batch.last_success.is_some() {
+            self.last_success = batch.last_success;
+        }
+    }
+
+    /// Render a compact, single line summary suitable for logs
+    pub fn summary_line(&self, target: &str) -> String {
+        let last_success = self
+            .last_success
+            .map_or_else(|| "never".to_string(), |t| t.unix_timestamp().to_string());
+
+        format!(
+            "{target}: written={} skipped={} (too old={}) deferred={} errors={} last_success={last_success}",
+            self.written, self.skipped, self.too_old, self.deferred, self.errors
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ShellSyncMetrics {
+    targets: BTreeMap<String, TargetMetrics>,
+}
+
+impl ShellSyncMetrics {
+    pub fn record(&mut self, target: &str, batch: &TargetMetrics) {
+        self.targets
+            .entry(target.to_string())
+            .or_default()
+            .merge(batch);
+    }
+
+    pub fn target(&self, target: &str) -> Option<&TargetMetrics> {
+        self.targets.get(target)
+    }
+
+    /// Iterate over targets in name order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TargetMetrics)> {
+        self.targets.iter().map(|(name, m)| (name.as_str(), m))
+    }
+
+    /// One summary line per target, in name order
+    pub fn summary_lines(&self) -> Vec<String> {
+        self.iter().map(|(name, m)| m.summary_line(name)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_merges_per_target() {
+        let mut metrics = ShellSyncMetrics::default();
+
+        let now = OffsetDateTime::now_utc();
+        metrics.record(
+            FISH_TARGET,
+            &TargetMetrics {
+                written: 2,
+                skipped: 1,
+                too_old: 1,
+                deferred: 0,
+                errors: 0,
+                last_success: Some(now),
+            },
+        );
+        metrics.record(
+            FISH_TARGET,
+            &TargetMetrics {
+                written: 1,
+                errors: 1,
+                ..Default::default()
+            },
+        );
+        metrics.record(
+            "zsh",
+            &TargetMetrics {
+                written: 5,
+                ..Default::default()
+            },
+        );
+
+        let fish = metrics.target(FISH_TARGET).unwrap();
+        assert_eq!(fish.written, 3);
+        assert_eq!(fish.skipped, 1);
+        assert_eq!(fish.too_old, 1);
+        assert_eq!(fish.errors, 1);
+        // a failed batch doesn't clear the last success
+        assert_eq!(fish.last_success, Some(now));
+
+        assert_eq!(metrics.target("zsh").unwrap().written, 5);
+        assert!(metrics.target("bash").is_none());
+    }
+
+    #[test]
+    fn test_summary_lines_one_per_target() {
+        let mut metrics = ShellSyncMetrics::default();
+        metrics.record("zsh", &TargetMetrics::default());
+        metrics.record(
+            FISH_TARGET,
+            &TargetMetrics {
+                written: 4,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            metrics.summary_lines(),
+            vec![
+                "fish: written=4 skipped=0 (too old=0) deferred=0 errors=0 last_success=never"
+                    .to_string(),
+                "zsh: written=0 skipped=0 (too old=0) deferred=0 errors=0 last_success=never"
+                    .to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! Telling running fish sessions about entries written to their history file
+//!
+//! fish only reads its history file when a session starts, or when told to with `history merge`.
+//! Running that from outside fish only affects a throwaway process, so the `uvar` mode sets a
+//! universal variable instead. fish propagates those to every session, and the hooks printed by
+//! `atuin init fish` merge whenever it changes.
+//!
+//! The CLI and the daemon may both write batches in quick succession, so notifications are
+//! throttled across processes rather than sent for every batch.
+
+use time::OffsetDateTime;
+
+use super::meta::FishSyncMeta;
+use super::runner::{DEFAULT_TIMEOUT, FishRunner, SystemFish};
+use crate::settings::{FishSyncNotify, Settings};
+
+/// The universal variable sessions watch when `notify = "uvar"`
+pub const DIRTY_VAR: &str = "_atuin_history_dirty";
+
+/// Notify running fish sessions that the history file changed, if configured to
+///
+/// Notifications are throttled to one per `fish_sync.notify_interval_secs` across every process,
+/// by way of the time of the last one in the meta file. One that's throttled is left pending,
+/// and sent with the next notification that isn't, or by [`flush_pending`]. Callers hold the
+/// shell sync lock, which keeps the meta file consistent between processes.
+///
+/// Failures are logged rather than returned, as the entries themselves were written fine.
+pub fn notify_sessions(settings: &Settings) {
+    notify_with(settings, OffsetDateTime::now_utc(), &SystemFish);
+}
+
+/// Send a notification that was throttled earlier, if one is due now
+pub fn flush_pending(settings: &Settings) {
+    let pending =
+        FishSyncMeta::load(&FishSyncMeta::path(settings)).is_ok_and(|meta| meta.notify_pending);
+
+    if pending {
+        notify_sessions(settings);
+    }
+}
+
+fn notify_with(settings: &Settings, now: OffsetDateTime, fish: &dyn FishRunner) -> bool {
+    let Some(script) = notify_script(settings.fish_sync.notify, now) else {
+        return false;
+    };
+
+    let path = FishSyncMeta::path(settings);
+    let mut meta = match FishSyncMeta::load(&path) {
+        Ok(meta) => meta,
+        Err(e) => {
+            log::warn!("failed to read fish sync meta, notifying fish sessions anyway: {e}");
+            FishSyncMeta::default()
+        }
+    };
+
+    let interval_ms = i64::try_from(settings.fish_sync.notify_interval_secs)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(1000);
+    let now_ms = i64::try_from(now.unix_timestamp_nanos() / 1_000_000).unwrap_or(i64::MAX);
+
+    let due = debounce(&mut meta.last_notify_ms, now_ms, interval_ms);
+    meta.notify_pending = !due;
+
+    if let Err(e) = meta.save(&path) {
+        log::warn!("failed to update fish sync meta: {e}");
+    }
+
+    if !due {
+        log::debug!("fish notification sent recently, leaving this one pending");
+        return false;
+    }
+
+    // skip the user's config, which would otherwise load atuin's own hooks into this process
+    let result = fish
+        .run(&["--no-config", "-c", &script], DEFAULT_TIMEOUT)
+        .and_then(|output| output.into_stdout());
+
+    if let Err(e) = result {
+        log::warn!("failed to notify fish sessions: {e}");
+    }
+
+    true
+}
+
+/// The fish script that notifies sessions, or `None` if notifications are off
+fn notify_script(notify: FishSyncNotify, now: OffsetDateTime) -> Option<String> {
+    match notify {
+        FishSyncNotify::None => None,
+        FishSyncNotify::Merge => Some("history merge".to_string()),
+        FishSyncNotify::Uvar => Some(format!("set -U {DIRTY_VAR} {}", now.unix_timestamp())),
+    }
+}
+
+/// Whether a notification is due at `now_ms`, recording it if so
+///
+/// A clock that went backwards counts as due, rather than holding notifications back until it
+/// catches up.
+fn debounce(last_ms: &mut Option<i64>, now_ms: i64, interval_ms: i64) -> bool {
+    if last_ms.is_some_and(|last| (0..interval_ms).contains(&(now_ms - last))) {
+        return false;
+    }
+
+    *last_ms = Some(now_ms);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::FishSync;
+    use crate::test_support::{ScriptedFish, fish_settings};
+
+    #[test]
+    fn test_notify_script() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+
+        assert_eq!(notify_script(FishSyncNotify::None, now), None);
+        assert_eq!(
+            notify_script(FishSyncNotify::Merge, now).as_deref(),
+            Some("history merge")
+        );
+        assert_eq!(
+            notify_script(FishSyncNotify::Uvar, now).as_deref(),
+            Some("set -U _atuin_history_dirty 1700000000")
+        );
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mut last = None;
+
+        assert!(debounce(&mut last, 10_000, 5000));
+        assert!(!debounce(&mut last, 11_000, 5000));
+        assert!(!debounce(&mut last, 14_999, 5000));
+        assert!(debounce(&mut last, 15_000, 5000));
+        assert!(!debounce(&mut last, 16_000, 5000));
+
+        // clock went backwards
+        assert!(debounce(&mut last, 1000, 5000));
+
+        // 0 means every time
+        assert!(debounce(&mut last, 1000, 0));
+    }
+
+    #[test]
+    fn test_rapid_batches_notify_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut settings = fish_settings(&dir.path().join("fish_history"));
+        settings.fish_sync.notify = FishSyncNotify::Merge;
+
+        let start = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let fish = ScriptedFish::default();
+
+        // a busy sync, ten batches in as many hundred milliseconds, from two processes
+        for i in 0..10 {
+            let now = start + time::Duration::milliseconds(i * 100);
+            notify_with(&settings, now, &fish);
+        }
+
+        assert_eq!(
+            fish.calls(),
+            vec![vec!["--no-config", "-c", "history merge"]]
+        );
+
+        let meta = FishSyncMeta::load(&FishSyncMeta::path(&settings)).unwrap();
+        assert!(meta.notify_pending);
+
+        // the next one after the interval covers everything left pending
+        let later = start + time::Duration::seconds(5);
+        assert!(notify_with(&settings, later, &fish));
+        assert_eq!(fish.calls().len(), 2);
+
+        let meta = FishSyncMeta::load(&FishSyncMeta::path(&settings)).unwrap();
+        assert!(!meta.notify_pending);
+    }
+
+    /// `[fish_sync]` settings as read from a config file
+    fn fish_sync_from(toml: &str) -> FishSync {
+        let mut fish_sync: FishSync = config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        fish_sync.apply_deprecated();
+        fish_sync
+    }
+
+    #[test]
+    fn test_fish_merge_merges_once_per_batch() {
+        let start = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let merge = vec!["--no-config", "-c", "history merge"];
+
+        for (config, expected) in [
+            ("fish_merge = true", vec![merge.clone(); 3]),
+            ("fish_merge = false", vec![]),
+            ("", vec![]),
+            // "none" reads the same as leaving notify unset
+            (
+                "fish_merge = true\nnotify = \"none\"",
+                vec![merge.clone(); 3],
+            ),
+            // otherwise the key that replaced it wins
+            (
+                "fish_merge = false\nnotify = \"merge\"",
+                vec![merge.clone(); 3],
+            ),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut settings = fish_settings(&dir.path().join("fish_history"));
+            settings.fish_sync = FishSync {
+                notify_interval_secs: 0,
+                ..fish_sync_from(config)
+            };
+
+            let fish = ScriptedFish::default();
+            for i in 0..3 {
+                notify_with(&settings, start + time::Duration::seconds(i), &fish);
+            }
+
+            assert_eq!(fish.calls(), expected, "{config}");
+        }
+    }
+
+    #[test]
+    fn test_failed_notification_is_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut settings = fish_settings(&dir.path().join("fish_history"));
+        settings.fish_sync.notify = FishSyncNotify::Uvar;
+
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let fish = ScriptedFish::default().then_output(127, "");
+        assert!(notify_with(&settings, now, &fish));
+
+        let meta = FishSyncMeta::load(&FishSyncMeta::path(&settings)).unwrap();
+        assert!(!meta.notify_pending);
+    }
+}
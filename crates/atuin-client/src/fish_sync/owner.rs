@@ -0,0 +1,123 @@
+//! Noticing when the fish history file belongs to another user
+//!
+//! Run through `sudo`, or from a daemon started as root, `~` can resolve to a different home than
+//! the user expects, and Atuin ends up appending to root's fish history, or root appends to
+//! someone else's. Either way the file's owner differs from the user Atuin runs as, so that's what
+//! gets checked before writing.
+
+use std::path::Path;
+
+/// Explain why `path` looks like another user's fish history, if it does
+///
+/// If the file doesn't exist yet, the directory it would be created in is checked instead.
+#[cfg(unix)]
+pub fn foreign_owner_reason(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = match fs_err::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => fs_err::metadata(path.parent()?).ok()?,
+    };
+
+    owner_mismatch(
+        metadata.uid(),
+        rustix::process::geteuid().as_raw(),
+        user_name,
+    )
+}
+
+#[cfg(not(unix))]
+pub fn foreign_owner_reason(_path: &Path) -> Option<String> {
+    None
+}
+
+/// Explain the mismatch between a file's `owner` and the effective user `euid`, if there is one
+///
+/// `name_of` looks up a user's name, so the message can say who's who.
+pub fn owner_mismatch(
+    owner: u32,
+    euid: u32,
+    name_of: impl Fn(u32) -> Option<String>,
+) -> Option<String> {
+    if owner == euid {
+        return None;
+    }
+
+    let describe = |uid| match name_of(uid) {
+        Some(name) => format!("{name} (uid {uid})"),
+        None => format!("uid {uid}"),
+    };
+
+    Some(format!(
+        "it belongs to {}, but atuin is running as {}",
+        describe(owner),
+        describe(euid)
+    ))
+}
+
+/// Look a user's name up in `/etc/passwd`
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    let passwd = fs_err::read_to_string("/etc/passwd").ok()?;
+
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id = fields.nth(1)?.parse::<u32>().ok()?;
+
+        (id == uid).then(|| name.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(uid: u32) -> Option<String> {
+        match uid {
+            0 => Some("root".to_string()),
+            1000 => Some("alice".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_owner_mismatch() {
+        assert_eq!(owner_mismatch(1000, 1000, names), None);
+        assert_eq!(owner_mismatch(0, 0, names), None);
+
+        assert_eq!(
+            owner_mismatch(1000, 0, names).unwrap(),
+            "it belongs to alice (uid 1000), but atuin is running as root (uid 0)"
+        );
+        assert_eq!(
+            owner_mismatch(0, 1000, names).unwrap(),
+            "it belongs to root (uid 0), but atuin is running as alice (uid 1000)"
+        );
+        assert_eq!(
+            owner_mismatch(4242, 1000, names).unwrap(),
+            "it belongs to uid 4242, but atuin is running as alice (uid 1000)"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_foreign_owner_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fish_history");
+
+        // not there yet, so its directory is checked, and that's ours
+        assert_eq!(foreign_owner_reason(&path), None);
+
+        fs_err::write(&path, "").unwrap();
+        assert_eq!(foreign_owner_reason(&path), None);
+
+        // handing the file to someone else needs root, which tests don't always have
+        if std::os::unix::fs::chown(&path, Some(65534), None).is_err() {
+            return;
+        }
+
+        let reason = foreign_owner_reason(&path).unwrap();
+        assert!(reason.contains("65534"), "{reason}");
+    }
+}
@@ -0,0 +1,174 @@
+//! The paths a command names, for the `paths:` field of its fish history entry
+//!
+//! Fish only autosuggests an old command while the paths in its entry still exist. It checks
+//! every path it's given, so an argument wrongly taken for one would keep the command from ever
+//! being suggested. Only arguments that are clearly paths count: those with a `/`, or starting
+//! with `~` or `.`, written without anything the shell would expand.
+
+use std::path::Path;
+
+/// More than this and the rest are left out, to keep entries small
+const MAX_PATHS: usize = 16;
+
+/// Longer arguments aren't taken for paths
+const MAX_PATH_LEN: usize = 4096;
+
+/// The paths `command`'s arguments name, relative ones resolved against `cwd`
+///
+/// A `cwd` that isn't absolute, such as the `unknown` of imported history, leaves relative paths
+/// as written, for fish to check against its own directory.
+pub fn command_paths(command: &str, cwd: &str) -> Vec<String> {
+    let cwd = Path::new(cwd);
+    let mut paths: Vec<String> = Vec::new();
+
+    for word in arguments(command) {
+        if !looks_like_path(&word) {
+            continue;
+        }
+
+        let path = if word.starts_with('~') || Path::new(&word).is_absolute() || !cwd.is_absolute()
+        {
+            word
+        } else {
+            cwd.join(&word).to_string_lossy().into_owned()
+        };
+
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+
+        if paths.len() == MAX_PATHS {
+            break;
+        }
+    }
+
+    paths
+}
+
+fn looks_like_path(word: &str) -> bool {
+    word.len() <= MAX_PATH_LEN
+        && !word.starts_with('-')
+        && !word.contains("://")
+        && (word.contains('/') || word.starts_with('~') || word.starts_with('.'))
+}
+
+/// The literal arguments of each command in `command`, unquoted, leaving out command names
+///
+/// Words the shell would expand, with a `$`, glob or brace in them, aren't literal and are left
+/// out too.
+fn arguments(command: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut literal = true;
+    let mut command_name = true;
+    let mut quote = None;
+    let mut chars = command.chars();
+
+    let mut end_word = |word: &mut String, literal: &mut bool, command_name: &mut bool| {
+        if !*command_name && *literal {
+            arguments.push(std::mem::take(word));
+        }
+        word.clear();
+        *literal = true;
+        *command_name = false;
+    };
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.push(c),
+            (Some(_), '$') => {
+                literal = false;
+                word.push(c);
+            }
+            (_, '\\') => {
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+                in_word = true;
+            }
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, '|' | ';' | '&' | '\n') => {
+                if in_word {
+                    end_word(&mut word, &mut literal, &mut command_name);
+                    in_word = false;
+                }
+                command_name = true;
+            }
+            (None, c) if c.is_whitespace() || c == '<' || c == '>' => {
+                if in_word {
+                    end_word(&mut word, &mut literal, &mut command_name);
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                if matches!(c, '$' | '*' | '?' | '{' | '}' | '(' | ')' | '[' | ']') {
+                    literal = false;
+                }
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        end_word(&mut word, &mut literal, &mut command_name);
+    }
+
+    arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_paths() {
+        let cases: &[(&str, &[&str])] = &[
+            ("git status", &[]),
+            ("vim src/main.rs", &["/home/me/proj/src/main.rs"]),
+            (
+                "cat ./notes.txt ../up",
+                &["/home/me/proj/./notes.txt", "/home/me/proj/../up"],
+            ),
+            ("ls /etc ~/.config", &["/etc", "~/.config"]),
+            // command names, flags, urls and expansions aren't paths
+            ("./build.sh --out=dist/ -o x/y", &["/home/me/proj/x/y"]),
+            ("curl https://example.com/a", &[]),
+            ("rm src/*.rs $HOME/x {a,b}/c", &[]),
+            ("echo hi | ./filter.sh data/in", &["/home/me/proj/data/in"]),
+            // quotes and escapes are removed, as the shell would
+            (
+                "cp 'my dir/a b' \"c/d\" e\\ f/g",
+                &[
+                    "/home/me/proj/my dir/a b",
+                    "/home/me/proj/c/d",
+                    "/home/me/proj/e f/g",
+                ],
+            ),
+            (
+                "sort < in/data > out/data",
+                &["/home/me/proj/in/data", "/home/me/proj/out/data"],
+            ),
+            ("vim a/b a/b", &["/home/me/proj/a/b"]),
+        ];
+
+        for (command, expected) in cases {
+            assert_eq!(
+                command_paths(command, "/home/me/proj"),
+                *expected,
+                "{command}"
+            );
+        }
+
+        assert_eq!(command_paths("vim src/main.rs", "unknown"), ["src/main.rs"]);
+
+        let many: String = (0..40).map(|i| format!(" d/{i}")).collect();
+        assert_eq!(command_paths(&format!("ls{many}"), "/").len(), MAX_PATHS);
+    }
+}
@@ -0,0 +1,249 @@
+//! Noticing new entries in the fish history file by polling it
+//!
+//! Filesystem notifications don't arrive on some network filesystems and inside some
+//! containers, so anything reading entries back out of the fish history file can't rely on them.
+//! [`HistoryPoller`] only needs `stat`: it remembers how far into the file it has read, and
+//! parses just the appended bytes when the file grows. When fish rewrites the file (on `history
+//! merge` or `history save`), or it shrinks, everything is parsed again.
+
+use std::fs::{File, Metadata};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use eyre::{Context, Result};
+
+use super::entry::FishHistoryEntry;
+use super::syncer::split_entries;
+
+/// What changed since the last poll
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Polled {
+    /// Entries that weren't there before, oldest first
+    pub entries: Vec<FishHistoryEntry>,
+
+    /// The file was replaced or shrank, so `entries` is everything in it rather than only what's
+    /// new, and callers need to dedup against what they saw before
+    pub reset: bool,
+}
+
+/// Tracks how far into a fish history file has been read
+#[derive(Debug)]
+pub struct HistoryPoller {
+    path: PathBuf,
+
+    /// Where the first entry not yet returned starts
+    offset: u64,
+
+    /// What the file looked like when last read, to skip reading it when nothing changed
+    seen: Option<Snapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    len: u64,
+    modified: Option<SystemTime>,
+    identity: Option<(u64, u64)>,
+}
+
+impl Snapshot {
+    fn of(metadata: &Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            identity: identity(metadata),
+        }
+    }
+}
+
+impl HistoryPoller {
+    /// Start polling `path`. The first poll returns every entry already in the file.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            offset: 0,
+            seen: None,
+        }
+    }
+
+    /// Start polling `path`, ignoring the entries already in the file
+    pub fn from_end(path: impl Into<PathBuf>) -> Result<Self> {
+        let mut poller = Self::new(path);
+        poller.poll()?;
+
+        Ok(poller)
+    }
+
+    /// Check the file for changes
+    pub fn poll(&mut self) -> Result<Polled> {
+        let metadata = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // start over if it comes back
+                let reset = self.seen.take().is_some();
+                self.offset = 0;
+
+                return Ok(Polled {
+                    entries: Vec::new(),
+                    reset,
+                });
+            }
+            Err(e) => return Err(e).context("failed to stat fish history file"),
+        };
+
+        let snapshot = Snapshot::of(&metadata);
+
+        let reset = match &self.seen {
+            Some(seen) if *seen == snapshot => return Ok(Polled::default()),
+            Some(seen) => seen.identity != snapshot.identity || snapshot.len < self.offset,
+            None => false,
+        };
+
+        if reset {
+            self.offset = 0;
+        }
+
+        let mut file = File::open(&self.path).context("failed to open fish history file")?;
+        file.seek(SeekFrom::Start(self.offset))?;
+
+        let mut appended = String::new();
+        file.read_to_string(&mut appended)
+            .context("failed to read fish history file")?;
+
+        let (entries, consumed) = complete_entries(&appended);
+
+        self.offset += consumed as u64;
+        self.seen = Some(snapshot);
+
+        Ok(Polled { entries, reset })
+    }
+}
+
+/// Parse the entries in `appended`, returning them along with how many bytes they took up
+///
+/// An entry fish is still writing, recognised by a missing timestamp or trailing newline, is
+/// left for the next poll.
+fn complete_entries(appended: &str) -> (Vec<FishHistoryEntry>, usize) {
+    let (preamble, raw) = split_entries(appended);
+
+    let mut consumed = preamble.len();
+    let mut entries = Vec::with_capacity(raw.len());
+
+    for entry in raw {
+        if entry.when.is_none() || !entry.text.ends_with('\n') {
+            break;
+        }
+
+        consumed += entry.text.len();
+        entries.push(FishHistoryEntry::from(&entry));
+    }
+
+    (entries, consumed)
+}
+
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn append(path: &std::path::Path, text: &str) {
+        let mut file = fs_err::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    fn commands(polled: &Polled) -> Vec<&str> {
+        polled.entries.iter().map(|e| e.command.as_str()).collect()
+    }
+
+    #[test]
+    fn test_only_appended_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fish_history");
+        append(&path, "- cmd:one\n  when:1\n- cmd:two\n  when:2\n");
+
+        let mut poller = HistoryPoller::new(&path);
+        let polled = poller.poll().unwrap();
+        assert_eq!(commands(&polled), vec!["one", "two"]);
+        assert!(!polled.reset);
+
+        assert_eq!(poller.poll().unwrap(), Polled::default());
+
+        append(&path, "- cmd:three\n  when:3\n");
+        assert_eq!(commands(&poller.poll().unwrap()), vec!["three"]);
+    }
+
+    #[test]
+    fn test_partial_entry_waits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fish_history");
+        append(&path, "- cmd:one\n  when:1\n- cmd:two\n");
+
+        let mut poller = HistoryPoller::new(&path);
+        assert_eq!(commands(&poller.poll().unwrap()), vec!["one"]);
+
+        append(&path, "  when:2\n");
+        assert_eq!(commands(&poller.poll().unwrap()), vec!["two"]);
+    }
+
+    #[test]
+    fn test_shrink_or_replace_reparses() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fish_history");
+        append(&path, "- cmd:one\n  when:1\n- cmd:two\n  when:2\n");
+
+        let mut poller = HistoryPoller::from_end(&path).unwrap();
+        assert_eq!(poller.poll().unwrap(), Polled::default());
+
+        // fish dropping old entries
+        fs_err::write(&path, "- cmd:two\n  when:2\n").unwrap();
+        let polled = poller.poll().unwrap();
+        assert!(polled.reset);
+        assert_eq!(commands(&polled), vec!["two"]);
+
+        // fish saving through a new file, which is larger but mustn't be read from the old offset
+        let temp = dir.path().join("fish_history.tmp");
+        fs_err::write(&temp, "- cmd:zero\n  when:0\n- cmd:two\n  when:2\n").unwrap();
+        fs_err::rename(&temp, &path).unwrap();
+
+        let polled = poller.poll().unwrap();
+        if cfg!(unix) {
+            assert!(polled.reset);
+            assert_eq!(commands(&polled), vec!["zero", "two"]);
+        }
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fish_history");
+
+        let mut poller = HistoryPoller::new(&path);
+        assert_eq!(poller.poll().unwrap(), Polled::default());
+
+        append(&path, "- cmd:one\n  when:1\n");
+        assert_eq!(commands(&poller.poll().unwrap()), vec!["one"]);
+
+        fs_err::remove_file(&path).unwrap();
+        assert!(poller.poll().unwrap().reset);
+
+        append(&path, "- cmd:two\n  when:2\n");
+        assert_eq!(commands(&poller.poll().unwrap()), vec!["two"]);
+    }
+}
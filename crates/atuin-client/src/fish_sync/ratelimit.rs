@@ -0,0 +1,281 @@
+//! Rate limiting for fish history writes
+//!
+//! Every write locks, reads and appends to the fish history file, so a flood of them (a script
+//! running thousands of commands a minute, or a huge sync batch) turns into disk thrashing. Writes
+//! go through a token bucket instead, and entries that arrive while it's empty are held and
+//! written together in the next write, never dropped.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::syncer::{CommandEntry, FishSyncer};
+use crate::settings::Settings;
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket allowing `per_min` writes a minute, with bursts of up to `burst`
+    pub fn new(per_min: u32, burst: u32, now: Instant) -> Self {
+        let capacity = f64::from(burst.max(1));
+
+        Self {
+            capacity,
+            refill_per_sec: f64::from(per_min) / 60.0,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Take a token if one is available
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Buckets outlive a single batch, so a long running process like the daemon shares one budget
+/// across all of its batches
+static BUCKETS: Mutex<BTreeMap<PathBuf, TokenBucket>> = Mutex::new(BTreeMap::new());
+
+/// Writes a batch of entries, one write per token, coalescing whatever the bucket holds back
+pub(crate) struct LimitedWriter {
+    syncer: FishSyncer,
+    bucket: Option<TokenBucket>,
+    pending: Vec<CommandEntry>,
+
+    /// Entries written to the file
+    pub written: u64,
+
+    /// Entries that were already in the file
+    pub duplicates: u64,
+
+    /// Entries older than everything in an already full file
+    pub too_old: u64,
+
+    /// Entries held back by the limit, to be written with a later entry
+    pub deferred: u64,
+
+    /// Entries in a write that failed
+    pub errors: u64,
+
+    /// The ids of the entries written, for the audit
+    pub written_ids: Vec<String>,
+}
+
+impl LimitedWriter {
+    pub fn new(syncer: FishSyncer, settings: &Settings) -> Self {
+        let per_min = settings.fish_sync.rate_limit_per_min;
+
+        let bucket = (per_min > 0).then(|| {
+            BUCKETS
+                .lock()
+                .expect("rate limit lock poisoned")
+                .remove(syncer.path())
+                .unwrap_or_else(|| {
+                    TokenBucket::new(per_min, settings.fish_sync.rate_limit_burst, Instant::now())
+                })
+        });
+
+        Self::with_bucket(syncer, bucket)
+    }
+
+    fn with_bucket(syncer: FishSyncer, bucket: Option<TokenBucket>) -> Self {
+        Self {
+            syncer,
+            bucket,
+            pending: Vec::new(),
+            written: 0,
+            duplicates: 0,
+            too_old: 0,
+            deferred: 0,
+            errors: 0,
+            written_ids: Vec::new(),
+        }
+    }
+
+    /// Write `entries` in one append, or hold them for the next write if the bucket is empty
+    ///
+    /// A batch takes one token however many entries it has, since it's one lock, read and append
+    /// of the file either way.
+    pub fn push_batch(&mut self, entries: Vec<CommandEntry>, now: Instant) {
+        if entries.is_empty() {
+            return;
+        }
+
+        let count = entries.len() as u64;
+        self.pending.extend(entries);
+
+        let allowed = self
+            .bucket
+            .as_mut()
+            .is_none_or(|bucket| bucket.try_take(now));
+
+        if allowed {
+            self.flush();
+        } else {
+            self.deferred += count;
+        }
+    }
+
+    /// Write anything still held back, and hand the bucket back for the next batch
+    pub fn finish(mut self) -> Self {
+        self.flush();
+
+        if let Some(bucket) = self.bucket.take() {
+            BUCKETS
+                .lock()
+                .expect("rate limit lock poisoned")
+                .insert(self.syncer.path().to_path_buf(), bucket);
+        }
+
+        self
+    }
+
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let batch = std::mem::take(&mut self.pending);
+        let attempted = batch.len() as u64;
+
+        let written_ids = &mut self.written_ids;
+        match self
+            .syncer
+            .append_inspecting(&batch, |entry| written_ids.extend(entry.uuid.clone()))
+        {
+            Ok(report) => {
+                self.written += report.written as u64;
+                self.duplicates += report.duplicates as u64;
+                self.too_old += report.too_old as u64;
+            }
+            Err(e) => {
+                log::warn!("error={e}: failed to write {attempted} entries to fish history");
+                self.errors += attempted;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::fish_sync::FishSyncOptions;
+
+    #[test]
+    fn test_burst_is_capped() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(60, 120, now);
+
+        let allowed = (0..1000).filter(|_| bucket.try_take(now)).count();
+        assert_eq!(allowed, 120);
+
+        // one token a second after that
+        assert!(!bucket.try_take(now + Duration::from_millis(500)));
+        assert!(bucket.try_take(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_normal_cadence_never_limited() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(60, 120, start);
+
+        // a command every couple of seconds, for an hour
+        for i in 0..1800 {
+            assert!(bucket.try_take(start + Duration::from_secs(i * 2)));
+        }
+    }
+
+    #[test]
+    fn test_refill_is_capped_at_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(60, 5, start);
+
+        let later = start + Duration::from_secs(3600);
+        let allowed = (0..100).filter(|_| bucket.try_take(later)).count();
+        assert_eq!(allowed, 5);
+    }
+
+    #[test]
+    fn test_writer_coalesces_instead_of_dropping() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer =
+            FishSyncer::open(dir.path().join("fish_history"), FishSyncOptions::default()).unwrap();
+
+        let now = Instant::now();
+        let mut writer = LimitedWriter::with_bucket(syncer, Some(TokenBucket::new(60, 3, now)));
+
+        for i in 0..10 {
+            let entry = CommandEntry::new(
+                format!("cmd {i}"),
+                OffsetDateTime::from_unix_timestamp(i).unwrap(),
+            );
+            writer.push_batch(vec![entry], now);
+        }
+
+        // three writes went straight through, the rest waited
+        assert_eq!(writer.written, 3);
+        assert_eq!(writer.deferred, 7);
+
+        let writer = writer.finish();
+        assert_eq!(writer.written, 10);
+        assert_eq!(writer.errors, 0);
+        assert_eq!(
+            crate::fish_sync::count_entries(writer.syncer.path()).unwrap(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_batch_takes_one_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer =
+            FishSyncer::open(dir.path().join("fish_history"), FishSyncOptions::default()).unwrap();
+
+        let now = Instant::now();
+        let mut writer = LimitedWriter::with_bucket(syncer, Some(TokenBucket::new(60, 1, now)));
+
+        let batch = |start: i64| {
+            (start..start + 100)
+                .map(|i| {
+                    CommandEntry::new(
+                        format!("cmd {i}"),
+                        OffsetDateTime::from_unix_timestamp(i).unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+        // the whole first batch is written with the one token, the second waits for a later write
+        writer.push_batch(batch(0), now);
+        assert_eq!(writer.written, 100);
+
+        writer.push_batch(batch(100), now);
+        assert_eq!(writer.written, 100);
+        assert_eq!(writer.deferred, 100);
+
+        assert_eq!(writer.finish().written, 200);
+    }
+}
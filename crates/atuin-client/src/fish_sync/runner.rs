@@ -0,0 +1,210 @@
+//! Running fish
+//!
+//! Fish sync asks fish where its history lives and how much it keeps, and runs it to notify
+//! sessions. Everything that does goes through [`FishRunner`], so it can be tested without fish
+//! installed, and so a hung fish times out rather than blocking the daemon.
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use eyre::{Context, Result, bail};
+
+/// How long a fish invocation gets, unless it says otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a finished fish process left behind
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FishOutput {
+    /// The exit code, or `None` if fish was killed by a signal
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl FishOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Fail unless fish exited successfully, returning its stdout if it did
+    pub fn into_stdout(self) -> Result<String> {
+        if !self.success() {
+            let code = self
+                .code
+                .map_or_else(|| "a signal".to_string(), |code| code.to_string());
+            bail!("fish exited with {code}: {}", self.stderr.trim());
+        }
+
+        Ok(self.stdout)
+    }
+}
+
+/// Runs fish with some arguments
+pub trait FishRunner: Send + Sync {
+    /// Run fish with `args`, killing it if it takes longer than `timeout`
+    ///
+    /// Fails if fish can't be started or times out. A non-zero exit is an `Ok` output; see
+    /// [`FishOutput::into_stdout`].
+    fn run(&self, args: &[&str], timeout: Duration) -> Result<FishOutput>;
+}
+
+/// The fish on `$PATH`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemFish;
+
+impl FishRunner for SystemFish {
+    fn run(&self, args: &[&str], timeout: Duration) -> Result<FishOutput> {
+        run_with_timeout(Command::new("fish").args(args), timeout)
+    }
+}
+
+fn run_with_timeout(command: &mut Command, timeout: Duration) -> Result<FishOutput> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run fish")?;
+
+    // read both pipes while waiting, so a chatty process can't fill one and stall
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut out = String::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_string(&mut out);
+            }
+            out
+        })
+    };
+    let stdout = read(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = read(child.stderr.take().map(|p| Box::new(p) as _));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().context("failed to wait for fish")? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("fish did not finish within {}s", timeout.as_secs_f32());
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    Ok(FishOutput {
+        code: status.code(),
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Fish's version, e.g. `(3, 7, 1)`, from the output of `fish --version`
+pub fn parse_fish_version(output: &str) -> Option<(u32, u32, u32)> {
+    // "fish, version 3.7.1", or "fish, version 4.0b1-123-gabc" for a development build
+    let version = output.trim().rsplit(' ').next()?;
+
+    let mut parts = version.split('.').map(|part| {
+        let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+        digits.parse::<u32>().ok()
+    });
+
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+
+    Some((major, minor, patch))
+}
+
+/// The feature flag fish is expected to turn on once it has its own way to add history from
+/// elsewhere, which Atuin would use instead of writing the history file
+pub const NATIVE_HISTORY_FEATURE: &str = "history-api";
+
+/// Whether `status features` lists `feature`, and turned on
+///
+/// Each line is a feature's name, then `on` or `off`, then when it was added and what it does.
+pub fn has_fish_feature(output: &str, feature: &str) -> bool {
+    output.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        fields.next() == Some(feature) && fields.next() == Some("on")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fish_version() {
+        assert_eq!(parse_fish_version("fish, version 3.7.1\n"), Some((3, 7, 1)));
+        assert_eq!(parse_fish_version("fish, version 4.0.0"), Some((4, 0, 0)));
+        assert_eq!(parse_fish_version("fish, version 3.6"), Some((3, 6, 0)));
+        assert_eq!(
+            parse_fish_version("fish, version 4.0b1-123-gabcdef"),
+            Some((4, 0, 0))
+        );
+        assert_eq!(parse_fish_version(""), None);
+        assert_eq!(parse_fish_version("fish, version unknown"), None);
+    }
+
+    #[test]
+    fn test_has_fish_feature() {
+        let output = "stderr-nocaret          on  3.0 ^ no longer redirects stderr\n\
+                      history-api             off 5.0 native history\n\
+                      history-api-v2          on  5.1 native history\n";
+
+        assert!(has_fish_feature(output, "stderr-nocaret"));
+        assert!(!has_fish_feature(output, "history-api"));
+        assert!(!has_fish_feature(output, "qmark-noglob"));
+        assert!(!has_fish_feature("", NATIVE_HISTORY_FEATURE));
+    }
+
+    #[test]
+    fn test_into_stdout() {
+        let ok = FishOutput {
+            code: Some(0),
+            stdout: "out".to_string(),
+            stderr: String::new(),
+        };
+        assert_eq!(ok.into_stdout().unwrap(), "out");
+
+        let failed = FishOutput {
+            code: Some(127),
+            stdout: String::new(),
+            stderr: "history: unknown option\n".to_string(),
+        };
+        let error = failed.into_stdout().unwrap_err().to_string();
+        assert_eq!(error, "fish exited with 127: history: unknown option");
+
+        let killed = FishOutput::default();
+        assert!(
+            killed
+                .into_stdout()
+                .unwrap_err()
+                .to_string()
+                .contains("a signal")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_kills_the_process() {
+        let start = Instant::now();
+        let result = run_with_timeout(
+            Command::new("sh").args(["-c", "sleep 10"]),
+            Duration::from_millis(100),
+        );
+
+        assert!(result.unwrap_err().to_string().contains("did not finish"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let output =
+            run_with_timeout(Command::new("sh").args(["-c", "echo hi"]), DEFAULT_TIMEOUT).unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, "hi\n");
+    }
+}
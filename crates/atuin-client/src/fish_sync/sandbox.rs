@@ -0,0 +1,82 @@
+//! Keeping tests and benches away from the developer's real fish history
+//!
+//! Tests build settings from [`Settings::default`](crate::settings::Settings::default) and point
+//! `fish_sync.history_path` at a temporary file. One that forgets to would append to the real
+//! `~/.local/share/fish/fish_history`. In this crate's own tests, or with
+//! `ATUIN_SHELL_SYNC_SANDBOX` set, writing anywhere outside the sandbox root panics instead.
+
+use std::path::{Component, Path, PathBuf};
+
+use super::canonicalize_lenient;
+
+/// Set to sandbox fish sync writes, to a directory to use as the root instead of the temp dir
+pub const SANDBOX_ENV: &str = "ATUIN_SHELL_SYNC_SANDBOX";
+
+/// The directory fish sync writes are confined to, if they are
+pub fn sandbox_root() -> Option<PathBuf> {
+    let var = std::env::var_os(SANDBOX_ENV).filter(|var| !var.is_empty());
+
+    if var.is_none() && !cfg!(test) {
+        return None;
+    }
+
+    Some(
+        var.map(PathBuf::from)
+            .filter(|root| root.is_absolute())
+            .unwrap_or_else(std::env::temp_dir),
+    )
+}
+
+/// Panic if fish sync is sandboxed and `path` is outside the sandbox root
+pub fn guard(path: &Path) {
+    let Some(root) = sandbox_root() else {
+        return;
+    };
+
+    if !within(path, &root) {
+        panic!(
+            "fish sync is sandboxed to {} ({SANDBOX_ENV} is set, or this is a test), but was about to write {}. Point fish_sync.history_path into a temporary directory, for example with test_support::fish_settings",
+            root.display(),
+            path.display()
+        );
+    }
+}
+
+fn within(path: &Path, root: &Path) -> bool {
+    path.is_absolute()
+        && !path
+            .components()
+            .any(|component| component == Component::ParentDir)
+        && (path.starts_with(root)
+            || canonicalize_lenient(path).starts_with(canonicalize_lenient(root)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_paths_are_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+
+        guard(&dir.path().join("fish_history"));
+        guard(&dir.path().join("missing").join("fish_history"));
+    }
+
+    #[test]
+    #[should_panic(expected = "fish sync is sandboxed")]
+    fn test_home_path_trips_the_guard() {
+        guard(&atuin_common::utils::home_dir().join(".local/share/fish/fish_history"));
+    }
+
+    #[test]
+    fn test_within() {
+        let root = Path::new("/sandbox");
+
+        assert!(within(Path::new("/sandbox/fish_history"), root));
+        assert!(!within(Path::new("/sandboxed/fish_history"), root));
+        assert!(!within(Path::new("/home/user/fish_history"), root));
+        assert!(!within(Path::new("fish_history"), root));
+        assert!(!within(Path::new("/sandbox/../home/fish_history"), root));
+    }
+}
@@ -0,0 +1,72 @@
+//! One view of the fish history file for everything a single command does to it
+//!
+//! A sync can write downloaded entries, seed the file and trim it, all in one run. Done
+//! separately, each of those parses the whole file again, and every append trims it. A
+//! [`ShellSyncSession`] parses the file once, keeps its index up to date as entries are appended,
+//! and trims once when it's finished.
+
+use std::sync::Mutex;
+
+use eyre::Result;
+
+use super::audit::WriteSource;
+use super::meta::FishSyncMeta;
+use super::syncer::{FishSyncer, TrimReport};
+use super::{resolve_writable_history_path, writer_options};
+use crate::settings::Settings;
+
+#[derive(Debug, Default)]
+pub struct ShellSyncSession {
+    /// Opened on first use, so a session that never writes never touches the file
+    syncer: Mutex<Option<FishSyncer>>,
+}
+
+impl ShellSyncSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A writer on the configured fish history file that shares the session's index
+    pub(crate) fn writer(&self, settings: &Settings, source: WriteSource) -> Result<FishSyncer> {
+        let mut syncer = self.syncer.lock().expect("fish session lock poisoned");
+
+        if let Some(syncer) = syncer.as_ref() {
+            return Ok(syncer.with_audit_source(writer_options(settings, source).audit_source));
+        }
+
+        let path = resolve_writable_history_path(settings)?;
+
+        // so the state can be brought up to date after each write without reading the file back
+        if let Ok(meta) = FishSyncMeta::load(&FishSyncMeta::path(settings)) {
+            meta.share_contents(&path);
+        }
+
+        let opened = FishSyncer::open(path, writer_options(settings, source))?.in_session();
+
+        Ok(syncer.insert(opened).clone())
+    }
+
+    /// How many times the session has parsed the whole file so far
+    pub fn parses(&self) -> usize {
+        self.syncer
+            .lock()
+            .expect("fish session lock poisoned")
+            .as_ref()
+            .map_or(0, FishSyncer::parses)
+    }
+
+    /// Trim the file to the configured limits, once for everything written in the session
+    ///
+    /// A session that never wrote leaves the file alone. Otherwise the next session starts from
+    /// this one's index, rather than parsing the file again, unless the file changes in between.
+    pub fn finish(self) -> Result<TrimReport> {
+        match self
+            .syncer
+            .into_inner()
+            .expect("fish session lock poisoned")
+        {
+            Some(syncer) => syncer.finish_session(),
+            None => Ok(TrimReport::default()),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! A record of what Atuin wrote to a fish history file, kept beside it
+//!
+//! Entries Atuin writes carry an `# atuin-uuid:` comment, but fish drops comments it doesn't know
+//! whenever it rewrites its history, as `history merge` does. When it does, it also drops older
+//! copies of a command that was run again since, so neither the comment nor the command and
+//! timestamp are left to tell an entry was already synced, and the next bootstrap would write it
+//! again. The sidecar remembers each written entry's id and a hash of its command and timestamp
+//! on its own, so dedup doesn't depend on fish keeping any of it.
+//!
+//! Trimming the history file prunes the sidecar to match, so it never outgrows the file.
+
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use super::meta::hash_contents;
+use super::syncer::CommandEntry;
+use super::versioned::{ATUIN_VERSION, Format, NewerFormat, write_atomic};
+use crate::history::canonical_id;
+
+const SIDECAR_FORMAT: Format = Format {
+    name: "fish sync sidecar",
+    current: 1,
+    migrations: &[],
+};
+
+/// The sidecar for the fish history file at `history`
+pub(crate) fn path_for(history: &Path) -> PathBuf {
+    let name = history.file_name().unwrap_or_default().to_string_lossy();
+    history.with_file_name(format!("{name}.atuin-state"))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct SidecarFile {
+    version: u32,
+    written_by: Option<String>,
+    entries: Vec<SyncedEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SyncedEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+
+    /// [`hash_contents`] of the command, so the sidecar doesn't keep a second copy of it
+    command: String,
+
+    when: i64,
+}
+
+impl SyncedEntry {
+    fn of(entry: &CommandEntry) -> Self {
+        Self {
+            id: entry.uuid.as_deref().map(canonical_id),
+            command: hash_contents(entry.command.as_bytes()),
+            when: entry.timestamp.unix_timestamp(),
+        }
+    }
+}
+
+/// The entries Atuin wrote to one fish history file
+#[derive(Debug, Default)]
+pub(crate) struct Sidecar {
+    path: PathBuf,
+    file: SidecarFile,
+    ids: HashSet<String>,
+    commands: HashSet<(String, i64)>,
+
+    /// Whether there's anything to save
+    changed: bool,
+}
+
+impl Sidecar {
+    /// Read the sidecar for the fish history file at `history`
+    ///
+    /// A missing or unreadable sidecar reads as empty, which only costs the protection it gave.
+    /// One from a newer Atuin is an error rather than being written over.
+    pub(crate) fn load(history: &Path) -> Result<Self> {
+        let path = path_for(history);
+
+        let file = match fs_err::read_to_string(&path) {
+            Ok(contents) => match SIDECAR_FORMAT.load(&path, &contents) {
+                Ok(file) => file,
+                Err(e) if e.is::<NewerFormat>() => return Err(e),
+                Err(e) => {
+                    log::warn!("ignoring unreadable {}: {e:#}", path.display());
+                    SidecarFile::default()
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => SidecarFile::default(),
+            Err(e) => {
+                log::warn!("ignoring unreadable {}: {e}", path.display());
+                SidecarFile::default()
+            }
+        };
+
+        let mut sidecar = Self {
+            path,
+            ..Self::default()
+        };
+        for entry in file.entries {
+            sidecar.insert(entry);
+        }
+        sidecar.changed = false;
+
+        Ok(sidecar)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.file.entries.len()
+    }
+
+    /// Whether Atuin wrote this entry, by id or by command and timestamp
+    pub(crate) fn contains(&self, entry: &CommandEntry) -> bool {
+        let synced = SyncedEntry::of(entry);
+
+        synced.id.as_ref().is_some_and(|id| self.ids.contains(id))
+            || self.commands.contains(&(synced.command, synced.when))
+    }
+
+    pub(crate) fn record(&mut self, entry: &CommandEntry) {
+        self.insert(SyncedEntry::of(entry));
+    }
+
+    fn insert(&mut self, entry: SyncedEntry) {
+        if let Some(id) = &entry.id {
+            self.ids.insert(id.clone());
+        }
+        self.commands.insert((entry.command.clone(), entry.when));
+        self.file.entries.push(entry);
+        self.changed = true;
+    }
+
+    /// Forget everything, for a history file that was started over
+    pub(crate) fn clear(&mut self) {
+        if self.file.entries.is_empty() {
+            return;
+        }
+
+        self.file.entries.clear();
+        self.ids.clear();
+        self.commands.clear();
+        self.changed = true;
+    }
+
+    /// Forget entries older than `oldest`, which a trim has removed from the file, returning how
+    /// many were forgotten
+    pub(crate) fn prune_before(&mut self, oldest: i64) -> usize {
+        let before = self.file.entries.len();
+        let changed = self.changed;
+        let entries = std::mem::take(&mut self.file.entries);
+
+        self.ids.clear();
+        self.commands.clear();
+        for entry in entries.into_iter().filter(|entry| entry.when >= oldest) {
+            self.insert(entry);
+        }
+
+        let pruned = before - self.file.entries.len();
+        self.changed = changed || pruned > 0;
+        pruned
+    }
+
+    /// Write the sidecar out, if anything changed since it was read
+    pub(crate) fn save(&mut self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        SIDECAR_FORMAT.ensure_writable(&self.path)?;
+
+        self.file.version = SIDECAR_FORMAT.current;
+        self.file.written_by = Some(ATUIN_VERSION.to_string());
+        write_atomic(&self.path, &serde_json::to_string(&self.file)?)?;
+        self.changed = false;
+
+        Ok(())
+    }
+}
+
+/// Forget what a trim removed from the history file at `history`, whose oldest entry is now
+/// `oldest`, if it has any left
+///
+/// The trim already happened, so failing here only leaves the sidecar larger than it needs to
+/// be, and is logged rather than returned.
+pub(crate) fn prune(history: &Path, oldest: Option<i64>) {
+    let result = Sidecar::load(history).and_then(|mut sidecar| {
+        match oldest {
+            Some(oldest) => {
+                sidecar.prune_before(oldest);
+            }
+            None => sidecar.clear(),
+        }
+        sidecar.save()
+    });
+
+    if let Err(e) = result {
+        log::warn!("failed to prune {}: {e:#}", path_for(history).display());
+    }
+}
+
+/// Forget everything written to the history file at `history`, once Atuin's entries are gone
+/// from it, so they can be written again
+pub(crate) fn clear(history: &Path) -> Result<()> {
+    let mut sidecar = Sidecar::load(history)?;
+    sidecar.clear();
+    sidecar.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    fn entry(command: &str, when: i64) -> CommandEntry {
+        CommandEntry::new(command, OffsetDateTime::from_unix_timestamp(when).unwrap())
+    }
+
+    #[test]
+    fn test_round_trip_and_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = dir.path().join("fish_history");
+
+        let mut sidecar = Sidecar::load(&history).unwrap();
+        sidecar.record(&entry("ls", 1).with_uuid("a"));
+        sidecar.record(&entry("pwd", 2));
+        sidecar.record(&entry("echo hi", 3).with_uuid("c"));
+        sidecar.save().unwrap();
+
+        let contents = fs_err::read_to_string(path_for(&history)).unwrap();
+        assert!(contents.contains(r#""version":1"#));
+        assert!(!contents.contains("echo hi"));
+
+        let sidecar = Sidecar::load(&history).unwrap();
+        assert!(sidecar.contains(&entry("something else", 9).with_uuid("a")));
+        assert!(sidecar.contains(&entry("pwd", 2)));
+        assert!(!sidecar.contains(&entry("pwd", 3)));
+
+        prune(&history, Some(2));
+        let sidecar = Sidecar::load(&history).unwrap();
+        assert_eq!(sidecar.len(), 2);
+        assert!(!sidecar.contains(&entry("ls", 1).with_uuid("a")));
+        assert!(sidecar.contains(&entry("echo hi", 3)));
+
+        prune(&history, None);
+        assert_eq!(Sidecar::load(&history).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_unreadable_sidecar_reads_as_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = dir.path().join("fish_history");
+
+        fs_err::write(path_for(&history), "torn").unwrap();
+        assert_eq!(Sidecar::load(&history).unwrap().len(), 0);
+
+        fs_err::write(path_for(&history), r#"{"version":2,"entries":[]}"#).unwrap();
+        assert!(Sidecar::load(&history).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! What a sync wrote to a shell's history file
+//!
+//! The sync command, the daemon's logs and JSON output all report the same numbers, so they all
+//! render a [`SyncSummary`] rather than formatting their own.
+
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Serialize, Serializer};
+use time::OffsetDateTime;
+
+use super::filter::{FilterCounts, FilterRule};
+use super::metrics::TargetMetrics;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SyncSummary {
+    /// The shell whose history file was written
+    pub target: String,
+
+    /// Entries written
+    pub written: u64,
+
+    /// Entries that were already in the file
+    pub duplicates: u64,
+
+    /// Entries kept out, by the rule that kept them out
+    pub filtered: FilterCounts,
+
+    /// Entries held back by the write rate limit and written together in a later write
+    pub deferred: u64,
+
+    /// Entries that failed to write
+    pub errors: u64,
+
+    #[serde(rename = "duration_ms", serialize_with = "as_millis")]
+    pub duration: Duration,
+
+    /// How much the history file grew, in bytes. Negative if a trim shrank it.
+    pub size_delta: i64,
+}
+
+impl SyncSummary {
+    pub fn new(target: &str) -> Self {
+        Self {
+            target: target.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// A single line of JSON, with fields in declaration order
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("sync summaries always serialize")
+    }
+
+    /// A compact, single line form for logs
+    pub fn log_line(&self) -> String {
+        let mut line = format!(
+            "target={} written={} duplicates={} filtered={}",
+            self.target,
+            self.written,
+            self.duplicates,
+            self.filtered.total()
+        );
+
+        for (rule, count) in self.filtered.iter() {
+            line.push_str(&format!(" filtered.{rule}={count}"));
+        }
+
+        line.push_str(&format!(
+            " deferred={} errors={} duration_ms={} size_delta={:+}",
+            self.deferred,
+            self.errors,
+            self.duration.as_millis(),
+            self.size_delta
+        ));
+
+        line
+    }
+
+    /// Running totals for status reporting, counting this as a success if nothing failed
+    pub fn to_metrics(&self, now: OffsetDateTime) -> TargetMetrics {
+        let too_old = self
+            .filtered
+            .iter()
+            .find(|(rule, _)| **rule == FilterRule::too_old())
+            .map_or(0, |(_, count)| count);
+
+        TargetMetrics {
+            written: self.written,
+            skipped: self.duplicates + self.filtered.total(),
+            too_old,
+            deferred: self.deferred,
+            errors: self.errors,
+            last_success: (self.errors == 0).then_some(now),
+        }
+    }
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} written, {} duplicates, {} filtered",
+            self.target,
+            self.written,
+            self.duplicates,
+            self.filtered.total()
+        )?;
+
+        if !self.filtered.is_empty() {
+            let reasons: Vec<_> = self
+                .filtered
+                .iter()
+                .map(|(rule, count)| format!("{rule} {count}"))
+                .collect();
+            write!(f, " ({})", reasons.join(", "))?;
+        }
+
+        write!(
+            f,
+            ", {} deferred, {} errors in {}ms, file size {:+} bytes",
+            self.deferred,
+            self.errors,
+            self.duration.as_millis(),
+            self.size_delta
+        )
+    }
+}
+
+fn as_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> SyncSummary {
+        let mut filtered = FilterCounts::default();
+        filtered.add(&FilterRule::new("secrets", "AWS Access Key ID"), 2);
+        filtered.add(&FilterRule::too_old(), 1);
+
+        SyncSummary {
+            written: 12,
+            duplicates: 3,
+            filtered,
+            deferred: 4,
+            errors: 0,
+            duration: Duration::from_millis(41),
+            size_delta: 1234,
+            ..SyncSummary::new("fish")
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            summary().to_string(),
+            "fish: 12 written, 3 duplicates, 3 filtered (secrets:aws-access-key-id 2, \
+             window:too-old 1), 4 deferred, 0 errors in 41ms, file size +1234 bytes"
+        );
+
+        assert_eq!(
+            SyncSummary::new("fish").to_string(),
+            "fish: 0 written, 0 duplicates, 0 filtered, 0 deferred, 0 errors in 0ms, \
+             file size +0 bytes"
+        );
+    }
+
+    #[test]
+    fn test_log_line() {
+        assert_eq!(
+            summary().log_line(),
+            "target=fish written=12 duplicates=3 filtered=3 \
+             filtered.secrets:aws-access-key-id=2 filtered.window:too-old=1 deferred=4 \
+             errors=0 duration_ms=41 size_delta=+1234"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            summary().to_json(),
+            r#"{"target":"fish","written":12,"duplicates":3,"filtered":{"secrets:aws-access-key-id":2,"window:too-old":1},"deferred":4,"errors":0,"duration_ms":41,"size_delta":1234}"#
+        );
+    }
+
+    #[test]
+    fn test_to_metrics() {
+        let now = OffsetDateTime::now_utc();
+
+        let metrics = summary().to_metrics(now);
+        assert_eq!(metrics.written, 12);
+        assert_eq!(metrics.skipped, 6);
+        assert_eq!(metrics.too_old, 1);
+        assert_eq!(metrics.deferred, 4);
+        assert_eq!(metrics.last_success, Some(now));
+
+        let failed = SyncSummary {
+            errors: 1,
+            ..summary()
+        };
+        assert_eq!(failed.to_metrics(now).last_success, None);
+    }
+}
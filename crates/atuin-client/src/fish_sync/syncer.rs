@@ -0,0 +1,3274 @@
+//! A small, semi-stable API for writing commands into a fish history file
+//!
+//! This is the same writer Atuin uses for its own fish sync, exposed so other tools can push
+//! commands into fish history without reimplementing the locking, dedup and trim logic.
+//!
+//! The public surface is intentionally small: [`FishSyncer`], [`FishSyncOptions`] and
+//! [`CommandEntry`]. How the file is indexed internally may change between releases, but these
+//! types and their methods will only change with a deprecation period.
+//!
+//! ```
+//! use atuin_client::fish_sync::{CommandEntry, FishSyncOptions, FishSyncer};
+//! use time::OffsetDateTime;
+//!
+//! # fn main() -> eyre::Result<()> {
+//! # let dir = tempfile::tempdir()?;
+//! # let path = dir.path().join("fish_history");
+//! let syncer = FishSyncer::open(&path, FishSyncOptions::default())?;
+//!
+//! let entry = CommandEntry::new("cargo build", OffsetDateTime::now_utc())
+//!     .with_uuid("0190b1a2-7c4e-7000-8000-000000000001");
+//!
+//! assert_eq!(syncer.append(&[entry.clone()])?, 1);
+//!
+//! // appending the same entry again is a no-op
+//! assert_eq!(syncer.append(&[entry])?, 0);
+//! assert!(syncer.contains("0190b1a2-7c4e-7000-8000-000000000001")?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use eyre::{Context, Result};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::audit::WriteSource;
+use super::entry::{FishHistoryEntry, ParseIssue, ParseIssueKind};
+use super::format::{
+    ATUIN_SRC_KEY, ATUIN_UUID_KEY, MAX_COMMENT_LINE, MAX_METADATA_VALUE, is_comment_line,
+    is_valid_id, metadata_line, parse_metadata_line, parse_uuid_line,
+};
+use super::journal::{self, Journal, JournalEntry, Operation};
+use super::meta::{ContentSummary, hash_contents};
+use super::sidecar::{self, Sidecar};
+use crate::history::{History, canonical_id};
+
+/// A single command to write to fish history
+///
+/// Unlike [`History`], this only carries what fish itself stores, plus an optional id used to
+/// recognise entries that were already written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandEntry {
+    pub command: String,
+    pub timestamp: OffsetDateTime,
+    pub uuid: Option<String>,
+
+    /// Written as the entry's `paths:` field, which fish checks before suggesting it
+    pub paths: Vec<String>,
+}
+
+impl CommandEntry {
+    pub fn new(command: impl Into<String>, timestamp: OffsetDateTime) -> Self {
+        Self {
+            command: command.into(),
+            timestamp,
+            uuid: None,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Attach an id, so the entry can be found with [`FishSyncer::contains`]
+    ///
+    /// Uuids are written as 32 hex digits, however they're given, so either spelling finds it.
+    pub fn with_uuid(mut self, uuid: impl Into<String>) -> Self {
+        self.uuid = Some(canonical_id(&uuid.into()));
+        self
+    }
+
+    pub fn with_paths(mut self, paths: Vec<String>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Render the entry in fish's history file format
+    ///
+    /// ```text
+    /// - cmd:vim src/main.rs
+    ///   when:1737097200
+    ///   paths:
+    ///     - /home/me/proj/src/main.rs
+    ///   # atuin-uuid:0190b1a27c4e70008000000000000001
+    /// ```
+    pub(crate) fn to_fish(&self) -> String {
+        let mut entry = format!(
+            "- cmd:{}\n  when:{}\n",
+            escape_fish_cmd(&self.command),
+            self.timestamp.unix_timestamp()
+        );
+
+        if !self.paths.is_empty() {
+            entry.push_str("  paths:\n");
+            for path in &self.paths {
+                entry.push_str(&format!("    - {}\n", escape_fish_cmd(path)));
+            }
+        }
+
+        match &self.uuid {
+            Some(uuid) if is_valid_id(uuid) => {
+                entry.push_str(&metadata_line(ATUIN_UUID_KEY, uuid));
+            }
+            Some(uuid) => log::warn!(
+                "not tagging fish history entry with malformed id {:?}",
+                uuid.chars().take(MAX_METADATA_VALUE).collect::<String>()
+            ),
+            None => {}
+        }
+
+        entry
+    }
+}
+
+impl From<&History> for CommandEntry {
+    fn from(history: &History) -> Self {
+        Self {
+            command: history.command.clone(),
+            timestamp: history.timestamp,
+            uuid: Some(history.id.canonical()),
+            paths: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FishSyncOptions {
+    /// Trim the file down to this many entries after every append. 0 means no limit.
+    pub max_entries: usize,
+
+    /// Let the file hold this many entries past `max_entries` before trimming it back down
+    pub trim_slack: usize,
+
+    /// With `max_entries` set, read only the end of the file to append, as far back as a trim
+    /// could keep, rather than all of it. A trim then works from that end alone too.
+    pub dedup_from_tail: bool,
+
+    /// Once the file holds `max_entries`, skip entries older than anything already in it
+    pub skip_older_than_window: bool,
+
+    /// Trim the file down to this many bytes after every append
+    pub max_bytes: Option<u64>,
+
+    /// Tag every written entry with what wrote it
+    pub audit_source: Option<WriteSource>,
+
+    /// Put the entries back in time order whenever the file is trimmed, which then happens after
+    /// every write
+    pub keep_sorted: bool,
+
+    /// Where to record appends, trims and cleans. Other rewrites are recorded by whatever asked
+    /// for them, as only the caller knows what they were for.
+    pub journal: Option<Journal>,
+}
+
+impl FishSyncOptions {
+    fn trim_limits(&self) -> Option<TrimLimits> {
+        if self.max_entries == 0 && self.max_bytes.is_none() {
+            return None;
+        }
+
+        Some(TrimLimits {
+            max_entries: (self.max_entries > 0).then_some(self.max_entries),
+            max_bytes: self.max_bytes,
+            ..TrimLimits::default()
+        })
+    }
+
+    /// The limits to trim to after writing, if there's any reason to trim: no limits at all, just
+    /// to sort, with `keep_sorted`
+    fn trim_after_write_limits(&self) -> Option<TrimLimits> {
+        self.trim_limits()
+            .or_else(|| self.keep_sorted.then(TrimLimits::default))
+    }
+
+    /// How many entries from the end of the file an append needs to read, if not all of them
+    ///
+    /// One more than a file can hold before it's trimmed: if the file has more than that, a trim
+    /// keeps only entries from within them.
+    fn tail_entries(&self) -> Option<usize> {
+        (self.dedup_from_tail && self.max_entries > 0).then(|| {
+            self.max_entries
+                .saturating_add(self.trim_slack)
+                .saturating_add(1)
+        })
+    }
+
+    /// Whether a file of `entries` entries and `bytes` bytes is due a trim after a write
+    ///
+    /// Entries may run `trim_slack` past `max_entries`, so a full file is rewritten once every so
+    /// many commands rather than for each. The size limit and `keep_sorted` have no slack.
+    fn trim_due(&self, entries: usize, bytes: u64) -> bool {
+        self.keep_sorted
+            || self.max_bytes.is_some_and(|max| bytes > max)
+            || (self.max_entries > 0 && entries > self.max_entries.saturating_add(self.trim_slack))
+    }
+}
+
+/// What [`FishSyncer::append_with_report`] did with each entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AppendReport {
+    /// Entries written to the file
+    pub written: usize,
+
+    /// Entries already in the file
+    pub duplicates: usize,
+
+    /// Entries skipped because the next trim would remove them anyway
+    pub too_old: usize,
+}
+
+/// What [`FishSyncer::reconcile`] does with an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reconcile {
+    Keep,
+    Remove,
+
+    /// Keep the entry, without the comments Atuin added to it
+    DropAnnotations,
+
+    /// Keep the entry with this command instead, unescaped, and everything else as it was
+    Rewrite(String),
+
+    /// Keep the entry, tagged as the history with this id
+    Annotate(String),
+}
+
+/// Which entries [`FishSyncer::remove_entries`] removes
+pub enum EntryMatch<'a> {
+    /// Entries tagged with one of these uuids, in any spelling
+    Uuids(&'a [String]),
+
+    /// Entries with one of these commands, unescaped, at that unix timestamp
+    CommandsAt(&'a [(String, i64)]),
+
+    /// Entries the function returns true for, called once per entry, oldest first
+    Predicate(Box<dyn FnMut(&FishHistoryEntry) -> bool + 'a>),
+}
+
+/// What a removal did, or would do
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RemovalReport {
+    /// Entries looked at
+    pub checked: usize,
+
+    pub removed: usize,
+
+    /// Entries kept without Atuin's comments
+    pub unannotated: usize,
+
+    /// Entries kept with a different command
+    pub rewritten: usize,
+
+    /// Entries fish wrote that were tagged with the id of their history
+    pub annotated: usize,
+
+    /// How much smaller the file got
+    pub bytes_removed: u64,
+}
+
+/// What [`FishSyncer::rebuild`] did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RebuildReport {
+    /// Entries fish wrote itself that were kept
+    pub kept: usize,
+
+    /// Entries left out of the new file: all of Atuin's, as they're written again from the
+    /// database, and fish's own unless they were kept
+    pub dropped: usize,
+
+    /// Entries written from the database
+    pub written: usize,
+
+    /// Entries from the database that weren't written, as a kept entry or an earlier one from the
+    /// database already has their command and timestamp, or their id
+    pub duplicates: usize,
+}
+
+/// Limits for [`FishSyncer::trim_with`]. Unset limits don't apply.
+#[derive(Debug, Clone, Default)]
+pub struct TrimLimits {
+    /// Keep at most this many entries
+    pub max_entries: Option<usize>,
+
+    /// Remove entries older than this
+    pub max_age: Option<Duration>,
+
+    /// Keep the file at most this many bytes
+    pub max_bytes: Option<u64>,
+
+    /// Fail with [`TrimRefused`], rather than remove more than this fraction of the entries
+    pub max_fraction: Option<f64>,
+}
+
+/// A trim would have removed more of the file than [`TrimLimits::max_fraction`] allows, so it
+/// was left alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrimRefused {
+    pub entries_removed: usize,
+    pub entries_before: usize,
+}
+
+impl std::fmt::Display for TrimRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "refusing to remove {} of {} entries from the fish history file",
+            self.entries_removed, self.entries_before
+        )
+    }
+}
+
+impl std::error::Error for TrimRefused {}
+
+/// What a trim removed, or would remove
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrimReport {
+    pub entries_before: usize,
+    pub entries_removed: usize,
+
+    /// Entries moved to put the file in time order, with `keep_sorted`
+    pub entries_moved: usize,
+
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// What [`FishSyncer::simulate_trim`] found a trim would do
+#[derive(Debug, Clone, Default)]
+pub struct TrimPlan {
+    pub report: TrimReport,
+
+    /// The entries that would be removed, oldest first
+    pub removed: Vec<FishHistoryEntry>,
+}
+
+impl TrimReport {
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before - self.bytes_after
+    }
+}
+
+/// A handle on a fish history file
+///
+/// Every operation takes an advisory lock on the file for its whole duration, so concurrent
+/// writers (including other processes using this API) never interleave.
+#[derive(Debug, Clone)]
+pub struct FishSyncer {
+    path: PathBuf,
+    options: FishSyncOptions,
+
+    /// Shared by every clone, when appends should reuse one index of the file
+    session: Option<Arc<Mutex<SessionState>>>,
+}
+
+/// What a session remembers of the file between appends
+#[derive(Debug, Default)]
+struct SessionState {
+    cached: Option<CachedIndex>,
+
+    /// How many times the whole file was read to index it
+    parses: usize,
+}
+
+#[derive(Debug)]
+struct CachedIndex {
+    index: FishDedupIndex,
+
+    /// The file right after our last append, so changes anyone else made since are noticed
+    stamp: FileStamp,
+}
+
+/// Size and modification time of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileStamp {
+    pub(crate) len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn of(file: &File) -> Result<Self> {
+        let metadata = file
+            .metadata()
+            .context("failed to read fish history file metadata")?;
+
+        Ok(Self::from_metadata(&metadata))
+    }
+
+    /// The stamp of the file at `path`, or `None` if there's no file there
+    pub(crate) fn of_path(path: &Path) -> Option<Self> {
+        std::fs::metadata(path)
+            .ok()
+            .map(|metadata| Self::from_metadata(&metadata))
+    }
+
+    fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+/// How many entries each fish history file had when Atuin last wrote it, and its stamp then
+///
+/// Like the rate limit's buckets, these outlive a single sync, so a long running process like the
+/// daemon can tell a file isn't due a trim without reading it, as long as nothing else has
+/// changed it since.
+static ENTRY_COUNTS: Mutex<BTreeMap<PathBuf, (FileStamp, usize)>> = Mutex::new(BTreeMap::new());
+
+/// Remember that the file at `path`, as it is now, holds `entries` entries
+fn remember_entries(path: &Path, entries: usize) {
+    let Ok(file) = File::open(path) else {
+        return;
+    };
+
+    if let Ok(stamp) = FileStamp::of(&file) {
+        ENTRY_COUNTS
+            .lock()
+            .expect("fish entry count lock poisoned")
+            .insert(path.to_path_buf(), (stamp, entries));
+    }
+}
+
+/// How many entries the file at `path` holds, if Atuin wrote it last and it's still `stamp`
+fn known_entries(path: &Path, stamp: FileStamp) -> Option<usize> {
+    ENTRY_COUNTS
+        .lock()
+        .expect("fish entry count lock poisoned")
+        .get(path)
+        .filter(|(known, _)| *known == stamp)
+        .map(|(_, entries)| *entries)
+}
+
+/// What each fish history file held when Atuin last wrote it, and its stamp then
+///
+/// Appends carry this forward over what they add, so the sync state can be brought up to date
+/// after a write without reading the whole file back, as long as nothing else has changed it.
+static KNOWN_CONTENTS: Mutex<BTreeMap<PathBuf, (FileStamp, ContentSummary)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Remember that the file at `path`, while it's still `stamp`, holds `contents`
+pub(crate) fn remember_contents(path: &Path, stamp: FileStamp, contents: ContentSummary) {
+    KNOWN_CONTENTS
+        .lock()
+        .expect("fish known contents lock poisoned")
+        .insert(path.to_path_buf(), (stamp, contents));
+}
+
+/// What the file at `path` holds, if Atuin wrote it last and it's still `stamp`
+fn contents_at(path: &Path, stamp: FileStamp) -> Option<ContentSummary> {
+    KNOWN_CONTENTS
+        .lock()
+        .expect("fish known contents lock poisoned")
+        .get(path)
+        .filter(|(known, _)| *known == stamp)
+        .map(|(_, contents)| contents.clone())
+}
+
+/// What the file at `path` holds as it is now, and its stamp, if that's known without reading it
+pub(crate) fn known_contents(path: &Path) -> Option<(FileStamp, ContentSummary)> {
+    let stamp = FileStamp::of_path(path)?;
+
+    contents_at(path, stamp).map(|contents| (stamp, contents))
+}
+
+/// Forget what the file at `path` holds, once it's changed in a way that wasn't followed
+fn forget_contents(path: &Path) {
+    KNOWN_CONTENTS
+        .lock()
+        .expect("fish known contents lock poisoned")
+        .remove(path);
+}
+
+/// The index each fish history file's last session finished with, keyed by path
+///
+/// A long running process like the daemon starts a new session for every batch it downloads.
+/// Each picks up where the last left off instead of reading the file again, unless the file's
+/// stamp shows something else has written to it since.
+static SHARED_INDEXES: Mutex<BTreeMap<PathBuf, CachedIndex>> = Mutex::new(BTreeMap::new());
+
+/// Forget the shared index of the file at `path`, once it's been rewritten
+fn forget_index(path: &Path) {
+    SHARED_INDEXES
+        .lock()
+        .expect("fish shared index lock poisoned")
+        .remove(path);
+}
+
+impl FishSyncer {
+    /// Open a fish history file for syncing. The file is created on first append.
+    pub fn open(path: impl Into<PathBuf>, options: FishSyncOptions) -> Result<Self> {
+        let path = path.into();
+        super::sandbox::guard(&path);
+
+        Ok(Self {
+            path,
+            options,
+            session: None,
+        })
+    }
+
+    /// Share one index of the file between this syncer's appends and those of its clones
+    ///
+    /// The file is parsed on the first append, and again only if something else changed it in
+    /// between. Appends don't trim, so trim once with [`FishSyncer::trim_to_options`] when done.
+    pub(crate) fn in_session(mut self) -> Self {
+        self.session = Some(Arc::default());
+        self
+    }
+
+    /// A clone sharing this one's session, tagging what it writes with `source` instead
+    pub(crate) fn with_audit_source(&self, source: Option<WriteSource>) -> Self {
+        let mut syncer = self.clone();
+        syncer.options.audit_source = source;
+        syncer
+    }
+
+    /// How many times appends in this session have parsed the whole file
+    pub(crate) fn parses(&self) -> usize {
+        self.session.as_ref().map_or(0, |session| {
+            session.lock().expect("fish session lock poisoned").parses
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append entries that aren't already in the file, returning how many were written
+    ///
+    /// An entry counts as already present if an entry with the same uuid exists, or one with the
+    /// same command and timestamp. Entries repeated within `entries` are only written once.
+    pub fn append(&self, entries: &[CommandEntry]) -> Result<usize> {
+        Ok(self.append_with_report(entries)?.written)
+    }
+
+    /// Like [`FishSyncer::append`], also reporting why entries weren't written
+    pub fn append_with_report(&self, entries: &[CommandEntry]) -> Result<AppendReport> {
+        self.append_inspecting(entries, |_| {})
+    }
+
+    /// Like [`FishSyncer::append_with_report`], calling `on_written` with each entry written
+    pub(crate) fn append_inspecting(
+        &self,
+        entries: &[CommandEntry],
+        mut on_written: impl FnMut(&CommandEntry),
+    ) -> Result<AppendReport> {
+        let mut file = self.open_locked()?;
+
+        // whether `content` is all of the file, rather than just its end
+        let mut whole = true;
+
+        let (mut index, content) = match self.cached_index(&file)? {
+            Some(index) => (index, None),
+            None => {
+                let content = match self.options.tail_entries() {
+                    Some(entries) => {
+                        let tail;
+                        (tail, whole) = read_tail(&mut file, entries)?;
+                        tail
+                    }
+                    None => read_all(&mut file)?,
+                };
+                (self.build_index(&content)?, Some(content))
+            }
+        };
+
+        // what was written to a file that's since been started over says nothing about it
+        if index.entries == 0 {
+            index.sidecar.clear();
+        }
+
+        let mut buf = String::new();
+        let mut report = AppendReport::default();
+
+        for entry in entries {
+            if index.contains(entry) {
+                report.duplicates += 1;
+                continue;
+            }
+
+            if self.outside_window(&index, entry) {
+                report.too_old += 1;
+                continue;
+            }
+
+            index.insert(entry);
+            buf.push_str(&entry.to_fish());
+            if let Some(source) = self.options.audit_source {
+                buf.push_str(&metadata_line(ATUIN_SRC_KEY, source.as_str()));
+            }
+            on_written(entry);
+            report.written += 1;
+        }
+
+        if report.written > 0 {
+            // a hand edited file may end mid line, which would run the first entry into it
+            if ends_without_newline(&mut file)? {
+                buf.insert(0, '\n');
+            }
+
+            let before = FileStamp::of(&file)?;
+
+            file.seek(SeekFrom::End(0))?;
+            file.write_all(buf.as_bytes())
+                .context("failed to write to fish history file")?;
+            file.flush().context("failed to flush fish history file")?;
+
+            // what was known of the file before, carried over what was just added to it
+            let known = contents_at(&self.path, before).or_else(|| match &content {
+                Some(content) if whole => Some(ContentSummary::of(content)),
+                _ => None,
+            });
+            let after = known.map(|known| known.appended(&buf));
+            match &after {
+                Some(after) => remember_contents(&self.path, FileStamp::of(&file)?, after.clone()),
+                None => forget_contents(&self.path),
+            }
+
+            // the entries are written either way, and still deduped while fish keeps their comments
+            if let Err(e) = index.sidecar.save() {
+                log::warn!("failed to record written fish history entries: {e:#}");
+            }
+
+            let written = report.written;
+            journal::record(self.options.journal.as_ref(), || {
+                let file_hash = match &after {
+                    Some(after) => after.hash(),
+                    None => {
+                        file.seek(SeekFrom::Start(0))?;
+                        hash_contents(read_all(&mut file)?.as_bytes())
+                    }
+                };
+
+                Ok(JournalEntry::hashed(Operation::Append, file_hash)
+                    .added(written)
+                    .bytes_delta(buf.len() as i64))
+            });
+
+            let mut entries = index.entries;
+
+            // a session has read nothing to trim, and trims once at the end instead
+            if let (Some(limits), Some(content)) = (self.options.trim_after_write_limits(), content)
+                && self.session.is_none()
+                && self
+                    .options
+                    .trim_due(entries, (content.len() + buf.len()) as u64)
+            {
+                let content = content + &buf;
+                entries -= self
+                    .trim_locked(
+                        &mut file,
+                        &content,
+                        &limits,
+                        OffsetDateTime::now_utc(),
+                        false,
+                    )?
+                    .entries_removed;
+            }
+
+            remember_entries(&self.path, entries);
+        }
+
+        self.keep_index(&file, index)?;
+
+        Ok(report)
+    }
+
+    /// The session's index of the file, or the one the last session finished with, if nothing
+    /// else has changed the file since it was built
+    fn cached_index(&self, file: &File) -> Result<Option<FishDedupIndex>> {
+        let Some(session) = &self.session else {
+            return Ok(None);
+        };
+
+        let cached = session
+            .lock()
+            .expect("fish session lock poisoned")
+            .cached
+            .take()
+            .or_else(|| {
+                SHARED_INDEXES
+                    .lock()
+                    .expect("fish shared index lock poisoned")
+                    .remove(&self.path)
+            });
+
+        let stamp = FileStamp::of(file)?;
+
+        Ok(cached
+            .filter(|cached| cached.stamp == stamp)
+            .map(|cached| cached.index))
+    }
+
+    fn build_index(&self, content: &str) -> Result<FishDedupIndex> {
+        if let Some(session) = &self.session {
+            session.lock().expect("fish session lock poisoned").parses += 1;
+        }
+
+        let mut index = FishDedupIndex::build(content);
+        index.sidecar = Sidecar::load(&self.path)?;
+
+        Ok(index)
+    }
+
+    /// Keep the index for the session's next append
+    fn keep_index(&self, file: &File, index: FishDedupIndex) -> Result<()> {
+        if let Some(session) = &self.session {
+            let stamp = FileStamp::of(file)?;
+            session.lock().expect("fish session lock poisoned").cached =
+                Some(CachedIndex { index, stamp });
+        }
+
+        Ok(())
+    }
+
+    /// Whether an entry predates everything in an already full file
+    fn outside_window(&self, index: &FishDedupIndex, entry: &CommandEntry) -> bool {
+        let max = self.options.max_entries;
+
+        self.options.skip_older_than_window
+            && max > 0
+            && index.entries >= max
+            && index
+                .oldest
+                .is_some_and(|oldest| entry.timestamp.unix_timestamp() < oldest)
+    }
+
+    /// Index the file for dedup, reading it once
+    ///
+    /// Appends build the same index themselves, so this is for checking many entries against the
+    /// file without writing them.
+    pub fn dedup_index(&self) -> Result<FishDedupIndex> {
+        if !self.path.exists() {
+            return Ok(FishDedupIndex::default());
+        }
+
+        let mut file = self.open_shared()?;
+        let content = read_all(&mut file)?;
+
+        self.build_index(&content)
+    }
+
+    /// Index only the newest `entries` entries of the file for dedup, reading it from the end
+    ///
+    /// Entries older than those are trimmed away, or far older than anything a sync writes, so
+    /// this costs the same however long the file is. [`FishDedupIndex::len`] counts only the
+    /// entries read.
+    pub fn dedup_index_tail(&self, entries: usize) -> Result<FishDedupIndex> {
+        if !self.path.exists() {
+            return Ok(FishDedupIndex::default());
+        }
+
+        let mut file = self.open_shared()?;
+        let (content, _) = read_tail(&mut file, entries)?;
+
+        self.build_index(&content)
+    }
+
+    /// Check whether an entry with this uuid is in the file
+    pub fn contains(&self, uuid: &str) -> Result<bool> {
+        if !self.path.exists() {
+            return Ok(false);
+        }
+
+        let mut file = self.open_shared()?;
+
+        let content = read_all(&mut file)?;
+
+        let uuid = canonical_id(uuid);
+
+        Ok(split_entries(&content)
+            .1
+            .iter()
+            .any(|e| e.uuid.is_some_and(|id| canonical_id(id) == uuid)))
+    }
+
+    /// Every entry in the file, oldest first
+    pub fn entries(&self) -> Result<Vec<FishHistoryEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut file = self.open_shared()?;
+
+        let content = read_all(&mut file)?;
+
+        Ok(FishHistoryEntry::parse(&content))
+    }
+
+    /// The timestamp of the last entry in the file that has a valid one
+    ///
+    /// Only an entry's own `when:` field counts, never text in a command or anywhere else that
+    /// happens to look like one. `None` if no entry has a valid timestamp.
+    pub fn last_timestamp(&self) -> Result<Option<i64>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let mut file = self.open_shared()?;
+
+        let content = read_all(&mut file)?;
+
+        Ok(split_entries(&content)
+            .1
+            .iter()
+            .rev()
+            .find_map(|entry| entry.when))
+    }
+
+    /// Everything malformed in the file, in file order
+    pub fn issues(&self) -> Result<Vec<ParseIssue>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut file = self.open_shared()?;
+
+        let content = read_all(&mut file)?;
+
+        Ok(FishHistoryEntry::issues(&content))
+    }
+
+    /// What trimming to `limits` would remove, without changing the file or anything beside it
+    ///
+    /// Unlike a dry run of [`FishSyncer::trim_with`], this only takes a shared lock, so it doesn't
+    /// wait for fish or Atuin to finish writing any longer than reading does.
+    pub fn simulate_trim(&self, limits: &TrimLimits, now: OffsetDateTime) -> Result<TrimPlan> {
+        if !self.path.exists() {
+            return Ok(TrimPlan::default());
+        }
+
+        let mut file = self.open_shared()?;
+        let content = read_all(&mut file)?;
+        drop(file);
+
+        let (preamble, entries) = split_entries(&content);
+        let keep = entries_to_keep(preamble, &entries, limits, now);
+
+        let mut plan = TrimPlan::default();
+        plan.report.entries_before = entries.len();
+        plan.report.bytes_before = content.len() as u64;
+        plan.report.bytes_after = preamble.len() as u64;
+
+        for (entry, keep) in entries.iter().zip(keep) {
+            if keep {
+                plan.report.bytes_after += entry.text.len() as u64;
+            } else {
+                plan.report.entries_removed += 1;
+                plan.removed.push(FishHistoryEntry::from(entry));
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Drop the oldest entries until at most `max_entries` remain, returning how many were removed
+    pub fn trim(&self, max_entries: usize) -> Result<usize> {
+        let limits = TrimLimits {
+            max_entries: Some(max_entries),
+            ..TrimLimits::default()
+        };
+
+        let report = self.trim_with(&limits, OffsetDateTime::now_utc(), false)?;
+
+        Ok(report.entries_removed)
+    }
+
+    /// Drop the oldest entries until the file fits the limits in its options, returning how many
+    /// were removed
+    ///
+    /// A file that's past `max_entries` by no more than `trim_slack` is left as it is.
+    pub fn trim_to_options(&self) -> Result<usize> {
+        Ok(self.trim_to_options_with_report()?.entries_removed)
+    }
+
+    /// Trim once for everything the session wrote, then leave its index for the next session
+    pub(crate) fn finish_session(&self) -> Result<TrimReport> {
+        let report = self.trim_to_options_with_report()?;
+
+        let cached = self.session.as_ref().and_then(|session| {
+            session
+                .lock()
+                .expect("fish session lock poisoned")
+                .cached
+                .take()
+        });
+
+        // a trim rewrote the file, so what was kept before it no longer describes it
+        if let Some(cached) = cached
+            && report.entries_removed == 0
+            && report.entries_moved == 0
+        {
+            SHARED_INDEXES
+                .lock()
+                .expect("fish shared index lock poisoned")
+                .insert(self.path.clone(), cached);
+        }
+
+        Ok(report)
+    }
+
+    /// Like [`FishSyncer::trim_to_options`], reporting what was removed
+    ///
+    /// The file is only trimmed once it's due, see [`FishSyncOptions::trim_due`], and only read
+    /// to find out if Atuin doesn't already know how many entries it has.
+    pub(crate) fn trim_to_options_with_report(&self) -> Result<TrimReport> {
+        let Some(limits) = self.options.trim_after_write_limits() else {
+            return Ok(TrimReport::default());
+        };
+
+        if !self.path.exists() {
+            return Ok(TrimReport::default());
+        }
+
+        let mut file = self.open_locked()?;
+        let stamp = FileStamp::of(&file)?;
+
+        if let Some(entries) = known_entries(&self.path, stamp)
+            && !self.options.trim_due(entries, stamp.len)
+        {
+            return Ok(TrimReport::default());
+        }
+
+        let content = read_all(&mut file)?;
+        let entries = split_entries(&content).1.len();
+
+        if !self.options.trim_due(entries, content.len() as u64) {
+            remember_entries(&self.path, entries);
+            return Ok(TrimReport::default());
+        }
+
+        let report = self.trim_locked(
+            &mut file,
+            &content,
+            &limits,
+            OffsetDateTime::now_utc(),
+            false,
+        )?;
+        remember_entries(&self.path, entries - report.entries_removed);
+
+        Ok(report)
+    }
+
+    /// Drop the oldest entries until every limit holds
+    ///
+    /// With `dry_run`, the file is left alone and the report describes what would be removed.
+    /// [`TrimLimits::max_fraction`] is checked under the same lock as the trim, so nothing fish
+    /// writes in between can make the trim that runs bigger than the one checked.
+    pub fn trim_with(
+        &self,
+        limits: &TrimLimits,
+        now: OffsetDateTime,
+        dry_run: bool,
+    ) -> Result<TrimReport> {
+        if !self.path.exists() {
+            return Ok(TrimReport::default());
+        }
+
+        let mut file = self.open_locked()?;
+        let content = read_all(&mut file)?;
+
+        self.trim_locked(&mut file, &content, limits, now, dry_run)
+    }
+
+    /// Trim an already locked file whose contents are `content`, putting what's left in time
+    /// order with `keep_sorted`
+    fn trim_locked(
+        &self,
+        file: &mut File,
+        content: &str,
+        limits: &TrimLimits,
+        now: OffsetDateTime,
+        dry_run: bool,
+    ) -> Result<TrimReport> {
+        // `content` may be only the end of the file, when an append read no more
+        let bytes_before = file
+            .metadata()
+            .context("failed to read fish history file metadata")?
+            .len();
+        let (mut trimmed, mut report) = plan_trim(content, limits, now);
+
+        #[allow(clippy::cast_precision_loss)]
+        if !dry_run
+            && let Some(max_fraction) = limits.max_fraction
+            && report.entries_before > 0
+            && report.entries_removed as f64 / report.entries_before as f64 > max_fraction
+        {
+            return Err(TrimRefused {
+                entries_removed: report.entries_removed,
+                entries_before: report.entries_before,
+            }
+            .into());
+        }
+
+        if self.options.keep_sorted {
+            (trimmed, report.entries_moved) = sort_by_time(&trimmed);
+        }
+
+        if dry_run || (report.entries_removed == 0 && report.entries_moved == 0) {
+            return Ok(report);
+        }
+
+        rewrite_locked(&self.path, file, content, &trimmed)?;
+
+        if report.entries_removed > 0 {
+            sidecar::prune(&self.path, oldest_when(&trimmed));
+        }
+
+        journal::record(self.options.journal.as_ref(), || {
+            Ok(JournalEntry::new(Operation::Trim, trimmed.as_bytes())
+                .removed(report.entries_removed)
+                .changed(report.entries_moved)
+                .bytes_delta(trimmed.len() as i64 - bytes_before as i64))
+        });
+
+        Ok(report)
+    }
+
+    /// Remove every entry tagged with one of these uuids, returning how many were removed
+    ///
+    /// The file is rewritten once, however many entries match.
+    pub fn remove_by_uuid(&self, uuids: &[String]) -> Result<usize> {
+        Ok(self
+            .remove_entries(EntryMatch::Uuids(uuids), false)?
+            .removed)
+    }
+
+    /// Remove every entry `matching` picks, rewriting the file once
+    ///
+    /// With `dry_run`, the file is left alone and the report describes what would be removed. If
+    /// nothing matches, the file isn't rewritten at all.
+    pub fn remove_entries(&self, matching: EntryMatch<'_>, dry_run: bool) -> Result<RemovalReport> {
+        let mut matches: Box<dyn FnMut(&RawEntry<'_>) -> bool + '_> = match matching {
+            EntryMatch::Uuids(uuids) => {
+                let uuids: HashSet<String> = uuids.iter().map(|id| canonical_id(id)).collect();
+                Box::new(move |entry| {
+                    entry
+                        .uuid
+                        .is_some_and(|uuid| uuids.contains(&canonical_id(uuid)))
+                })
+            }
+            EntryMatch::CommandsAt(commands) => {
+                let commands: HashSet<(&str, i64)> = commands
+                    .iter()
+                    .map(|(command, when)| (command.as_str(), *when))
+                    .collect();
+                Box::new(move |entry| {
+                    entry.when.is_some_and(|when| {
+                        commands.contains(&(unescape_fish_cmd(entry.cmd).as_str(), when))
+                    })
+                })
+            }
+            EntryMatch::Predicate(mut predicate) => {
+                Box::new(move |entry| predicate(&FishHistoryEntry::from(entry)))
+            }
+        };
+
+        self.rewrite_entries(dry_run, |entry| {
+            if matches(entry) {
+                Reconcile::Remove
+            } else {
+                Reconcile::Keep
+            }
+        })
+    }
+
+    /// Remove later copies of entries already in the file, returning how many were removed
+    ///
+    /// An entry is a copy if an earlier one has the same command and either the same uuid or the
+    /// same timestamp. A uuid on an entry with a different command doesn't make it a copy: fish
+    /// may have moved the comment when it rewrote the file, so it proves nothing. The first copy
+    /// of each entry is kept exactly as it was, comments and all.
+    pub fn dedupe(&self) -> Result<usize> {
+        Ok(self
+            .remove_entries(EntryMatch::Predicate(duplicates()), false)?
+            .removed)
+    }
+
+    /// Remove every entry but the newest of each command
+    ///
+    /// Fish keeps one entry per command itself, so older copies only push other commands down its
+    /// suggestions. Entries are grouped by their unescaped command, whether Atuin or fish wrote
+    /// them, and the one with the latest timestamp is kept exactly as it was, uuid and all. On a
+    /// tie, the later one in the file wins. Entries without a timestamp are left alone.
+    ///
+    /// Commands that differ only in whitespace are different commands, unless
+    /// `ignore_whitespace`. With `dry_run`, the file is left alone.
+    pub fn dedupe_commands(&self, ignore_whitespace: bool, dry_run: bool) -> Result<RemovalReport> {
+        self.rewrite_planned(dry_run, |entries| {
+            let commands: Vec<String> = entries
+                .iter()
+                .map(|entry| {
+                    let command = unescape_fish_cmd(entry.cmd);
+                    if ignore_whitespace {
+                        command.split_whitespace().collect::<Vec<_>>().join(" ")
+                    } else {
+                        command
+                    }
+                })
+                .collect();
+
+            // the index of the newest entry of each command
+            let mut newest: HashMap<&str, (i64, usize)> = HashMap::new();
+            for (i, (entry, command)) in entries.iter().zip(&commands).enumerate() {
+                let Some(when) = entry.when else {
+                    continue;
+                };
+
+                newest
+                    .entry(command)
+                    .and_modify(|kept| {
+                        if when >= kept.0 {
+                            *kept = (when, i);
+                        }
+                    })
+                    .or_insert((when, i));
+            }
+
+            let keep: HashSet<usize> = newest.into_values().map(|(_, i)| i).collect();
+
+            entries
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    if entry.when.is_none() || keep.contains(&i) {
+                        Reconcile::Keep
+                    } else {
+                        Reconcile::Remove
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// Put the entries in ascending `when:` order, returning how many moved
+    ///
+    /// See [`sort_by_time`] for the order. Entries are moved whole, comments and all, so nothing
+    /// about them changes but their place. With `dry_run`, the file is left alone.
+    pub fn sort(&self, dry_run: bool) -> Result<usize> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+
+        let mut file = self.open_locked()?;
+        let content = read_all(&mut file)?;
+        let (sorted, moved) = sort_by_time(&content);
+
+        if !dry_run && moved > 0 {
+            rewrite_locked(&self.path, &mut file, &content, &sorted)?;
+        }
+
+        Ok(moved)
+    }
+
+    /// Keep, remove, or strip Atuin's comments from each entry Atuin wrote, as `decide` says
+    ///
+    /// `decide` gets the canonical uuid, the unescaped command and the timestamp of each entry
+    /// with a uuid, so a uuid is only ever judged together with the entry it's part of. Entries
+    /// without a uuid are always kept, and not counted as checked. With `dry_run`, the file is
+    /// left alone.
+    pub(crate) fn reconcile(
+        &self,
+        dry_run: bool,
+        mut decide: impl FnMut(&str, &str, Option<i64>) -> Reconcile,
+    ) -> Result<RemovalReport> {
+        let mut checked = 0;
+
+        let report = self.rewrite_entries(dry_run, |entry| {
+            let Some(uuid) = entry.uuid else {
+                return Reconcile::Keep;
+            };
+
+            checked += 1;
+            decide(
+                &canonical_id(uuid),
+                &unescape_fish_cmd(entry.cmd),
+                entry.when,
+            )
+        })?;
+
+        Ok(RemovalReport { checked, ..report })
+    }
+
+    /// Tag each entry fish wrote with the id `ids` has for its command and timestamp
+    ///
+    /// Entries that already have a uuid, or whose command and timestamp aren't in `ids`, are left
+    /// alone.
+    pub(crate) fn annotate(&self, ids: &HashMap<(String, i64), String>) -> Result<RemovalReport> {
+        self.rewrite_entries(false, |entry| {
+            if entry.uuid.is_some() {
+                return Reconcile::Keep;
+            }
+
+            entry
+                .when
+                .and_then(|when| ids.get(&(unescape_fish_cmd(entry.cmd), when)))
+                .map_or(Reconcile::Keep, |id| Reconcile::Annotate(id.clone()))
+        })
+    }
+
+    /// Rewrite the file once, doing with each entry what `decide` says
+    ///
+    /// Removing entries for any reason goes through here, so there's one locked, atomic rewrite to
+    /// get right. The file is only rewritten if something changes, and never with `dry_run`.
+    fn rewrite_entries(
+        &self,
+        dry_run: bool,
+        mut decide: impl FnMut(&RawEntry<'_>) -> Reconcile,
+    ) -> Result<RemovalReport> {
+        self.rewrite_planned(dry_run, |entries| entries.iter().map(&mut decide).collect())
+    }
+
+    /// [`Self::rewrite_entries`], for decisions that need to see every entry first
+    ///
+    /// `plan` gets all the entries at once, and returns what to do with each, in the same order.
+    fn rewrite_planned(
+        &self,
+        dry_run: bool,
+        plan: impl FnOnce(&[RawEntry<'_>]) -> Vec<Reconcile>,
+    ) -> Result<RemovalReport> {
+        let mut report = RemovalReport::default();
+
+        if !self.path.exists() {
+            return Ok(report);
+        }
+
+        let mut file = self.open_locked()?;
+        let content = read_all(&mut file)?;
+        let (preamble, entries) = split_entries(&content);
+
+        let mut kept = String::with_capacity(content.len());
+        kept.push_str(preamble);
+
+        let decisions = plan(&entries);
+
+        for (entry, decision) in entries.iter().zip(decisions) {
+            report.checked += 1;
+
+            match decision {
+                Reconcile::Keep => kept.push_str(entry.text),
+                Reconcile::Remove => report.removed += 1,
+                Reconcile::DropAnnotations => {
+                    report.unannotated += 1;
+                    kept.extend(
+                        entry
+                            .text
+                            .split_inclusive('\n')
+                            .enumerate()
+                            .filter(|(i, line)| *i == 0 || parse_metadata_line(line).is_none())
+                            .map(|(_, line)| line),
+                    );
+                }
+                Reconcile::Rewrite(command) => {
+                    report.rewritten += 1;
+                    kept.push_str("- cmd:");
+                    kept.push_str(&escape_fish_cmd(&command));
+                    kept.push('\n');
+                    kept.extend(entry.text.split_inclusive('\n').skip(1));
+                }
+                Reconcile::Annotate(id) => {
+                    report.annotated += 1;
+                    kept.push_str(entry.text);
+                    if !entry.text.ends_with('\n') {
+                        kept.push('\n');
+                    }
+                    kept.push_str(&metadata_line(ATUIN_UUID_KEY, &id));
+                }
+            }
+        }
+
+        report.bytes_removed = content.len().saturating_sub(kept.len()) as u64;
+
+        if !dry_run && kept != content {
+            rewrite_locked(&self.path, &mut file, &content, &kept)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Replace the file with `entries`, and with `keep_native`, the entries fish wrote itself,
+    /// all in time order
+    ///
+    /// Everything Atuin wrote before is dropped, so whatever state it got into, the file ends up
+    /// holding each entry once. `on_written` is called with each entry written. The file is
+    /// replaced in one atomic rewrite, and what was written is recorded afresh beside it.
+    pub(crate) fn rebuild(
+        &self,
+        entries: &[CommandEntry],
+        keep_native: bool,
+        mut on_written: impl FnMut(&CommandEntry),
+    ) -> Result<RebuildReport> {
+        let mut file = self.open_locked()?;
+        let content = read_all(&mut file)?;
+        let (preamble, existing) = split_entries(&content);
+
+        let mut report = RebuildReport::default();
+        let mut rebuilt = String::with_capacity(content.len());
+        rebuilt.push_str(preamble);
+
+        let mut ids = HashSet::new();
+        let mut commands = HashSet::new();
+
+        for entry in &existing {
+            if !keep_native || entry.uuid.is_some() {
+                report.dropped += 1;
+                continue;
+            }
+
+            report.kept += 1;
+            if let Some(when) = entry.when {
+                commands.insert((unescape_fish_cmd(entry.cmd), when));
+            }
+
+            rebuilt.push_str(entry.text);
+            if !entry.text.ends_with('\n') {
+                rebuilt.push('\n');
+            }
+        }
+
+        let mut sidecar = Sidecar::load(&self.path)?;
+        sidecar.clear();
+
+        for entry in entries {
+            let new_id = entry
+                .uuid
+                .as_deref()
+                .is_none_or(|id| ids.insert(canonical_id(id)));
+
+            if !new_id
+                || !commands.insert((entry.command.clone(), entry.timestamp.unix_timestamp()))
+            {
+                report.duplicates += 1;
+                continue;
+            }
+
+            rebuilt.push_str(&entry.to_fish());
+            if let Some(source) = self.options.audit_source {
+                rebuilt.push_str(&metadata_line(ATUIN_SRC_KEY, source.as_str()));
+            }
+            sidecar.record(entry);
+            on_written(entry);
+            report.written += 1;
+        }
+
+        let (rebuilt, _) = sort_by_time(&rebuilt);
+        rewrite_locked(&self.path, &mut file, &content, &rebuilt)?;
+
+        // the file is rebuilt either way, and dedup still works while fish keeps the comments
+        if let Err(e) = sidecar.save() {
+            log::warn!("failed to record written fish history entries: {e:#}");
+        }
+
+        journal::record(self.options.journal.as_ref(), || {
+            Ok(JournalEntry::new(Operation::Rebuild, rebuilt.as_bytes())
+                .added(report.written)
+                .removed(report.dropped)
+                .bytes_delta(rebuilt.len() as i64 - content.len() as i64))
+        });
+
+        Ok(report)
+    }
+
+    /// Remove every comment Atuin added to the file, returning how many lines were removed
+    ///
+    /// Only `atuin-` keys are removed. Comments other tools added stay where they are.
+    pub fn strip_metadata(&self) -> Result<usize> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+
+        let mut file = self.open_locked()?;
+        let content = read_all(&mut file)?;
+
+        let mut kept = String::with_capacity(content.len());
+        let mut removed = 0;
+
+        for line in content.split_inclusive('\n') {
+            if line.starts_with("- cmd:") || parse_metadata_line(line).is_none() {
+                kept.push_str(line);
+            } else {
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            rewrite_locked(&self.path, &mut file, &content, &kept)?;
+
+            journal::record(self.options.journal.as_ref(), || {
+                Ok(JournalEntry::new(Operation::Clean, kept.as_bytes())
+                    .changed(removed)
+                    .bytes_delta(kept.len() as i64 - content.len() as i64))
+            });
+        }
+
+        Ok(removed)
+    }
+
+    /// Count the entries tagged with one of these uuids, without changing the file
+    pub fn count_by_uuid(&self, uuids: &[String]) -> Result<usize> {
+        Ok(self.remove_entries(EntryMatch::Uuids(uuids), true)?.removed)
+    }
+
+    fn open_locked(&self) -> Result<File> {
+        loop {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .read(true)
+                .write(true)
+                .open(&self.path)
+                .context("failed to open fish history file")?;
+
+            file.lock_exclusive()
+                .context("failed to acquire lock on fish history file")?;
+
+            // a rewrite may have replaced the file while we waited for the lock
+            if is_current(&self.path, &file)? {
+                return Ok(file);
+            }
+        }
+    }
+
+    fn open_shared(&self) -> Result<File> {
+        loop {
+            let file = File::open(&self.path).context("failed to open fish history file")?;
+            file.lock_shared()
+                .context("failed to acquire lock on fish history file")?;
+
+            if is_current(&self.path, &file)? {
+                return Ok(file);
+            }
+        }
+    }
+}
+
+/// Escape a command the way fish does in its history file
+pub(crate) fn escape_fish_cmd(command: &str) -> String {
+    command.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Undo [`escape_fish_cmd`], turning a command from the history file back into what was run
+pub(crate) fn unescape_fish_cmd(escaped: &str) -> String {
+    let mut command = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            command.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => command.push('\n'),
+            Some('\\') => command.push('\\'),
+            Some(other) => {
+                command.push('\\');
+                command.push(other);
+            }
+            None => command.push('\\'),
+        }
+    }
+
+    command
+}
+
+/// A predicate for [`EntryMatch::Predicate`] picking later copies of entries it has already seen
+///
+/// An entry is a copy if an earlier one has the same command and either the same uuid or the
+/// same timestamp.
+pub(crate) fn duplicates() -> Box<dyn FnMut(&FishHistoryEntry) -> bool> {
+    let mut uuids: HashSet<(String, String)> = HashSet::new();
+    let mut commands: HashSet<(String, i64)> = HashSet::new();
+
+    Box::new(move |entry| {
+        let uuid = entry
+            .uuid
+            .as_ref()
+            .map(|uuid| (canonical_id(uuid), entry.command.clone()));
+        let timed = entry.when.map(|when| (entry.command.clone(), when));
+
+        let seen = uuid.as_ref().is_some_and(|uuid| uuids.contains(uuid))
+            || timed.as_ref().is_some_and(|timed| commands.contains(timed));
+
+        if !seen {
+            uuids.extend(uuid);
+            commands.extend(timed);
+        }
+
+        seen
+    })
+}
+
+/// Whether the file has contents that don't end in a newline
+fn ends_without_newline(file: &mut File) -> Result<bool> {
+    if file.seek(SeekFrom::End(0))? == 0 {
+        return Ok(false);
+    }
+
+    let mut last = [0; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)
+        .context("failed to read fish history file")?;
+
+    Ok(last[0] != b'\n')
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Whole reads of fish history files on this thread, so tests can check what a sync reads
+    pub(crate) static FILE_READS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+fn count_read() {
+    #[cfg(test)]
+    FILE_READS.with(|reads| reads.set(reads.get() + 1));
+}
+
+/// Read the whole fish history file at `path`, for what can't be answered from
+/// [`known_contents`]
+pub(crate) fn read_file(path: &Path) -> std::io::Result<Vec<u8>> {
+    count_read();
+    std::fs::read(path)
+}
+
+fn read_all(file: &mut File) -> Result<String> {
+    count_read();
+
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut content)
+        .context("failed to read fish history file")?;
+
+    Ok(content)
+}
+
+/// The file from the start of its `entries`th entry from the end, and whether that's all of it
+///
+/// The file is read backwards in growing chunks, so only about as much of it is read as those
+/// entries take up. A file with no more entries than that is returned whole, anything before its
+/// first entry included.
+fn read_tail(file: &mut File, entries: usize) -> Result<(String, bool)> {
+    count_read();
+
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut start = len;
+    let mut chunk = TAIL_CHUNK;
+    let mut tail = Vec::new();
+
+    loop {
+        let from = start.saturating_sub(chunk);
+        let mut read = vec![0; (start - from) as usize];
+        file.seek(SeekFrom::Start(from))?;
+        file.read_exact(&mut read)
+            .context("failed to read fish history file")?;
+        read.extend_from_slice(&tail);
+        tail = read;
+        start = from;
+        chunk = chunk.saturating_mul(2);
+
+        // a line start is only certain after a newline, or at the start of the file
+        let starts: Vec<usize> = (0..tail.len())
+            .filter(|&i| (i > 0 && tail[i - 1] == b'\n') || (i == 0 && start == 0))
+            .filter(|&i| tail[i..].starts_with(b"- cmd:"))
+            .collect();
+
+        if starts.len() > entries {
+            let cut = starts.get(starts.len() - entries).copied();
+            tail.drain(..cut.unwrap_or(tail.len()));
+            break;
+        }
+
+        if start == 0 {
+            break;
+        }
+    }
+
+    let whole = tail.len() as u64 == len;
+    let tail = String::from_utf8(tail).context("failed to read fish history file")?;
+
+    Ok((tail, whole))
+}
+
+/// How much of the file [`read_tail`] reads first, doubling each time it needs more
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+/// The oldest timestamp of any entry in `content`
+fn oldest_when(content: &str) -> Option<i64> {
+    split_entries(content)
+        .1
+        .iter()
+        .filter_map(|entry| entry.when)
+        .min()
+}
+
+/// Work out what's left of `content` after trimming it to `limits`
+fn plan_trim(content: &str, limits: &TrimLimits, now: OffsetDateTime) -> (String, TrimReport) {
+    let (preamble, entries) = split_entries(content);
+    let keep = entries_to_keep(preamble, &entries, limits, now);
+    let kept_entries = keep.iter().filter(|k| **k).count();
+
+    let mut trimmed = String::with_capacity(content.len());
+    trimmed.push_str(preamble);
+    for (entry, _) in entries.iter().zip(&keep).filter(|(_, k)| **k) {
+        trimmed.push_str(entry.text);
+    }
+
+    let report = TrimReport {
+        entries_before: entries.len(),
+        entries_removed: entries.len() - kept_entries,
+        entries_moved: 0,
+        bytes_before: content.len() as u64,
+        bytes_after: trimmed.len() as u64,
+    };
+
+    (trimmed, report)
+}
+
+/// Which of `entries` are left after trimming to `limits`
+fn entries_to_keep(
+    preamble: &str,
+    entries: &[RawEntry<'_>],
+    limits: &TrimLimits,
+    now: OffsetDateTime,
+) -> Vec<bool> {
+    let cutoff = limits
+        .max_age
+        .map(|age| now.unix_timestamp().saturating_sub(age.as_secs() as i64));
+
+    // entries without a timestamp are never too old
+    let mut keep: Vec<bool> = entries
+        .iter()
+        .map(|entry| match (cutoff, entry.when) {
+            (Some(cutoff), Some(when)) => when >= cutoff,
+            _ => true,
+        })
+        .collect();
+
+    let mut kept_entries = keep.iter().filter(|k| **k).count();
+    let mut kept_bytes = preamble.len() as u64
+        + entries
+            .iter()
+            .zip(&keep)
+            .filter(|(_, k)| **k)
+            .map(|(entry, _)| entry.text.len() as u64)
+            .sum::<u64>();
+
+    // then drop the oldest remaining entries until the count and size fit, whichever is
+    // stricter
+    let newest = entries.len().saturating_sub(1);
+    for (i, (entry, keep)) in entries.iter().zip(keep.iter_mut()).enumerate() {
+        let too_many = limits.max_entries.is_some_and(|max| kept_entries > max);
+        let too_big = limits.max_bytes.is_some_and(|max| kept_bytes > max);
+
+        if !too_many && !too_big {
+            break;
+        }
+
+        // an empty file helps nobody, so the newest entry stays even if it's too big alone
+        if !too_many && i == newest && *keep {
+            log::warn!(
+                "the newest fish history entry alone is {} bytes, more than the size limit of {}; keeping it",
+                entry.text.len(),
+                limits.max_bytes.unwrap_or_default()
+            );
+            break;
+        }
+
+        if *keep {
+            *keep = false;
+            kept_entries -= 1;
+            kept_bytes -= entry.text.len() as u64;
+        }
+    }
+
+    keep
+}
+
+/// `content` with its entries in ascending `when:` order, and how many of them moved
+///
+/// The sort is stable, so entries sharing a timestamp keep their order. Entries without one count
+/// as older than any with one, as fish would have written them first.
+fn sort_by_time(content: &str) -> (String, usize) {
+    let (preamble, entries) = split_entries(content);
+
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&i| entries[i].when);
+
+    let moved = order
+        .iter()
+        .enumerate()
+        .filter(|(to, from)| to != *from)
+        .count();
+    if moved == 0 {
+        return (content.to_string(), 0);
+    }
+
+    let mut sorted = String::with_capacity(content.len() + 1);
+    sorted.push_str(preamble);
+    for i in order {
+        sorted.push_str(entries[i].text);
+        // only the file's last entry can be missing its newline
+        if !entries[i].text.ends_with('\n') {
+            sorted.push('\n');
+        }
+    }
+
+    (sorted, moved)
+}
+
+/// How a rewrite replaced the file's contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RewriteStrategy {
+    /// Written to a temp file beside the original, then renamed over it
+    Atomic,
+
+    /// Overwritten in place, as no temp file could be put beside it
+    InPlace,
+}
+
+/// Replace the contents of an already locked file, whose current contents are `original`
+///
+/// The new contents go to a temp file that's renamed over the original, so a crash part way
+/// leaves one or the other intact. Some directories allow writing the file but not creating new
+/// ones next to it, and some platforms won't rename over an open file. There the file is
+/// overwritten in place instead, with `original` put back if that fails.
+fn rewrite_locked(
+    path: &Path,
+    file: &mut File,
+    original: &str,
+    content: &str,
+) -> Result<RewriteStrategy> {
+    rewrite_locked_with(path, file, original, content, |temp| temp)
+}
+
+/// [`rewrite_locked`], writing the temp file through whatever `wrap` makes of it
+fn rewrite_locked_with<W: TempFile>(
+    path: &Path,
+    file: &mut File,
+    original: &str,
+    content: &str,
+    wrap: impl FnOnce(File) -> W,
+) -> Result<RewriteStrategy> {
+    let temp_path = temp_path(path);
+
+    let strategy = match replace_with_temp(path, &temp_path, file, content, wrap) {
+        Ok(()) => RewriteStrategy::Atomic,
+        Err(TempError::Write(e)) => {
+            let _ = fs_err::remove_file(&temp_path);
+            return Err(e);
+        }
+        Err(TempError::Unavailable(e)) => {
+            let _ = fs_err::remove_file(&temp_path);
+            log::debug!(
+                "can't replace {} through a temp file, rewriting in place: {e}",
+                path.display()
+            );
+
+            rewrite_in_place(file, original, content)?;
+            RewriteStrategy::InPlace
+        }
+    };
+
+    forget_index(path);
+    match FileStamp::of_path(path) {
+        Some(stamp) => remember_contents(path, stamp, ContentSummary::of(content)),
+        None => forget_contents(path),
+    }
+    log::debug!("rewrote {} (strategy={strategy:?})", path.display());
+
+    Ok(strategy)
+}
+
+enum TempError {
+    /// The temp file couldn't be created or renamed, so rewriting in place is worth a try
+    Unavailable(std::io::Error),
+
+    /// Writing the contents failed, which would fail in place too
+    Write(eyre::Report),
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.atuin-tmp"))
+}
+
+/// Where a rewrite's temp file is written, so tests can fail it part way
+trait TempFile: Write {
+    fn sync_all(&self) -> std::io::Result<()>;
+}
+
+impl TempFile for File {
+    fn sync_all(&self) -> std::io::Result<()> {
+        File::sync_all(self)
+    }
+}
+
+fn replace_with_temp<W: TempFile>(
+    path: &Path,
+    temp_path: &Path,
+    file: &File,
+    content: &str,
+    wrap: impl FnOnce(File) -> W,
+) -> Result<(), TempError> {
+    // the lock is held, so a temp file left over from a crash is safe to reuse
+    let temp = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(temp_path)
+        .map_err(TempError::Unavailable)?;
+
+    let permissions = file
+        .metadata()
+        .context("failed to read fish history file permissions")
+        .map_err(TempError::Write)?
+        .permissions();
+
+    temp.set_permissions(permissions)
+        .context("failed to set fish history temp file permissions")
+        .map_err(TempError::Write)?;
+
+    // the contents have to be on disk before the rename, or a crash could leave the renamed file
+    // empty
+    let mut temp = wrap(temp);
+    temp.write_all(content.as_bytes())
+        .and_then(|()| temp.flush())
+        .and_then(|()| temp.sync_all())
+        .context("failed to write fish history temp file")
+        .map_err(TempError::Write)?;
+    drop(temp);
+
+    std::fs::rename(temp_path, path).map_err(TempError::Unavailable)?;
+
+    // the rename itself only survives a crash once the directory is synced too
+    if let Err(e) = sync_dir(path) {
+        log::warn!(
+            "failed to sync the directory of {} after a rewrite: {e}",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories can't be opened to sync them here, and renames are durable without it
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn rewrite_in_place(file: &mut File, original: &str, content: &str) -> Result<()> {
+    let overwrite = |file: &mut File, content: &str| -> std::io::Result<()> {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(content.as_bytes())?;
+        file.set_len(content.len() as u64)?;
+        file.flush()
+    };
+
+    if let Err(e) = overwrite(file, content) {
+        if let Err(restore) = overwrite(file, original) {
+            log::error!("failed to restore fish history file after a failed rewrite: {restore}");
+        }
+
+        return Err(e).context("failed to rewrite fish history file");
+    }
+
+    Ok(())
+}
+
+/// Whether `file` is still the file at `path`, rather than one a rewrite renamed something over
+#[cfg(unix)]
+fn is_current(path: &Path, file: &File) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let open = file
+        .metadata()
+        .context("failed to read fish history file metadata")?;
+
+    Ok(match std::fs::metadata(path) {
+        Ok(current) => current.dev() == open.dev() && current.ino() == open.ino(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e).context("failed to read fish history file metadata"),
+    })
+}
+
+/// Files can't be renamed over while open here, so the open file is always the current one
+#[cfg(not(unix))]
+fn is_current(_path: &Path, _file: &File) -> Result<bool> {
+    Ok(true)
+}
+
+/// One entry as it appears in the file
+#[derive(Debug)]
+pub(crate) struct RawEntry<'a> {
+    /// The entry's full text, including its trailing newline
+    pub text: &'a str,
+
+    /// The escaped command
+    pub cmd: &'a str,
+
+    pub when: Option<i64>,
+
+    pub uuid: Option<&'a str>,
+
+    /// The paths fish recorded the command as using, as written
+    pub paths: Vec<&'a str>,
+
+    /// Comment lines other tools added, verbatim and in order
+    pub comments: Vec<&'a str>,
+
+    /// Anything wrong with the entry that parsing skipped over, with where in the content it is
+    pub issues: Vec<ParseIssue>,
+}
+
+/// Split a history file into anything before the first entry, and the entries themselves
+///
+/// Only a line that *starts* with `- cmd:` begins an entry, so commands that merely contain that
+/// text don't split an entry in two.
+pub(crate) fn split_entries(content: &str) -> (&str, Vec<RawEntry<'_>>) {
+    // the byte offset and line number of each entry's first line
+    let mut starts = Vec::new();
+    let mut offset = 0;
+
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        if line.starts_with("- cmd:") {
+            starts.push((offset, i + 1));
+        }
+        offset += line.len();
+    }
+
+    let preamble = &content[..starts.first().map_or(content.len(), |&(start, _)| start)];
+
+    let entries = starts
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, line))| {
+            let end = starts.get(i + 1).map_or(content.len(), |&(end, _)| end);
+            parse_raw_entry(&content[start..end], start, line)
+        })
+        .collect();
+
+    (preamble, entries)
+}
+
+fn parse_raw_entry(text: &str, offset: usize, line: usize) -> RawEntry<'_> {
+    // each line with its offset and number, without its line ending, as `str::lines` gives them
+    let mut lines = text
+        .split_inclusive('\n')
+        .enumerate()
+        .scan(offset, move |next, (i, raw)| {
+            let start = *next;
+            *next += raw.len();
+
+            let trimmed = raw.strip_suffix('\n').unwrap_or(raw);
+            let trimmed = trimmed.strip_suffix('\r').unwrap_or(trimmed);
+            Some((start, line + i, trimmed))
+        });
+
+    let first = lines.next().map_or("", |(_, _, first)| first);
+    let cmd = first
+        .strip_prefix("- cmd:")
+        .map(|cmd| cmd.strip_prefix(' ').unwrap_or(cmd))
+        .unwrap_or_default();
+
+    let mut issues = Vec::new();
+    if cmd.trim().is_empty() {
+        issues.push(ParseIssue::new(
+            ParseIssueKind::EmptyCommand,
+            offset,
+            line,
+            first,
+        ));
+    }
+
+    let mut when = None;
+    let mut when_line = None;
+    let mut uuid = None;
+    let mut paths = Vec::new();
+    let mut comments = Vec::new();
+    let mut in_paths = false;
+
+    // fields fish doesn't know yet are skipped, so a newer fish's files still parse
+    for (line_offset, line_number, line) in lines {
+        if !line.is_empty() && !line.starts_with(char::is_whitespace) {
+            issues.push(ParseIssue::new(
+                ParseIssueKind::UnexpectedLine,
+                line_offset,
+                line_number,
+                line,
+            ));
+            continue;
+        }
+
+        if in_paths && let Some(path) = line.strip_prefix("    - ") {
+            paths.push(path);
+            continue;
+        }
+        in_paths = false;
+
+        if line.len() > MAX_COMMENT_LINE && is_comment_line(line) {
+            log::warn!(
+                "ignoring a {} byte comment line in a fish history entry",
+                line.len()
+            );
+            continue;
+        }
+
+        if let Some(ts) = line.strip_prefix("  when:") {
+            when = ts.trim().parse().ok();
+            when_line = Some((line_offset, line_number, line));
+        } else if line.trim_end() == "  paths:" {
+            in_paths = true;
+        } else if let Some(id) = parse_uuid_line(line) {
+            uuid = Some(id);
+        } else if is_comment_line(line) && parse_metadata_line(line).is_none() {
+            comments.push(line);
+        }
+    }
+
+    match (when, when_line) {
+        (None, Some((line_offset, line_number, line))) => issues.push(ParseIssue::new(
+            ParseIssueKind::InvalidTimestamp,
+            line_offset,
+            line_number,
+            line,
+        )),
+        (None, None) => issues.push(ParseIssue::new(
+            ParseIssueKind::MissingTimestamp,
+            offset,
+            line,
+            first,
+        )),
+        _ => {}
+    }
+
+    RawEntry {
+        text,
+        cmd,
+        when,
+        uuid,
+        paths,
+        comments,
+        issues,
+    }
+}
+
+/// Everything needed to decide whether an entry is already in the file
+///
+/// Built in one pass over the file, collecting uuids and command and timestamp pairs together,
+/// so callers checking many entries read the file once rather than once per entry. Commands are
+/// compared unescaped, since that's how they're stored in the database.
+#[derive(Debug, Default)]
+pub struct FishDedupIndex {
+    uuids: HashSet<String>,
+    commands: HashSet<(String, i64)>,
+
+    /// How many entries the file holds
+    entries: usize,
+
+    /// The oldest timestamp in the file
+    oldest: Option<i64>,
+
+    /// What Atuin wrote to the file, whether or not fish kept it as written
+    sidecar: Sidecar,
+}
+
+impl FishDedupIndex {
+    fn build(content: &str) -> Self {
+        let mut index = Self::default();
+
+        for entry in split_entries(content).1 {
+            index.insert_raw(&entry);
+        }
+
+        index
+    }
+
+    fn insert_raw(&mut self, entry: &RawEntry<'_>) {
+        self.entries += 1;
+
+        // written by older versions, possibly in another spelling
+        if let Some(uuid) = entry.uuid {
+            self.uuids.insert(canonical_id(uuid));
+        }
+
+        if let Some(when) = entry.when {
+            self.commands.insert((unescape_fish_cmd(entry.cmd), when));
+            self.oldest = Some(self.oldest.map_or(when, |oldest| oldest.min(when)));
+        }
+    }
+
+    /// Whether the file has this entry, by uuid, by command and timestamp, or because Atuin
+    /// wrote it there before and fish has since changed it
+    pub fn contains(&self, entry: &CommandEntry) -> bool {
+        if let Some(uuid) = &entry.uuid
+            && self.uuids.contains(&canonical_id(uuid))
+        {
+            return true;
+        }
+
+        self.commands
+            .contains(&(entry.command.clone(), entry.timestamp.unix_timestamp()))
+            || self.sidecar.contains(entry)
+    }
+
+    /// Whether an entry in the file has this uuid, in any spelling
+    pub fn contains_uuid(&self, uuid: &str) -> bool {
+        self.uuids.contains(&canonical_id(uuid))
+    }
+
+    /// How many entries the file holds
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    fn insert(&mut self, entry: &CommandEntry) {
+        self.entries += 1;
+        self.sidecar.record(entry);
+
+        if let Some(uuid) = &entry.uuid {
+            self.uuids.insert(canonical_id(uuid));
+        }
+
+        self.commands
+            .insert((entry.command.clone(), entry.timestamp.unix_timestamp()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FishFileBuilder, HistoryBuilder, assert_file_parses};
+
+    fn entry(cmd: &str, ts: i64) -> CommandEntry {
+        CommandEntry::new(cmd, OffsetDateTime::from_unix_timestamp(ts).unwrap())
+    }
+
+    fn syncer(dir: &tempfile::TempDir) -> FishSyncer {
+        FishSyncer::open(dir.path().join("fish_history"), FishSyncOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_append_and_contains() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        assert!(!syncer.contains("abc").unwrap());
+
+        let written = syncer
+            .append(&[entry("ls", 1).with_uuid("abc"), entry("pwd", 2)])
+            .unwrap();
+        assert_eq!(written, 2);
+
+        assert!(syncer.contains("abc").unwrap());
+        assert!(!syncer.contains("def").unwrap());
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        assert_eq!(
+            content,
+            FishFileBuilder::new()
+                .atuin("ls", 1, "abc")
+                .native("pwd", 2)
+                .build()
+        );
+    }
+
+    #[test]
+    fn test_append_dedups_by_uuid_and_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        syncer.append(&[entry("ls", 1).with_uuid("abc")]).unwrap();
+
+        // same uuid, even with a different command
+        assert_eq!(
+            syncer
+                .append(&[entry("ls -la", 5).with_uuid("abc")])
+                .unwrap(),
+            0
+        );
+
+        // same command and timestamp, no uuid
+        assert_eq!(syncer.append(&[entry("ls", 1)]).unwrap(), 0);
+
+        // repeats within a single batch
+        assert_eq!(syncer.append(&[entry("cd", 3), entry("cd", 3)]).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_dedup_escaped_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        let multiline = entry("echo a \\\n  b", 1);
+        assert_eq!(syncer.append(std::slice::from_ref(&multiline)).unwrap(), 1);
+        assert_eq!(syncer.append(&[multiline]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_unescape_round_trip() {
+        for cmd in [
+            "ls",
+            "echo a\nb",
+            r"C:\Users\test",
+            "printf '\\n'\n",
+            "trailing \\",
+        ] {
+            assert_eq!(unescape_fish_cmd(&escape_fish_cmd(cmd)), cmd);
+        }
+    }
+
+    #[test]
+    fn test_dedup_multiline_after_fish_strips_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        let entries = [
+            entry("for f in *\n  echo $f\nend", 1).with_uuid("a"),
+            entry("ls", 2).with_uuid("b"),
+        ];
+        assert_eq!(syncer.append(&entries).unwrap(), 2);
+
+        // fish drops lines it doesn't know when it rewrites its history file
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        let stripped: String = content
+            .lines()
+            .filter(|line| parse_uuid_line(line).is_none())
+            .map(|line| format!("{line}\n"))
+            .collect();
+        fs_err::write(syncer.path(), stripped).unwrap();
+        assert!(!syncer.contains("a").unwrap());
+
+        // syncing the same entries again must not duplicate either of them
+        assert_eq!(syncer.append(&entries).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_append_after_missing_trailing_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        fs_err::write(syncer.path(), "- cmd:ls\n  when:1700000000").unwrap();
+        assert_eq!(
+            syncer
+                .append(&[entry("git status", 1_700_000_001)])
+                .unwrap(),
+            1
+        );
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        assert_eq!(
+            content,
+            "- cmd:ls\n  when:1700000000\n- cmd:git status\n  when:1700000001\n"
+        );
+
+        let parsed = FishHistoryEntry::parse(&content);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].when, Some(1_700_000_000));
+        assert_eq!(parsed[1].command, "git status");
+        assert!(FishHistoryEntry::issues(&content).is_empty());
+
+        // nothing extra once the file ends properly
+        assert_eq!(syncer.append(&[entry("pwd", 1_700_000_002)]).unwrap(), 1);
+        assert!(
+            !fs_err::read_to_string(syncer.path())
+                .unwrap()
+                .contains("\n\n")
+        );
+    }
+
+    #[test]
+    fn test_no_duplicates_after_fish_merge_rewrites_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        let entries = [
+            entry("ls", 1).with_uuid("a"),
+            entry("cargo build", 2).with_uuid("b"),
+            entry("git status", 3).with_uuid("c"),
+        ];
+        assert_eq!(syncer.append(&entries).unwrap(), 3);
+
+        // `history merge` writes the file back without our comments, keeping only the newest copy
+        // of a command run again since, so nothing in the file shows `ls` at 1 was ever synced
+        fs_err::write(
+            syncer.path(),
+            "- cmd:cargo build\n  when:2\n- cmd:git status\n  when:3\n- cmd:ls\n  when:10\n",
+        )
+        .unwrap();
+
+        assert_eq!(syncer.append(&entries).unwrap(), 0);
+        assert_eq!(syncer.append(&[entry("ls", 11).with_uuid("d")]).unwrap(), 1);
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        let parsed = FishHistoryEntry::parse(&content);
+        let mut seen = HashSet::new();
+        for entry in &parsed {
+            assert!(
+                seen.insert((entry.command.clone(), entry.when)),
+                "{content}"
+            );
+        }
+        assert_eq!(parsed.len(), 4);
+
+        // trimming prunes the sidecar with the file, so what it forgets is gone from both
+        assert_eq!(syncer.trim(2).unwrap(), 2);
+        let sidecar = Sidecar::load(syncer.path()).unwrap();
+        assert_eq!(sidecar.len(), 1);
+        assert!(sidecar.contains(&entry("ls", 11)));
+
+        // and a file started over is synced from scratch
+        fs_err::remove_file(syncer.path()).unwrap();
+        assert_eq!(syncer.append(&entries).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_trim_keeps_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        let entries: Vec<_> = (0..5).map(|i| entry(&format!("cmd {i}"), i)).collect();
+        syncer.append(&entries).unwrap();
+
+        assert_eq!(syncer.trim(2).unwrap(), 3);
+        assert_eq!(syncer.trim(2).unwrap(), 0);
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        assert_eq!(content, "- cmd:cmd 3\n  when:3\n- cmd:cmd 4\n  when:4\n");
+    }
+
+    #[test]
+    fn test_trim_with_age_and_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        let entries: Vec<_> = (0..10)
+            .map(|i| entry(&format!("cmd {i}"), i * 86_400))
+            .collect();
+        syncer.append(&entries).unwrap();
+        let now = OffsetDateTime::from_unix_timestamp(9 * 86_400).unwrap();
+
+        // a dry run reports, but leaves the file alone
+        let limits = TrimLimits {
+            max_age: Some(Duration::from_secs(3 * 86_400)),
+            ..TrimLimits::default()
+        };
+        let report = syncer.trim_with(&limits, now, true).unwrap();
+        assert_eq!(report.entries_before, 10);
+        assert_eq!(report.entries_removed, 6);
+        assert!(report.bytes_reclaimed() > 0);
+        assert_eq!(crate::fish_sync::count_entries(syncer.path()).unwrap(), 10);
+
+        let report = syncer.trim_with(&limits, now, false).unwrap();
+        assert_eq!(report.entries_removed, 6);
+        let size = fs_err::metadata(syncer.path()).unwrap().len();
+        assert_eq!(size, report.bytes_after);
+
+        // every entry is the same size here, so room for two and a bit keeps two
+        let entry_size = size / 4;
+        let limits = TrimLimits {
+            max_bytes: Some(entry_size * 2 + 1),
+            ..TrimLimits::default()
+        };
+        let report = syncer.trim_with(&limits, now, false).unwrap();
+        assert_eq!(report.entries_removed, 2);
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        assert_eq!(
+            content,
+            "- cmd:cmd 8\n  when:691200\n- cmd:cmd 9\n  when:777600\n"
+        );
+    }
+
+    #[test]
+    fn test_trim_with_max_fraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        let entries: Vec<_> = (0..10).map(|i| entry(&format!("cmd {i}"), i)).collect();
+        syncer.append(&entries).unwrap();
+        let now = OffsetDateTime::now_utc();
+
+        let limits = TrimLimits {
+            max_entries: Some(1),
+            max_fraction: Some(0.8),
+            ..TrimLimits::default()
+        };
+
+        // a dry run still reports what it would do
+        let report = syncer.trim_with(&limits, now, true).unwrap();
+        assert_eq!(report.entries_removed, 9);
+
+        let err = syncer.trim_with(&limits, now, false).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TrimRefused>(),
+            Some(&TrimRefused {
+                entries_removed: 9,
+                entries_before: 10,
+            })
+        );
+        assert_eq!(crate::fish_sync::count_entries(syncer.path()).unwrap(), 10);
+
+        // checked against the file as it is when trimming, however it looked when planned
+        let limits = TrimLimits {
+            max_entries: Some(2),
+            max_fraction: Some(0.8),
+            ..TrimLimits::default()
+        };
+        assert_eq!(
+            syncer
+                .trim_with(&limits, now, true)
+                .unwrap()
+                .entries_removed,
+            8
+        );
+        let more: Vec<_> = (10..20).map(|i| entry(&format!("cmd {i}"), i)).collect();
+        syncer.append(&more).unwrap();
+        assert!(syncer.trim_with(&limits, now, false).is_err());
+
+        let limits = TrimLimits {
+            max_fraction: None,
+            ..limits
+        };
+        assert_eq!(
+            syncer
+                .trim_with(&limits, now, false)
+                .unwrap()
+                .entries_removed,
+            18
+        );
+    }
+
+    #[test]
+    fn test_append_trims_with_max_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = FishSyncer::open(
+            dir.path().join("fish_history"),
+            FishSyncOptions {
+                max_entries: 3,
+                ..FishSyncOptions::default()
+            },
+        )
+        .unwrap();
+
+        for i in 0..5 {
+            syncer.append(&[entry(&format!("cmd {i}"), i)]).unwrap();
+        }
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        let (_, entries) = split_entries(&content);
+        let commands: Vec<_> = entries.iter().map(|e| e.cmd).collect();
+        assert_eq!(commands, vec!["cmd 2", "cmd 3", "cmd 4"]);
+    }
+
+    #[test]
+    fn test_skip_older_than_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = FishSyncer::open(
+            dir.path().join("fish_history"),
+            FishSyncOptions {
+                max_entries: 3,
+                skip_older_than_window: true,
+                ..FishSyncOptions::default()
+            },
+        )
+        .unwrap();
+
+        // not full yet, so older entries still go in
+        syncer
+            .append(&[entry("cmd 10", 10), entry("cmd 20", 20)])
+            .unwrap();
+        let report = syncer.append_with_report(&[entry("cmd 5", 5)]).unwrap();
+        assert_eq!(report.written, 1);
+
+        // full now, and older than everything in it
+        let report = syncer
+            .append_with_report(&[entry("cmd 1", 1), entry("cmd 30", 30), entry("cmd 10", 10)])
+            .unwrap();
+        assert_eq!(
+            report,
+            AppendReport {
+                written: 1,
+                duplicates: 1,
+                too_old: 1,
+            }
+        );
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        assert!(!content.contains("cmd 1\n"));
+        assert!(content.contains("cmd 30"));
+    }
+
+    #[test]
+    fn test_append_trims_to_max_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // each of these is 21 bytes: "- cmd:cmd N\n  when:N\n"
+        let entries: Vec<_> = (0..10).map(|i| entry(&format!("cmd {i}"), i)).collect();
+        assert_eq!(entries[0].to_fish().len(), 21);
+
+        let syncer = FishSyncer::open(
+            dir.path().join("fish_history"),
+            FishSyncOptions {
+                max_bytes: Some(70),
+                ..FishSyncOptions::default()
+            },
+        )
+        .unwrap();
+        syncer.append(&entries).unwrap();
+
+        let kept: Vec<_> = syncer
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.command)
+            .collect();
+        assert_eq!(kept, vec!["cmd 7", "cmd 8", "cmd 9"]);
+        assert_eq!(fs_err::metadata(syncer.path()).unwrap().len(), 63);
+
+        // with max_entries too, the stricter one wins
+        let syncer = FishSyncer::open(
+            dir.path().join("fish_history_both"),
+            FishSyncOptions {
+                max_entries: 2,
+                max_bytes: Some(70),
+                ..FishSyncOptions::default()
+            },
+        )
+        .unwrap();
+        syncer.append(&entries).unwrap();
+        assert_eq!(syncer.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_giant_newest_entry_is_kept() {
+        let giant = format!("echo {}", "x".repeat(500));
+        let content = format!(
+            "{}{}",
+            entry("small", 1).to_fish(),
+            entry(&giant, 2).to_fish()
+        );
+
+        let limits = TrimLimits {
+            max_bytes: Some(100),
+            ..TrimLimits::default()
+        };
+        let (trimmed, report) = plan_trim(&content, &limits, OffsetDateTime::now_utc());
+
+        assert_eq!(report.entries_removed, 1);
+        assert_eq!(trimmed, entry(&giant, 2).to_fish());
+
+        // but a giant older entry goes
+        let content = format!(
+            "{}{}",
+            entry(&giant, 1).to_fish(),
+            entry("small", 2).to_fish()
+        );
+        let (trimmed, _) = plan_trim(&content, &limits, OffsetDateTime::now_utc());
+        assert_eq!(trimmed, entry("small", 2).to_fish());
+    }
+
+    #[test]
+    fn test_rewrite_strategies() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        let path = syncer.path().to_path_buf();
+
+        let entries: Vec<_> = (0..5).map(|i| entry(&format!("cmd {i}"), i)).collect();
+        syncer.append(&entries).unwrap();
+        let original = fs_err::read_to_string(&path).unwrap();
+
+        let mut file = syncer.open_locked().unwrap();
+        let strategy = rewrite_locked(&path, &mut file, &original, "- cmd:a\n").unwrap();
+        assert_eq!(strategy, RewriteStrategy::Atomic);
+        assert!(!temp_path(&path).exists());
+        drop(file);
+        assert_eq!(fs_err::read_to_string(&path).unwrap(), "- cmd:a\n");
+
+        // a directory in the way stands in for one that doesn't allow new files, which root
+        // would ignore
+        fs_err::create_dir(temp_path(&path)).unwrap();
+
+        let mut file = syncer.open_locked().unwrap();
+        let strategy = rewrite_locked(&path, &mut file, "- cmd:a\n", "- cmd:b\n").unwrap();
+        assert_eq!(strategy, RewriteStrategy::InPlace);
+        drop(file);
+        assert_eq!(fs_err::read_to_string(&path).unwrap(), "- cmd:b\n");
+
+        // trimming keeps working too
+        let more: Vec<_> = (5..10).map(|i| entry(&format!("cmd {i}"), i)).collect();
+        syncer.append(&more).unwrap();
+        assert_eq!(syncer.trim(2).unwrap(), 4);
+        assert_eq!(syncer.entries().unwrap().len(), 2);
+    }
+
+    /// Writes the first `limit` bytes it's given, then fails like a full disk
+    struct FailingWriter {
+        inner: File,
+        limit: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.limit == 0 {
+                return Err(std::io::Error::other("no space left on device"));
+            }
+
+            let n = buf.len().min(self.limit);
+            self.limit -= n;
+            self.inner.write(&buf[..n])
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl TempFile for FailingWriter {
+        fn sync_all(&self) -> std::io::Result<()> {
+            self.inner.sync_all()
+        }
+    }
+
+    #[test]
+    fn test_failed_rewrite_leaves_original_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        let path = syncer.path().to_path_buf();
+
+        let entries: Vec<_> = (0..5).map(|i| entry(&format!("cmd {i}"), i)).collect();
+        syncer.append(&entries).unwrap();
+        let original = fs_err::read_to_string(&path).unwrap();
+
+        let mut file = syncer.open_locked().unwrap();
+        let result =
+            rewrite_locked_with(&path, &mut file, &original, "- cmd:a\n- cmd:b\n", |inner| {
+                FailingWriter { inner, limit: 4 }
+            });
+        drop(file);
+
+        assert!(result.is_err());
+        assert_eq!(fs_err::read_to_string(&path).unwrap(), original);
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rewrite_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        let path = syncer.path().to_path_buf();
+
+        syncer.append(&[entry("first", 1)]).unwrap();
+        fs_err::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        let mut file = syncer.open_locked().unwrap();
+        let strategy = rewrite_locked(&path, &mut file, "", "- cmd:second\n").unwrap();
+        drop(file);
+
+        assert_eq!(strategy, RewriteStrategy::Atomic);
+        let mode = fs_err::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_waiting_writer_follows_rewrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        syncer.append(&[entry("first", 1)]).unwrap();
+
+        // a handle opened before the rename no longer counts as the file
+        let stale = File::open(syncer.path()).unwrap();
+        let mut file = syncer.open_locked().unwrap();
+        rewrite_locked(syncer.path(), &mut file, "", "- cmd:second\n").unwrap();
+        drop(file);
+
+        assert!(!is_current(syncer.path(), &stale).unwrap());
+        syncer.append(&[entry("third", 3)]).unwrap();
+
+        let commands: Vec<_> = syncer
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.command)
+            .collect();
+        assert_eq!(commands, vec!["second", "third"]);
+    }
+
+    #[test]
+    fn test_uuid_spellings_are_equivalent() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        let hyphenated = "0190B1A2-7C4E-7000-8000-000000000001";
+        let simple = "0190b1a27c4e70008000000000000001";
+
+        // as older versions wrote it
+        FishFileBuilder::new()
+            .atuin("ls", 1, hyphenated)
+            .write(syncer.path());
+
+        assert!(syncer.contains(simple).unwrap());
+        assert!(syncer.contains(hyphenated).unwrap());
+
+        let mut moved = entry("ls -l", 2);
+        moved.uuid = Some(simple.to_string());
+        assert_eq!(syncer.append(&[moved]).unwrap(), 0);
+
+        let from_history = CommandEntry::from(&History {
+            id: hyphenated.to_string().into(),
+            ..HistoryBuilder::new("ls").build()
+        });
+        assert_eq!(from_history.uuid.as_deref(), Some(simple));
+        assert_eq!(syncer.append(&[from_history]).unwrap(), 0);
+
+        assert_eq!(syncer.count_by_uuid(&[simple.to_string()]).unwrap(), 1);
+        assert_eq!(syncer.remove_by_uuid(&[simple.to_string()]).unwrap(), 1);
+        assert!(syncer.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_by_uuid() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        syncer
+            .append(&[
+                entry("ls", 1).with_uuid("a"),
+                entry("pwd", 2),
+                entry("cd", 3).with_uuid("b"),
+                entry("top", 4).with_uuid("c"),
+            ])
+            .unwrap();
+
+        let uuids = vec!["a".to_string(), "c".to_string(), "missing".to_string()];
+        assert_eq!(syncer.count_by_uuid(&uuids).unwrap(), 2);
+        assert_eq!(syncer.remove_by_uuid(&uuids).unwrap(), 2);
+        assert_eq!(syncer.count_by_uuid(&uuids).unwrap(), 0);
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        assert_eq!(
+            content,
+            FishFileBuilder::new()
+                .native("pwd", 2)
+                .atuin("cd", 3, "b")
+                .build()
+        );
+    }
+
+    /// Another tool's comments, attached to the entries they follow
+    fn third_party_fixture() -> String {
+        FishFileBuilder::new()
+            .native("ls", 1)
+            .raw("  # fork-host:laptop\n")
+            .atuin("pwd", 2, "a")
+            .raw("  # fork-host:desktop\n  # note: keep me\n")
+            .native("ls", 1)
+            .raw("  # fork-host:laptop\n")
+            .atuin("pwd", 3, "a")
+            .native("cd", 4)
+            .raw("  # fork-host:server\n")
+            .build()
+    }
+
+    #[test]
+    fn test_audit_source_is_tagged_and_strippable() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = FishSyncer::open(
+            dir.path().join("fish_history"),
+            FishSyncOptions {
+                audit_source: Some(WriteSource::Daemon),
+                ..FishSyncOptions::default()
+            },
+        )
+        .unwrap();
+
+        let ls = entry("ls", 1).with_uuid("a");
+        assert_eq!(syncer.append(std::slice::from_ref(&ls)).unwrap(), 1);
+        assert_eq!(syncer.append(&[ls]).unwrap(), 0);
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        assert_eq!(
+            content,
+            FishFileBuilder::new()
+                .atuin("ls", 1, "a")
+                .raw(&metadata_line(ATUIN_SRC_KEY, "daemon"))
+                .build()
+        );
+        assert!(syncer.entries().unwrap()[0].comments.is_empty());
+
+        assert_eq!(syncer.strip_metadata().unwrap(), 2);
+        assert_eq!(
+            fs_err::read_to_string(syncer.path()).unwrap(),
+            FishFileBuilder::new().native("ls", 1).build()
+        );
+    }
+
+    #[test]
+    fn test_third_party_comments_are_parsed() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        fs_err::write(syncer.path(), third_party_fixture()).unwrap();
+
+        let entries = syncer.entries().unwrap();
+        assert_eq!(entries[0].comments, vec!["  # fork-host:laptop"]);
+        assert_eq!(entries[1].uuid.as_deref(), Some("a"));
+        assert_eq!(
+            entries[1].comments,
+            vec!["  # fork-host:desktop", "  # note: keep me"]
+        );
+        assert_eq!(entries[4].comments, vec!["  # fork-host:server"]);
+    }
+
+    #[test]
+    fn test_dedupe_preserves_third_party_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        let fixture = third_party_fixture();
+        fs_err::write(syncer.path(), &fixture).unwrap();
+
+        // the second ls matches on command and timestamp, the second pwd on uuid
+        assert_eq!(syncer.dedupe().unwrap(), 2);
+        assert_eq!(syncer.dedupe().unwrap(), 0);
+
+        let (_, entries) = split_entries(&fixture);
+        let expected: String = [0, 1, 4].iter().map(|&i| entries[i].text).collect();
+        assert_eq!(fs_err::read_to_string(syncer.path()).unwrap(), expected);
+    }
+
+    /// What fish leaves behind when it rewrites an entry Atuin annotated: the annotation ends up
+    /// under a neighbouring entry with a different command, and a stray one before the first entry
+    fn misassociated_fixture() -> String {
+        FishFileBuilder::new()
+            .raw(&metadata_line(ATUIN_UUID_KEY, "a"))
+            .atuin("ls", 1, "a")
+            .atuin("rm -rf target", 2, "a")
+            .raw("  # fork-host:laptop\n")
+            .native("cd", 3)
+            .build()
+    }
+
+    #[test]
+    fn test_dedupe_needs_matching_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        fs_err::write(syncer.path(), misassociated_fixture()).unwrap();
+
+        // same uuid, but a different command, so not a copy
+        assert_eq!(syncer.dedupe().unwrap(), 0);
+        assert_eq!(syncer.entries().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_dedup_index_tail_reads_from_the_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        // a few times the first chunk read, so it has to go back further
+        FishFileBuilder::new()
+            .raw("# written by hand\n")
+            .many_native(20_000, 1)
+            .atuin("make", 30_000, "u1")
+            .write(syncer.path());
+
+        let index = syncer.dedup_index_tail(15_000).unwrap();
+        assert_eq!(index.len(), 15_000);
+        assert!(index.contains_uuid("u1"));
+        assert!(index.contains(&entry("command 19998", 19_999)));
+        assert!(index.contains(&entry("command 5001", 5_002)));
+        assert!(!index.contains(&entry("command 5000", 5_001)));
+
+        // asking for more than the file has reads all of it
+        assert_eq!(syncer.dedup_index_tail(50_000).unwrap().len(), 20_001);
+        assert!(syncer.dedup_index_tail(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dedupe_commands_keeps_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        FishFileBuilder::new()
+            .atuin("make", 10, "u1")
+            .native("ls", 15)
+            .atuin("make", 30, "u2")
+            .native("make", 20)
+            .atuin("ls", 5, "u3")
+            .raw("- cmd:make\n")
+            .write(syncer.path());
+
+        let before = fs_err::read_to_string(syncer.path()).unwrap();
+        let report = syncer.dedupe_commands(false, true).unwrap();
+        assert_eq!(report.removed, 3);
+        assert_eq!(fs_err::read_to_string(syncer.path()).unwrap(), before);
+
+        assert_eq!(syncer.dedupe_commands(false, false).unwrap().removed, 3);
+        assert_eq!(syncer.dedupe_commands(false, false).unwrap().removed, 0);
+
+        // fish's own ls beats Atuin's older one, the newest make keeps its uuid, and the entry
+        // without a timestamp is left alone
+        let kept: Vec<_> = syncer
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.command, entry.when, entry.uuid))
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                ("ls".to_string(), Some(15), None),
+                ("make".to_string(), Some(30), Some("u2".to_string())),
+                ("make".to_string(), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedupe_commands_whitespace() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        FishFileBuilder::new()
+            .native("git  status", 10)
+            .native("git status", 20)
+            .native("git status ", 30)
+            .write(syncer.path());
+
+        assert_eq!(syncer.dedupe_commands(false, false).unwrap().removed, 0);
+        assert_eq!(syncer.dedupe_commands(true, false).unwrap().removed, 2);
+
+        let entries = syncer.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "git status ");
+    }
+
+    #[test]
+    fn test_sort_is_stable() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        FishFileBuilder::new()
+            .native("c", 30)
+            .atuin("a1", 10, "u1")
+            .native_with_paths("p", 20, &["x"])
+            .native("a2", 10)
+            .raw("- cmd:no time\n")
+            .raw("- cmd:last\n  when:5")
+            .write(syncer.path());
+        let before = fs_err::read_to_string(syncer.path()).unwrap();
+
+        assert_eq!(syncer.sort(true).unwrap(), 5);
+        assert_eq!(fs_err::read_to_string(syncer.path()).unwrap(), before);
+
+        // entries move whole, and ties keep their order
+        assert_eq!(syncer.sort(false).unwrap(), 5);
+        assert_file_parses(syncer.path());
+        assert_eq!(
+            fs_err::read_to_string(syncer.path()).unwrap(),
+            FishFileBuilder::new()
+                .raw("- cmd:no time\n")
+                .raw("- cmd:last\n  when:5\n")
+                .atuin("a1", 10, "u1")
+                .native("a2", 10)
+                .native_with_paths("p", 20, &["x"])
+                .native("c", 30)
+                .build()
+        );
+        assert_eq!(syncer.sort(false).unwrap(), 0);
+
+        // the same entries are still recognised as written
+        assert!(syncer.contains("u1").unwrap());
+        let written = syncer
+            .append(&[
+                entry("a1", 10).with_uuid("u1"),
+                entry("a2", 10),
+                entry("c", 30),
+            ])
+            .unwrap();
+        assert_eq!(written, 0);
+    }
+
+    #[test]
+    fn test_keep_sorted_sorts_after_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = FishSyncer::open(
+            dir.path().join("fish_history"),
+            FishSyncOptions {
+                keep_sorted: true,
+                ..FishSyncOptions::default()
+            },
+        )
+        .unwrap();
+        FishFileBuilder::new()
+            .native("b", 20)
+            .native("a", 10)
+            .write(syncer.path());
+
+        syncer.append(&[entry("c", 15), entry("d", 40)]).unwrap();
+
+        let commands: Vec<_> = syncer
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+        assert_eq!(commands, ["a", "c", "b", "d"]);
+    }
+
+    #[test]
+    fn test_reconcile_judges_uuid_with_its_own_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        let fixture = misassociated_fixture();
+        fs_err::write(syncer.path(), &fixture).unwrap();
+
+        let mut seen = Vec::new();
+        let report = syncer
+            .reconcile(false, |uuid, command, _| {
+                seen.push((uuid.to_string(), command.to_string()));
+                if command == "ls" {
+                    Reconcile::Keep
+                } else {
+                    Reconcile::DropAnnotations
+                }
+            })
+            .unwrap();
+
+        // the stray annotation in the preamble never reaches an entry
+        assert_eq!(
+            seen,
+            vec![
+                ("a".to_string(), "ls".to_string()),
+                ("a".to_string(), "rm -rf target".to_string()),
+            ]
+        );
+        assert_eq!(
+            report,
+            RemovalReport {
+                checked: 2,
+                removed: 0,
+                unannotated: 1,
+                rewritten: 0,
+                annotated: 0,
+                bytes_removed: metadata_line(ATUIN_UUID_KEY, "a").len() as u64,
+            }
+        );
+
+        let entries = syncer.entries().unwrap();
+        assert_eq!(entries[0].uuid.as_deref(), Some("a"));
+        assert_eq!(entries[1].command, "rm -rf target");
+        assert_eq!(entries[1].uuid, None);
+        assert_eq!(entries[1].comments, vec!["  # fork-host:laptop"]);
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        assert!(content.starts_with(&metadata_line(ATUIN_UUID_KEY, "a")));
+    }
+
+    #[test]
+    fn test_remove_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        let fixture = third_party_fixture();
+        let (_, entries) = split_entries(&fixture);
+        let size = |indices: &[usize]| indices.iter().map(|&i| entries[i].text.len() as u64).sum();
+
+        fs_err::write(syncer.path(), &fixture).unwrap();
+        let report = syncer
+            .remove_entries(EntryMatch::Uuids(&["a".to_string()]), true)
+            .unwrap();
+        assert_eq!(
+            report,
+            RemovalReport {
+                checked: 5,
+                removed: 2,
+                unannotated: 0,
+                rewritten: 0,
+                annotated: 0,
+                bytes_removed: size(&[1, 3]),
+            }
+        );
+        assert_eq!(fs_err::read_to_string(syncer.path()).unwrap(), fixture);
+
+        let report = syncer
+            .remove_entries(EntryMatch::Uuids(&["a".to_string()]), false)
+            .unwrap();
+        assert_eq!(report.removed, 2);
+
+        fs_err::write(syncer.path(), &fixture).unwrap();
+        let report = syncer
+            .remove_entries(EntryMatch::CommandsAt(&[("ls".to_string(), 1)]), false)
+            .unwrap();
+        assert_eq!(report.removed, 2);
+        assert_eq!(report.bytes_removed, size(&[0, 2]));
+
+        fs_err::write(syncer.path(), &fixture).unwrap();
+        let report = syncer
+            .remove_entries(
+                EntryMatch::Predicate(Box::new(|entry| {
+                    entry.comments.contains(&"  # fork-host:server".to_string())
+                })),
+                false,
+            )
+            .unwrap();
+        assert_eq!(report.removed, 1);
+
+        let expected: String = entries[..4].iter().map(|e| e.text).collect();
+        assert_eq!(fs_err::read_to_string(syncer.path()).unwrap(), expected);
+        assert_file_parses(syncer.path());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remove_entries_without_match_leaves_file_alone() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        fs_err::write(syncer.path(), third_party_fixture()).unwrap();
+        let inode = fs_err::metadata(syncer.path()).unwrap().ino();
+
+        for matching in [
+            EntryMatch::Uuids(&[]),
+            EntryMatch::Uuids(&["b".to_string()]),
+            EntryMatch::CommandsAt(&[("ls".to_string(), 2)]),
+            EntryMatch::Predicate(Box::new(|_| false)),
+        ] {
+            let report = syncer.remove_entries(matching, false).unwrap();
+            assert_eq!(report.removed, 0);
+            assert_eq!(report.bytes_removed, 0);
+        }
+
+        // a rewrite replaces the file, so the same inode means there was none
+        assert_eq!(fs_err::metadata(syncer.path()).unwrap().ino(), inode);
+        assert_eq!(
+            fs_err::read_to_string(syncer.path()).unwrap(),
+            third_party_fixture()
+        );
+    }
+
+    #[test]
+    fn test_trim_preserves_third_party_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        let fixture = third_party_fixture();
+        fs_err::write(syncer.path(), &fixture).unwrap();
+
+        assert_eq!(syncer.trim(3).unwrap(), 2);
+
+        let (_, entries) = split_entries(&fixture);
+        let expected: String = entries[2..].iter().map(|e| e.text).collect();
+        assert_eq!(fs_err::read_to_string(syncer.path()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_strip_metadata_only_removes_atuin_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        fs_err::write(syncer.path(), third_party_fixture()).unwrap();
+
+        assert_eq!(syncer.strip_metadata().unwrap(), 2);
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        let expected = third_party_fixture()
+            .lines()
+            .filter(|line| parse_uuid_line(line).is_none())
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+        assert_eq!(content, expected);
+        assert_eq!(content.matches("# fork-host:").count(), 4);
+        assert!(!syncer.contains("a").unwrap());
+        assert_file_parses(syncer.path());
+    }
+
+    #[test]
+    fn test_split_entries_only_at_line_start() {
+        let content = "- cmd:grep -- '- cmd:' fish_history\n  when:1\n- cmd:ls\n  when:2\n";
+        let (preamble, entries) = split_entries(content);
+
+        assert_eq!(preamble, "");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].cmd, "grep -- '- cmd:' fish_history");
+        assert_eq!(entries[1].when, Some(2));
+    }
+
+    #[test]
+    fn test_last_timestamp_ignores_lookalikes() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+        assert_eq!(syncer.last_timestamp().unwrap(), None);
+
+        // commands that look like a timestamp once escaped, or before
+        let entries = [
+            entry("ls", 100),
+            entry("echo 'x\n  when:999'", 200),
+            entry("printf '  when:999'", 300),
+        ];
+        syncer.append(&entries).unwrap();
+        assert_eq!(syncer.last_timestamp().unwrap(), Some(300));
+
+        for (content, expected) in [
+            // an entry fish is still writing
+            ("- cmd:ls\n  when:5\n- cmd:pwd\n", Some(5)),
+            // a hand edit that lost its indent
+            ("- cmd:ls\n  when:5\n- cmd:pwd\nwhen:999\n", Some(5)),
+            (
+                "- cmd:ls\n  when:5\n- cmd:pwd\n  paths:\n    -   when:999\n",
+                Some(5),
+            ),
+            ("- cmd:ls\n  when:later\n", None),
+            ("  when:7\n- cmd:ls\n", None),
+            ("- cmd:ls\n- cmd:  when:8\n", None),
+        ] {
+            fs_err::write(syncer.path(), content).unwrap();
+            assert_eq!(syncer.last_timestamp().unwrap(), expected, "{content:?}");
+        }
+    }
+
+    #[test]
+    fn test_trim_keeps_commands_containing_entry_markers_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let syncer = syncer(&dir);
+
+        let tricky = [
+            "grep -- '- cmd:' fish_history",
+            "printf '- cmd:x\n  when:1\n' >> fish_history",
+            "echo one\n- cmd: two",
+        ];
+        let mut entries = vec![entry("ls", 1), entry("pwd", 2)];
+        entries.extend(
+            tricky
+                .iter()
+                .enumerate()
+                .map(|(i, command)| entry(command, 3 + i as i64)),
+        );
+        assert_eq!(syncer.append(&entries).unwrap(), 5);
+
+        assert_eq!(syncer.trim(3).unwrap(), 2);
+
+        let content = fs_err::read_to_string(syncer.path()).unwrap();
+        let parsed = FishHistoryEntry::parse(&content);
+        let commands: Vec<&str> = parsed.iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, tricky);
+        assert!(FishHistoryEntry::issues(&content).is_empty());
+        assert_file_parses(syncer.path());
+    }
+}
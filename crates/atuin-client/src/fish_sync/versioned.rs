@@ -0,0 +1,287 @@
+//! Format versions for the state fish sync keeps on disk
+//!
+//! Every state file records the version of its format and the Atuin release that wrote it. Older
+//! formats are upgraded in place when they're read, one step at a time, keeping a copy of the
+//! original beside it. A format newer than this build knows is refused rather than guessed at,
+//! as misreading which ids were synced means either writing them all again or never writing some.
+
+use std::path::{Path, PathBuf};
+
+use eyre::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+/// The Atuin release this build is, as recorded in the state files it writes
+pub const ATUIN_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The key a state file's format version is stored under. Files without one are version 0.
+pub const VERSION_KEY: &str = "version";
+
+/// The key the release that last wrote a state file is stored under
+pub const WRITTEN_BY_KEY: &str = "written_by";
+
+/// Upgrades a state file's fields from one version to the next
+pub type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// One kind of state file, and how to bring its older versions up to date
+pub struct Format {
+    /// What the file holds, for messages
+    pub name: &'static str,
+
+    /// The version this build reads and writes
+    pub current: u32,
+
+    /// `migrations[n]` upgrades version `n` to `n + 1`, so there's one per version before
+    /// `current`
+    pub migrations: &'static [Migration],
+}
+
+/// A state file was written in a format newer than this build understands
+#[derive(Debug)]
+pub struct NewerFormat {
+    pub name: &'static str,
+    pub path: PathBuf,
+    pub version: u32,
+    pub supported: u32,
+    pub written_by: Option<String>,
+}
+
+impl std::fmt::Display for NewerFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {} is format {}, but atuin {ATUIN_VERSION} only understands up to {}",
+            self.name,
+            self.path.display(),
+            self.version,
+            self.supported
+        )?;
+
+        if let Some(written_by) = &self.written_by {
+            write!(f, ". It was written by atuin {written_by}")?;
+        }
+
+        write!(
+            f,
+            ". Upgrade atuin, or remove the file to have it rebuilt from the fish history file"
+        )
+    }
+}
+
+impl std::error::Error for NewerFormat {}
+
+impl Format {
+    /// Parse the state file at `path`, upgrading it in place first if it's an older version
+    ///
+    /// The original of an upgraded file is kept as `<name>.v<version>.bak`. Fails with
+    /// [`NewerFormat`] if the file is newer than this build, so callers can tell that apart from
+    /// a file that's merely unreadable.
+    pub fn load<T: DeserializeOwned>(&self, path: &Path, contents: &str) -> Result<T> {
+        let mut value: Value = serde_json::from_str(contents)
+            .with_context(|| format!("failed to parse {}", self.name))?;
+
+        let Some(fields) = value.as_object_mut() else {
+            eyre::bail!("failed to parse {}: not a JSON object", self.name);
+        };
+
+        let version = version_of(fields);
+        self.check_version(path, version, fields)?;
+
+        if version < self.current {
+            self.migrate(path, contents, version, fields)?;
+        }
+
+        serde_json::from_value(value).with_context(|| format!("failed to parse {}", self.name))
+    }
+
+    /// Fail if the state file at `path` is a newer version than this build writes, so writing
+    /// over it would lose what the newer release knew
+    pub fn ensure_writable(&self, path: &Path) -> Result<()> {
+        let Ok(contents) = fs_err::read_to_string(path) else {
+            return Ok(());
+        };
+
+        // an unreadable file is replaced like a missing one
+        let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(&contents) else {
+            return Ok(());
+        };
+
+        self.check_version(path, version_of(&fields), &fields)
+    }
+
+    fn check_version(&self, path: &Path, version: u32, fields: &Map<String, Value>) -> Result<()> {
+        if version <= self.current {
+            return Ok(());
+        }
+
+        Err(NewerFormat {
+            name: self.name,
+            path: path.to_path_buf(),
+            version,
+            supported: self.current,
+            written_by: fields
+                .get(WRITTEN_BY_KEY)
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        }
+        .into())
+    }
+
+    fn migrate(
+        &self,
+        path: &Path,
+        original: &str,
+        from: u32,
+        fields: &mut Map<String, Value>,
+    ) -> Result<()> {
+        for version in from..self.current {
+            let migration = self.migrations.get(version as usize).ok_or_else(|| {
+                eyre::eyre!("no migration for {} from version {version}", self.name)
+            })?;
+
+            migration(fields).with_context(|| {
+                format!("failed to upgrade {} from version {version}", self.name)
+            })?;
+        }
+
+        fields.insert(VERSION_KEY.to_string(), self.current.into());
+        fields.insert(WRITTEN_BY_KEY.to_string(), ATUIN_VERSION.into());
+
+        // a backup from an earlier attempt is the real original, so keep that one
+        let backup = backup_path(path, from);
+        if !backup.exists() {
+            fs_err::write(&backup, original)?;
+        }
+
+        write_atomic(path, &serde_json::to_string(fields)?)?;
+
+        log::info!(
+            "upgraded {} at {} from version {from} to {}, keeping the original at {}",
+            self.name,
+            path.display(),
+            self.current,
+            backup.display()
+        );
+
+        Ok(())
+    }
+}
+
+fn version_of(fields: &Map<String, Value>) -> u32 {
+    fields
+        .get(VERSION_KEY)
+        .and_then(Value::as_u64)
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(0)
+}
+
+/// Where the original of a state file upgraded from `version` is kept
+pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{name}.v{version}.bak"))
+}
+
+/// Replace the file at `path` with `contents`, so a crash leaves either the old file or the new
+pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!("{name}.tmp"));
+
+    let mut file = fs_err::File::create(&temp)?;
+    std::io::Write::write_all(&mut file, contents.as_bytes())?;
+    file.sync_all()?;
+    fs_err::rename(temp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Example {
+        renamed: u64,
+        version: u32,
+        written_by: String,
+    }
+
+    fn rename(fields: &mut Map<String, Value>) -> Result<()> {
+        let value = fields.remove("original").unwrap_or_default();
+        fields.insert("renamed".to_string(), value);
+        Ok(())
+    }
+
+    fn double(fields: &mut Map<String, Value>) -> Result<()> {
+        let value = fields["renamed"].as_u64().unwrap_or_default();
+        fields.insert("renamed".to_string(), (value * 2).into());
+        Ok(())
+    }
+
+    const EXAMPLE: Format = Format {
+        name: "example state",
+        current: 2,
+        migrations: &[rename, double],
+    };
+
+    #[test]
+    fn test_migrates_step_by_step_with_a_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let original = r#"{"original":21}"#;
+        fs_err::write(&path, original).unwrap();
+
+        let loaded: Example = EXAMPLE.load(&path, original).unwrap();
+        assert_eq!(
+            loaded,
+            Example {
+                renamed: 42,
+                version: 2,
+                written_by: ATUIN_VERSION.to_string(),
+            }
+        );
+
+        assert_eq!(
+            fs_err::read_to_string(backup_path(&path, 0)).unwrap(),
+            original
+        );
+
+        // upgraded in place, so the next load has nothing to do
+        let upgraded = fs_err::read_to_string(&path).unwrap();
+        assert!(upgraded.contains(r#""version":2"#));
+        let again: Example = EXAMPLE.load(&path, &upgraded).unwrap();
+        assert_eq!(again, loaded);
+
+        // starting part way
+        let partial = r#"{"renamed":5,"version":1}"#;
+        let loaded: Example = EXAMPLE.load(&path, partial).unwrap();
+        assert_eq!(loaded.renamed, 10);
+        assert!(backup_path(&path, 1).exists());
+    }
+
+    #[test]
+    fn test_newer_versions_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let newer = r#"{"renamed":1,"version":3,"written_by":"99.0.0"}"#;
+        fs_err::write(&path, newer).unwrap();
+
+        let err = EXAMPLE.load::<Example>(&path, newer).unwrap_err();
+        let newer_format = err.downcast_ref::<NewerFormat>().unwrap();
+        assert_eq!(newer_format.version, 3);
+        assert!(err.to_string().contains("written by atuin 99.0.0"));
+
+        assert!(EXAMPLE.ensure_writable(&path).is_err());
+
+        // left exactly as it was
+        assert_eq!(fs_err::read_to_string(&path).unwrap(), newer);
+        assert!(!backup_path(&path, 3).exists());
+
+        // anything else can be written over
+        fs_err::write(&path, r#"{"renamed":1,"version":2}"#).unwrap();
+        assert!(EXAMPLE.ensure_writable(&path).is_ok());
+        fs_err::write(&path, "torn").unwrap();
+        assert!(EXAMPLE.ensure_writable(&path).is_ok());
+    }
+}
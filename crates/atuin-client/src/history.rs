@@ -35,6 +35,21 @@ impl From<String> for HistoryId {
     }
 }
 
+impl HistoryId {
+    /// This id in a single spelling, so the same id written two ways compares equal
+    ///
+    /// Ids are uuids, written either hyphenated or as 32 hex digits depending on where they came
+    /// from. See [`canonical_id`].
+    pub fn canonical(&self) -> String {
+        canonical_id(&self.0)
+    }
+}
+
+/// A uuid, however it's written, as 32 lowercase hex digits. Anything else is returned unchanged.
+pub fn canonical_id(id: &str) -> String {
+    uuid::Uuid::try_parse(id).map_or_else(|_| id.to_string(), |uuid| uuid.simple().to_string())
+}
+
 /// Client-side history entry.
 ///
 /// Client stores data unencrypted, and only encrypts it before sending to the server.
@@ -389,7 +404,7 @@ mod tests {
 
     use crate::{history::HISTORY_VERSION, settings::Settings};
 
-    use super::History;
+    use super::{History, HistoryId, canonical_id};
 
     // Test that we don't save history where necessary
     #[test]
@@ -450,6 +465,17 @@ mod tests {
         assert!(!with_psql.should_save(&settings));
     }
 
+    #[test]
+    fn canonical_ids() {
+        let hyphenated = HistoryId("0190B1A2-7C4E-7000-8000-000000000001".to_string());
+        let simple = HistoryId("0190b1a27c4e70008000000000000001".to_string());
+
+        assert_eq!(hyphenated.canonical(), "0190b1a27c4e70008000000000000001");
+        assert_eq!(hyphenated.canonical(), simple.canonical());
+
+        assert_eq!(canonical_id("not-a-uuid"), "not-a-uuid");
+    }
+
     #[test]
     fn disable_secrets() {
         let settings = Settings {
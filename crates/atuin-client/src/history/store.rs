@@ -188,6 +188,24 @@ impl HistoryStore {
         self.push_record(record).await
     }
 
+    /// The history record `id`, or `None` if it's a record of some other kind
+    pub async fn get(&self, id: RecordId) -> Result<Option<HistoryRecord>> {
+        let record = self.store.get(id).await?;
+
+        if record.tag != HISTORY_TAG {
+            return Ok(None);
+        }
+
+        match record.version.as_str() {
+            HISTORY_VERSION => {
+                let decrypted = record.decrypt::<PASETO_V4>(&self.encryption_key)?;
+
+                HistoryRecord::deserialize(&decrypted.data, HISTORY_VERSION).map(Some)
+            }
+            version => bail!("unknown history version {version:?}"),
+        }
+    }
+
     pub async fn history(&self) -> Result<Vec<HistoryRecord>> {
         // Atm this loads all history into memory
         // Not ideal as that is potentially quite a lot, although history will be small.
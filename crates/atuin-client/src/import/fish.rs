@@ -8,13 +8,30 @@ use directories::BaseDirs;
 use eyre::{Result, eyre};
 use time::OffsetDateTime;
 
-use super::{Importer, Loader, unix_byte_lines};
+use atuin_common::utils::uuid_v7;
+
+use super::{Importer, Loader};
+use crate::fish_sync::FishHistoryEntry;
 use crate::history::History;
 use crate::import::read_to_end;
+use crate::utils::get_host_user;
 
 #[derive(Debug)]
 pub struct Fish {
     bytes: Vec<u8>,
+    hostname: String,
+}
+
+impl Fish {
+    /// Import the history as run on `hostname`, rather than on this machine
+    ///
+    /// Fish doesn't record ids, so each entry's id is derived from its command, its time and the
+    /// hostname. Machines importing one shared fish history file under the same hostname get the
+    /// same ids, so the history isn't duplicated once they sync.
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
 }
 
 /// see https://fishshell.com/docs/current/interactive.html#searchable-command-history
@@ -50,7 +67,10 @@ impl Importer for Fish {
 
     async fn new() -> Result<Self> {
         let bytes = read_to_end(default_histpath()?)?;
-        Ok(Self { bytes })
+        Ok(Self {
+            bytes,
+            hostname: get_host_user(),
+        })
     }
 
     async fn entries(&mut self) -> Result<usize> {
@@ -59,48 +79,23 @@ impl Importer for Fish {
 
     async fn load(self, loader: &mut impl Loader) -> Result<()> {
         let now = OffsetDateTime::now_utc();
-        let mut time: Option<OffsetDateTime> = None;
-        let mut cmd: Option<String> = None;
-
-        for b in unix_byte_lines(&self.bytes) {
-            let s = match std::str::from_utf8(b) {
-                Ok(s) => s,
-                Err(_) => continue, // we can skip past things like invalid utf8
+        let session = uuid_v7().as_simple().to_string();
+
+        for entry in FishHistoryEntry::parse(&String::from_utf8_lossy(&self.bytes)) {
+            // entries Atuin wrote keep their history's id, everything else gets one derived from
+            // the entry, so importing again, or on another machine, doesn't duplicate it
+            let history = match entry.when {
+                Some(_) => entry.to_history(&self.hostname, &session)?,
+                None => History::import()
+                    .timestamp(now)
+                    .command(entry.command)
+                    .hostname(self.hostname.clone())
+                    .session(session.clone())
+                    .build()
+                    .into(),
             };
 
-            if let Some(c) = s.strip_prefix("- cmd: ") {
-                // first, we must deal with the prev cmd
-                if let Some(cmd) = cmd.take() {
-                    let time = time.unwrap_or(now);
-                    let entry = History::import().timestamp(time).command(cmd);
-
-                    loader.push(entry.build().into()).await?;
-                }
-
-                // using raw strings to avoid needing escaping.
-                // replaces double backslashes with single backslashes
-                let c = c.replace(r"\\", r"\");
-                // replaces escaped newlines
-                let c = c.replace(r"\n", "\n");
-                // TODO: any other escape characters?
-
-                cmd = Some(c);
-            } else if let Some(t) = s.strip_prefix("  when: ") {
-                // if t is not an int, just ignore this line
-                if let Ok(t) = t.parse::<i64>() {
-                    time = Some(OffsetDateTime::from_unix_timestamp(t)?);
-                }
-            } else {
-                // ... ignore paths lines
-            }
-        }
-
-        // we might have a trailing cmd
-        if let Some(cmd) = cmd.take() {
-            let time = time.unwrap_or(now);
-            let entry = History::import().timestamp(time).command(cmd);
-
-            loader.push(entry.build().into()).await?;
+            loader.push(history).await?;
         }
 
         Ok(())
@@ -152,7 +147,10 @@ ERROR
         .as_bytes()
         .to_owned();
 
-        let fish = Fish { bytes };
+        let fish = Fish {
+            bytes,
+            hostname: "host:user".to_string(),
+        };
 
         let mut loader = TestLoader::default();
         fish.load(&mut loader).await.unwrap();
@@ -176,4 +174,65 @@ ERROR
         fishtory!(1639163063, r#"echo "\"" \\ "\\""#);
         fishtory!(1639163066, "cat ~/.local/share/fish/fish_history");
     }
+
+    #[tokio::test]
+    async fn shared_file_imports_converge() {
+        use std::collections::HashSet;
+
+        use crate::database::{Database, Sqlite};
+        use crate::history::History;
+        use crate::test_support::FishFileBuilder;
+
+        let bytes = FishFileBuilder::new()
+            .native("cargo build", 1_700_000_000)
+            .native("cargo test", 1_700_000_010)
+            .atuin(
+                "git push",
+                1_700_000_020,
+                "0190b1a27c4e70008000000000000001",
+            )
+            .build()
+            .into_bytes();
+
+        // each machine imports the shared file into its own database, and sync merges them
+        let import = |host: &str, declared: Option<&str>| {
+            let mut fish = Fish {
+                bytes: bytes.clone(),
+                hostname: host.to_string(),
+            };
+            if let Some(declared) = declared {
+                fish = fish.with_hostname(declared);
+            }
+            async move {
+                let mut loader = TestLoader::default();
+                fish.load(&mut loader).await.unwrap();
+                loader.buf
+            }
+        };
+
+        let ids = |imported: Vec<History>| -> HashSet<String> {
+            imported.into_iter().map(|h| h.id.0).collect()
+        };
+
+        // the same ids from both machines, so sync has one row per entry
+        let laptop = ids(import("laptop:me", Some("shared:me")).await);
+        let desktop = ids(import("desktop:me", Some("shared:me")).await);
+        assert_eq!(laptop.len(), 3);
+        assert_eq!(laptop, desktop);
+
+        let db = Sqlite::new("sqlite::memory:", 2.0).await.unwrap();
+        for host in ["laptop:me", "desktop:me"] {
+            let imported = import(host, Some("shared:me")).await;
+            assert!(imported.iter().all(|h| h.hostname == "shared:me"));
+            db.save_bulk(&imported).await.unwrap();
+        }
+        assert_eq!(db.history_count(true).await.unwrap(), 3);
+
+        // without a shared hostname each machine's entries are its own, but what Atuin wrote
+        // keeps its id either way
+        let laptop = ids(import("laptop:me", None).await);
+        let desktop = ids(import("desktop:me", None).await);
+        assert_eq!(laptop.intersection(&desktop).count(), 1);
+        assert!(laptop.contains("0190b1a27c4e70008000000000000001"));
+    }
 }
@@ -21,6 +21,8 @@ pub mod record;
 pub mod register;
 pub mod secrets;
 pub mod settings;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 pub mod theme;
 
 mod utils;
@@ -1,7 +1,8 @@
 // do a sync :O
-use std::{cmp::Ordering, fmt::Write};
+use std::{cmp::Ordering, fmt::Write, fs::File, path::Path};
 
-use eyre::Result;
+use eyre::{Context, Result};
+use fs2::FileExt;
 use thiserror::Error;
 
 use super::store::Store;
@@ -312,6 +313,48 @@ pub async fn sync_remote(
     Ok((uploaded, downloaded))
 }
 
+/// Held for the duration of a sync, so only one runs at a time
+///
+/// The lock is released when this is dropped.
+#[derive(Debug)]
+pub struct SyncLock {
+    _file: File,
+}
+
+impl SyncLock {
+    fn open(settings: &Settings) -> Result<File> {
+        let path = Path::new(&settings.record_store_path).with_file_name("sync.lock");
+
+        fs_err::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map(File::from)
+            .context("failed to open sync lock")
+    }
+
+    /// Wait until no other sync is running
+    pub fn acquire(settings: &Settings) -> Result<Self> {
+        let file = Self::open(settings)?;
+        file.lock_exclusive()
+            .context("failed to acquire sync lock")?;
+
+        Ok(Self { _file: file })
+    }
+
+    /// Take the lock if no other sync is running, without waiting
+    pub fn try_acquire(settings: &Settings) -> Result<Option<Self>> {
+        let file = Self::open(settings)?;
+
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => Ok(None),
+            Err(e) => Err(e).context("failed to acquire sync lock"),
+        }
+    }
+}
+
 pub async fn sync(
     settings: &Settings,
     store: &impl Store,
@@ -613,4 +656,19 @@ mod tests {
 
         assert_eq!(result_ops, operations);
     }
+
+    #[test]
+    fn sync_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = crate::settings::Settings {
+            record_store_path: dir.path().join("records.db").to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        let held = sync::SyncLock::acquire(&settings).unwrap();
+        assert!(sync::SyncLock::try_acquire(&settings).unwrap().is_none());
+
+        drop(held);
+        assert!(sync::SyncLock::try_acquire(&settings).unwrap().is_some());
+    }
 }
@@ -335,6 +335,10 @@ impl Default for Stats {
 #[derive(Clone, Debug, Deserialize, Default, Serialize)]
 pub struct Sync {
     pub records: bool,
+
+    /// How long `atuin sync` waits to reach the server before treating the machine as offline.
+    /// 0 skips the check.
+    pub offline_check_timeout_ms: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Default, Serialize)]
@@ -420,22 +424,278 @@ impl Default for Daemon {
     }
 }
 
+/// Which process writes remote entries to the fish history file when more than one could
+#[derive(Clone, Debug, Default, Deserialize, Copy, PartialEq, Eq, Serialize)]
+pub enum FishSyncPrefer {
+    /// The daemon writes if it's running, otherwise the client does
+    #[default]
+    #[serde(rename = "daemon")]
+    Daemon,
+
+    /// Only the client writes, the daemon never does
+    #[serde(rename = "client")]
+    Client,
+
+    /// Both write, relying on dedup to catch any overlap
+    #[serde(rename = "both")]
+    Both,
+}
+
+/// How running fish sessions are told that new entries were written to the history file
+#[derive(Clone, Debug, Default, Deserialize, Copy, PartialEq, Eq, Serialize)]
+pub enum FishSyncNotify {
+    /// Don't notify, sessions pick up new entries whenever fish next reloads its history
+    #[default]
+    #[serde(rename = "none")]
+    None,
+
+    /// Run `history merge` in a fresh fish process
+    #[serde(rename = "merge")]
+    Merge,
+
+    /// Set a universal variable that sessions watch, merging their history when it changes
+    #[serde(rename = "uvar")]
+    Uvar,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct FishSync {
     /// Enable syncing Atuin history to Fish shell history file
     /// This allows Fish's autosuggestions (ghost text) to work with Atuin history
     #[serde(alias = "enable")]
     pub enabled: bool,
 
-    /// Path to the Fish history file
+    /// Path to the Fish history file. Empty means ask fish.
     pub history_path: String,
+
+    /// Which process is responsible for writing when both the daemon and the client could
+    pub prefer: FishSyncPrefer,
+
+    /// Write other machines' history to the Fish history file. Turn off to only back up to the
+    /// server, leaving other machines' commands out of Fish's suggestions.
+    pub sync_downloaded: bool,
+
+    /// Before seeding the Fish history file, save the commands fish wrote to it itself to the
+    /// database
+    pub import_on_bootstrap: bool,
+
+    /// Also remove entries from the Fish history file when they're deleted from Atuin
+    pub sync_deletes: bool,
+
+    /// Keep commands that look like they hold a secret out of the Fish history file, using the
+    /// same patterns as `secrets_filter`
+    pub filter_secrets: bool,
+
+    /// More patterns for commands to keep out of the Fish history file as secrets, whatever
+    /// `filter_secrets` is set to
+    #[serde(with = "serde_regex", default = "RegexSet::empty", skip_serializing)]
+    pub secrets_filter_extra: RegexSet,
+
+    /// Keep commands matching any of these patterns out of the Fish history file
+    #[serde(with = "serde_regex", default = "RegexSet::empty", skip_serializing)]
+    pub exclude_patterns: RegexSet,
+
+    /// Keep these exact commands out of the Fish history file
+    pub exclude_exact: Vec<String>,
+
+    /// Only write commands from these hosts to the Fish history file, or from every host if empty
+    pub include_hosts: Vec<String>,
+
+    /// Keep commands from these hosts out of the Fish history file
+    pub exclude_hosts: Vec<String>,
+
+    /// Only write commands run in one of these directories, or below one, to the Fish history
+    /// file, or from anywhere if empty
+    pub cwd_prefixes: Vec<String>,
+
+    /// Only write commands run inside a git repository to the Fish history file
+    pub require_git_root: bool,
+
+    /// Keep commands that exited with an error out of the Fish history file
+    pub only_successful: bool,
+
+    /// Keep commands that ran for less than this many milliseconds out of the Fish history file
+    pub min_duration_ms: u64,
+
+    /// Keep commands longer than this many bytes, once escaped for the Fish history file, out of
+    /// it. 0 for no limit
+    pub max_command_length: usize,
+
+    /// Write even if `history_path` looks like an Atuin database or is in the Atuin data dir
+    pub allow_unsafe_path: bool,
+
+    /// Write even if the Fish history file belongs to a different user than Atuin runs as
+    pub allow_foreign_owner: bool,
+
+    /// `atuin sync --startup` runs at most once per this many minutes
+    pub startup_interval_mins: u64,
+
+    /// How long the daemon holds back startup work that can wait, like reconciling deletes, and
+    /// the work after any startup phase that ran long
+    pub startup_defer_secs: u64,
+
+    /// Maximum sustained writes to the Fish history file per minute. 0 disables the limit.
+    pub rate_limit_per_min: u32,
+
+    /// How many writes may happen in a burst before the rate limit applies
+    pub rate_limit_burst: u32,
+
+    /// Trim the Fish history file to at most this many entries after writing. 0 means no limit.
+    pub max_entries: usize,
+
+    /// Trim the Fish history file to at most this many bytes after writing
+    pub max_file_bytes: Option<u64>,
+
+    /// Let the Fish history file grow this many percent past `max_entries` before trimming it
+    /// back down, so a full file isn't rewritten for every new command
+    pub trim_slack_percent: u32,
+
+    /// Never keep more entries than Fish itself does
+    pub respect_fish_history_max: bool,
+
+    /// How to tell running Fish sessions about newly written entries
+    pub notify: FishSyncNotify,
+
+    /// Notify running Fish sessions at most once this many seconds, across all processes
+    pub notify_interval_secs: u64,
+
+    /// Deprecated: `true` is the same as `notify = "merge"`, which takes precedence when set
+    #[serde(skip_serializing)]
+    pub fish_merge: Option<bool>,
+
+    /// Once the Fish history file is full, skip entries older than everything in it
+    pub skip_older_than_window: bool,
+
+    /// Write the paths a command's arguments name in its entry's `paths:` field, so fish stops
+    /// suggesting it once they're gone
+    pub include_paths: bool,
+
+    /// Keep the Fish history file in timestamp order, sorting it whenever it's trimmed
+    pub keep_sorted: bool,
+
+    /// Warn, at most once a day, when the Fish history file holds more entries than this. 0
+    /// disables the warning.
+    pub warn_entries: u64,
+
+    /// Warn, at most once a day, when the Fish history file is larger than this many megabytes. 0
+    /// disables the warning.
+    pub warn_size_mb: u64,
+
+    /// Refuse to write unless Fish is installed. Off by default, as the history file may be
+    /// written on a machine without Fish for others that share it.
+    pub require_fish: bool,
+
+    /// Turn fish sync off once fish can take history from Atuin itself, so entries aren't
+    /// written twice
+    pub disable_if_native: bool,
+
+    /// The first fish version that may have its own history API. Older versions aren't asked
+    /// whether they do.
+    pub native_min_version: String,
+
+    /// Tag every written entry with the code path that wrote it, and record each write in the
+    /// database
+    pub audit: bool,
+
+    /// Append a line to this file for every change Atuin makes to the Fish history file. Empty
+    /// turns the journal off.
+    pub journal_path: String,
+}
+
+impl FishSync {
+    /// Move settings from their deprecated keys to the ones that replaced them, warning about
+    /// each
+    pub fn apply_deprecated(&mut self) {
+        if let Some(fish_merge) = self.fish_merge.take() {
+            if self.notify == FishSyncNotify::None {
+                if fish_merge {
+                    self.notify = FishSyncNotify::Merge;
+                }
+
+                log::warn!(
+                    "fish_sync.fish_merge is deprecated, set fish_sync.notify = \"{}\" instead",
+                    if fish_merge { "merge" } else { "none" }
+                );
+            } else {
+                log::warn!(
+                    "fish_sync.fish_merge is deprecated and ignored, as fish_sync.notify is set"
+                );
+            }
+        }
+    }
+}
+
+/// Where fish keeps its history on this platform, unless told otherwise
+///
+/// Fish follows the XDG base directory spec on every Unix, macOS and the BSDs included, rather
+/// than using `~/Library`. It doesn't run natively anywhere else, so there the path is left empty
+/// and fish is asked where its history is when the path is resolved.
+pub fn default_fish_history_path() -> String {
+    #[cfg(unix)]
+    {
+        match std::env::var("XDG_DATA_HOME") {
+            // the spec says to ignore relative paths, and fish does
+            Ok(data_home) if std::path::Path::new(&data_home).is_absolute() => {
+                PathBuf::from(data_home)
+                    .join("fish")
+                    .join("fish_history")
+                    .to_string_lossy()
+                    .to_string()
+            }
+            _ => "~/.local/share/fish/fish_history".to_string(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        String::new()
+    }
 }
 
 impl Default for FishSync {
     fn default() -> Self {
         Self {
             enabled: false,
-            history_path: "~/.local/share/fish/fish_history".to_string(),
+            history_path: default_fish_history_path(),
+            prefer: FishSyncPrefer::default(),
+            sync_downloaded: true,
+            import_on_bootstrap: false,
+            sync_deletes: false,
+            filter_secrets: true,
+            secrets_filter_extra: RegexSet::empty(),
+            exclude_patterns: RegexSet::empty(),
+            exclude_exact: Vec::new(),
+            include_hosts: Vec::new(),
+            exclude_hosts: Vec::new(),
+            cwd_prefixes: Vec::new(),
+            require_git_root: false,
+            only_successful: false,
+            min_duration_ms: 0,
+            max_command_length: 0,
+            allow_unsafe_path: false,
+            allow_foreign_owner: false,
+            startup_interval_mins: 60,
+            startup_defer_secs: 30,
+            rate_limit_per_min: 60,
+            rate_limit_burst: 120,
+            max_entries: 0,
+            max_file_bytes: None,
+            trim_slack_percent: 10,
+            respect_fish_history_max: true,
+            notify: FishSyncNotify::default(),
+            notify_interval_secs: 5,
+            fish_merge: None,
+            skip_older_than_window: true,
+            include_paths: false,
+            keep_sorted: false,
+            warn_entries: 100_000,
+            warn_size_mb: 50,
+            require_fish: false,
+            disable_if_native: true,
+            native_min_version: "5.0".to_string(),
+            audit: false,
+            journal_path: String::new(),
         }
     }
 }
@@ -836,6 +1096,7 @@ impl Settings {
             // New users will get the new default, that is more similar to what they are used to.
             .set_default("enter_accept", false)?
             .set_default("sync.records", true)?
+            .set_default("sync.offline_check_timeout_ms", 1000)?
             .set_default("keys.scroll_exits", true)?
             .set_default("keys.accept_past_line_end", true)?
             .set_default("keys.exit_past_line_start", true)?
@@ -854,7 +1115,35 @@ impl Settings {
             .set_default("daemon.systemd_socket", false)?
             .set_default("daemon.tcp_port", 8889)?
             .set_default("fish_sync.enabled", false)?
-            .set_default("fish_sync.history_path", "~/.local/share/fish/fish_history")?
+            .set_default("fish_sync.history_path", default_fish_history_path())?
+            .set_default("fish_sync.prefer", "daemon")?
+            .set_default("fish_sync.sync_downloaded", true)?
+            .set_default("fish_sync.import_on_bootstrap", false)?
+            .set_default("fish_sync.sync_deletes", false)?
+            .set_default("fish_sync.filter_secrets", true)?
+            .set_default("fish_sync.require_git_root", false)?
+            .set_default("fish_sync.only_successful", false)?
+            .set_default("fish_sync.min_duration_ms", 0)?
+            .set_default("fish_sync.max_command_length", 0)?
+            .set_default("fish_sync.allow_unsafe_path", false)?
+            .set_default("fish_sync.startup_interval_mins", 60)?
+            .set_default("fish_sync.startup_defer_secs", 30)?
+            .set_default("fish_sync.rate_limit_per_min", 60)?
+            .set_default("fish_sync.rate_limit_burst", 120)?
+            .set_default("fish_sync.max_entries", 0)?
+            .set_default("fish_sync.trim_slack_percent", 10)?
+            .set_default("fish_sync.respect_fish_history_max", true)?
+            .set_default("fish_sync.notify", "none")?
+            .set_default("fish_sync.notify_interval_secs", 5)?
+            .set_default("fish_sync.skip_older_than_window", true)?
+            .set_default("fish_sync.include_paths", false)?
+            .set_default("fish_sync.keep_sorted", false)?
+            .set_default("fish_sync.warn_entries", 100_000)?
+            .set_default("fish_sync.warn_size_mb", 50)?
+            .set_default("fish_sync.require_fish", false)?
+            .set_default("fish_sync.disable_if_native", true)?
+            .set_default("fish_sync.native_min_version", "5.0")?
+            .set_default("fish_sync.journal_path", "")?
             .set_default("kv.db_path", kv_path.to_str())?
             .set_default("scripts.db_path", scripts_path.to_str())?
             .set_default(
@@ -930,6 +1219,12 @@ impl Settings {
         settings.session_path = Self::expand_path(settings.session_path)?;
         settings.daemon.socket_path = Self::expand_path(settings.daemon.socket_path)?;
         settings.fish_sync.history_path = Self::expand_path(settings.fish_sync.history_path)?;
+        settings.fish_sync.journal_path = Self::expand_path(settings.fish_sync.journal_path)?;
+        settings.fish_sync.cwd_prefixes = std::mem::take(&mut settings.fish_sync.cwd_prefixes)
+            .into_iter()
+            .map(Self::expand_path)
+            .collect::<Result<_>>()?;
+        settings.fish_sync.apply_deprecated();
 
         Ok(settings)
     }
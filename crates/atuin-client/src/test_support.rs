@@ -0,0 +1,321 @@
+//! Fixtures and assertions shared by the shell sync tests
+//!
+//! Only compiled for this crate's own tests, or for other crates that enable the `test-support`
+//! feature in their dev-dependencies.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use eyre::{Result, eyre};
+use time::OffsetDateTime;
+
+use crate::database::Sqlite;
+use crate::history::History;
+use crate::settings::{FishSync, Settings};
+
+pub use crate::fish_sync::count_entries;
+use crate::fish_sync::format::{ATUIN_UUID_KEY, metadata_line};
+use crate::fish_sync::runner::{FishOutput, FishRunner};
+
+/// Settings with fish sync enabled and pointed at `fish_path`
+///
+/// Atuin's own files are placed next to `fish_path`, so tests never touch the real data dir.
+pub fn fish_settings(fish_path: &Path) -> Settings {
+    let sibling = |name: &str| fish_path.with_file_name(name).to_string_lossy().to_string();
+
+    Settings {
+        db_path: sibling("history.db"),
+        record_store_path: sibling("records.db"),
+        key_path: sibling("key"),
+        session_path: sibling("session"),
+        fish_sync: FishSync {
+            enabled: true,
+            history_path: fish_path.to_string_lossy().to_string(),
+            ..FishSync::default()
+        },
+        ..Settings::default()
+    }
+}
+
+/// An empty history database, kept in memory
+pub async fn memory_db() -> Sqlite {
+    Sqlite::new("sqlite::memory:", 2.0).await.unwrap()
+}
+
+/// [`fish_settings`] with no rate limit on writes, and an empty database, for tests that write
+/// other machines' history in bulk
+pub async fn fish_db_settings(fish_path: &Path) -> (Settings, Sqlite) {
+    let mut settings = fish_settings(fish_path);
+    settings.fish_sync.rate_limit_per_min = 0;
+
+    (settings, memory_db().await)
+}
+
+/// `count` commands run on `hostname` a second apart, oldest first, each with an id made from
+/// its position
+pub fn remote_histories(count: i64, hostname: &str) -> Vec<History> {
+    (0..count)
+        .map(|i| {
+            HistoryBuilder::new(format!("remote {i}"))
+                .id(format!("{i:032x}"))
+                .timestamp(1_700_000_000 + i)
+                .hostname(hostname)
+                .build()
+        })
+        .collect()
+}
+
+/// Builds a [`History`] with sensible defaults, so tests only set what they care about
+#[derive(Debug, Clone)]
+pub struct HistoryBuilder {
+    history: History,
+}
+
+impl HistoryBuilder {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            history: History {
+                id: "00000000-0000-0000-000000000000001".to_string().into(),
+                timestamp: OffsetDateTime::UNIX_EPOCH,
+                duration: 100,
+                exit: 0,
+                command: command.into(),
+                cwd: "/home/user".to_string(),
+                session: "test-session".to_string(),
+                hostname: "localhost".to_string(),
+                deleted_at: None,
+            },
+        }
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.history.id = id.into().into();
+        self
+    }
+
+    pub fn timestamp(mut self, unix: i64) -> Self {
+        self.history.timestamp = OffsetDateTime::from_unix_timestamp(unix).unwrap();
+        self
+    }
+
+    pub fn duration(mut self, duration: i64) -> Self {
+        self.history.duration = duration;
+        self
+    }
+
+    pub fn exit(mut self, exit: i64) -> Self {
+        self.history.exit = exit;
+        self
+    }
+
+    pub fn cwd(mut self, cwd: impl Into<String>) -> Self {
+        self.history.cwd = cwd.into();
+        self
+    }
+
+    pub fn session(mut self, session: impl Into<String>) -> Self {
+        self.history.session = session.into();
+        self
+    }
+
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.history.hostname = hostname.into();
+        self
+    }
+
+    pub fn deleted_at(mut self, unix: i64) -> Self {
+        self.history.deleted_at = Some(OffsetDateTime::from_unix_timestamp(unix).unwrap());
+        self
+    }
+
+    pub fn build(self) -> History {
+        self.history
+    }
+}
+
+/// Builds the contents of a fish history file
+///
+/// Commands are escaped the same way fish escapes them. Use [`FishFileBuilder::raw`] for
+/// anything deliberately malformed.
+#[derive(Debug, Clone, Default)]
+pub struct FishFileBuilder {
+    content: String,
+}
+
+impl FishFileBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An entry as fish itself writes it
+    pub fn native(mut self, command: &str, when: i64) -> Self {
+        self.push_entry(command, when);
+        self
+    }
+
+    /// An entry as fish writes it, including the paths it recorded
+    pub fn native_with_paths(mut self, command: &str, when: i64, paths: &[&str]) -> Self {
+        self.push_entry(command, when);
+        self.content.push_str("  paths:\n");
+        for path in paths {
+            self.content.push_str(&format!("    - {path}\n"));
+        }
+        self
+    }
+
+    /// An entry as atuin writes it, tagged with the id of the history it came from
+    pub fn atuin(mut self, command: &str, when: i64, uuid: &str) -> Self {
+        self.push_entry(command, when);
+        self.content.push_str(&metadata_line(ATUIN_UUID_KEY, uuid));
+        self
+    }
+
+    /// Text appended verbatim, for adversarial fixtures
+    pub fn raw(mut self, text: &str) -> Self {
+        self.content.push_str(text);
+        self
+    }
+
+    /// `count` numbered native entries, one second apart starting at `start`
+    pub fn many_native(mut self, count: usize, start: i64) -> Self {
+        for i in 0..count {
+            self.push_entry(&format!("command {i}"), start + i as i64);
+        }
+        self
+    }
+
+    pub fn build(self) -> String {
+        self.content
+    }
+
+    pub fn write(self, path: &Path) {
+        fs_err::write(path, self.content).unwrap();
+    }
+
+    fn push_entry(&mut self, command: &str, when: i64) {
+        let escaped = command.replace('\\', "\\\\").replace('\n', "\\n");
+        self.content
+            .push_str(&format!("- cmd:{escaped}\n  when:{when}\n"));
+    }
+}
+
+/// Panic unless every line of the file is something fish's history parser understands
+pub fn assert_file_parses(path: &Path) {
+    let content = fs_err::read_to_string(path).unwrap();
+    let mut in_paths = false;
+
+    for (i, line) in content.lines().enumerate() {
+        let ok = if line.starts_with("- cmd:") {
+            in_paths = false;
+            true
+        } else if let Some(when) = line.strip_prefix("  when:") {
+            in_paths = false;
+            when.trim().parse::<i64>().is_ok()
+        } else if line == "  paths:" {
+            in_paths = true;
+            true
+        } else if line.starts_with("    - ") {
+            in_paths
+        } else {
+            line.starts_with("  # ")
+        };
+
+        assert!(ok, "{}:{}: unexpected line {line:?}", path.display(), i + 1);
+    }
+
+    assert!(
+        content.is_empty() || content.ends_with('\n'),
+        "{}: missing trailing newline",
+        path.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixtures_parse() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fish_history");
+
+        FishFileBuilder::new()
+            .native("ls", 1)
+            .native_with_paths("cat foo", 2, &["foo"])
+            .atuin("echo a\nb", 3, "abc")
+            .many_native(3, 10)
+            .write(&path);
+
+        assert_file_parses(&path);
+        assert_eq!(count_entries(&path).unwrap(), 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected line")]
+    fn test_adversarial_fixture_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fish_history");
+
+        FishFileBuilder::new()
+            .native("ls", 1)
+            .raw("garbage\n")
+            .write(&path);
+
+        assert_file_parses(&path);
+    }
+}
+
+/// A [`FishRunner`] that replays canned results in order, and records what it was asked to run
+///
+/// Once the script runs out, every run succeeds with no output.
+#[derive(Debug, Default)]
+pub struct ScriptedFish {
+    results: Mutex<VecDeque<Result<FishOutput, String>>>,
+    calls: Mutex<Vec<Vec<String>>>,
+}
+
+impl ScriptedFish {
+    /// Next, exit with `code` after printing `stdout`
+    pub fn then_output(self, code: i32, stdout: &str) -> Self {
+        self.results.lock().unwrap().push_back(Ok(FishOutput {
+            code: Some(code),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        }));
+        self
+    }
+
+    /// Next, fail to run at all, e.g. because fish isn't installed or timed out
+    pub fn then_error(self, message: &str) -> Self {
+        self.results
+            .lock()
+            .unwrap()
+            .push_back(Err(message.to_string()));
+        self
+    }
+
+    /// The arguments of every run so far
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl FishRunner for ScriptedFish {
+    fn run(&self, args: &[&str], _timeout: Duration) -> Result<FishOutput> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(args.iter().map(ToString::to_string).collect());
+
+        match self.results.lock().unwrap().pop_front() {
+            Some(Ok(output)) => Ok(output),
+            Some(Err(message)) => Err(eyre!(message)),
+            None => Ok(FishOutput {
+                code: Some(0),
+                ..FishOutput::default()
+            }),
+        }
+    }
+}
@@ -14,55 +14,63 @@ use atuin_client::history::History;
 use crate::history::{
     EndHistoryRequest, StartHistoryRequest, history_client::HistoryClient as HistoryServiceClient,
 };
+use crate::shell_sync::{
+    GetShellSyncStateRequest, ShellSyncState,
+    shell_sync_client::ShellSyncClient as ShellSyncServiceClient,
+};
 
 pub struct HistoryClient {
     client: HistoryServiceClient<Channel>,
 }
 
+#[cfg(unix)]
+async fn connect(path: String) -> Result<Channel> {
+    let log_path = path.clone();
+    Endpoint::try_from("http://atuin_local_daemon:0")?
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let path = path.clone();
+
+            async move {
+                Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(path.clone()).await?))
+            }
+        }))
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "failed to connect to local atuin daemon at {}. Is it running?",
+                &log_path
+            )
+        })
+}
+
+#[cfg(not(unix))]
+async fn connect(port: u64) -> Result<Channel> {
+    Endpoint::try_from("http://atuin_local_daemon:0")?
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let url = format!("127.0.0.1:{port}");
+
+            async move {
+                Ok::<_, std::io::Error>(TokioIo::new(TcpStream::connect(url.clone()).await?))
+            }
+        }))
+        .await
+        .wrap_err_with(|| {
+            format!("failed to connect to local atuin daemon at 127.0.0.1:{port}. Is it running?")
+        })
+}
+
 // Wrap the grpc client
 impl HistoryClient {
     #[cfg(unix)]
     pub async fn new(path: String) -> Result<Self> {
-        let log_path = path.clone();
-        let channel = Endpoint::try_from("http://atuin_local_daemon:0")?
-            .connect_with_connector(service_fn(move |_: Uri| {
-                let path = path.clone();
-
-                async move {
-                    Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(path.clone()).await?))
-                }
-            }))
-            .await
-            .wrap_err_with(|| {
-                format!(
-                    "failed to connect to local atuin daemon at {}. Is it running?",
-                    &log_path
-                )
-            })?;
-
-        let client = HistoryServiceClient::new(channel);
+        let client = HistoryServiceClient::new(connect(path).await?);
 
         Ok(HistoryClient { client })
     }
 
     #[cfg(not(unix))]
     pub async fn new(port: u64) -> Result<Self> {
-        let channel = Endpoint::try_from("http://atuin_local_daemon:0")?
-            .connect_with_connector(service_fn(move |_: Uri| {
-                let url = format!("127.0.0.1:{port}");
-
-                async move {
-                    Ok::<_, std::io::Error>(TokioIo::new(TcpStream::connect(url.clone()).await?))
-                }
-            }))
-            .await
-            .wrap_err_with(|| {
-                format!(
-                    "failed to connect to local atuin daemon at 127.0.0.1:{port}. Is it running?"
-                )
-            })?;
-
-        let client = HistoryServiceClient::new(channel);
+        let client = HistoryServiceClient::new(connect(port).await?);
 
         Ok(HistoryClient { client })
     }
@@ -95,3 +103,33 @@ impl HistoryClient {
         Ok((resp.id, resp.idx))
     }
 }
+
+/// Reads the daemon's shell sync state, for status displays
+pub struct ShellSyncClient {
+    client: ShellSyncServiceClient<Channel>,
+}
+
+impl ShellSyncClient {
+    #[cfg(unix)]
+    pub async fn new(path: String) -> Result<Self> {
+        let client = ShellSyncServiceClient::new(connect(path).await?);
+
+        Ok(ShellSyncClient { client })
+    }
+
+    #[cfg(not(unix))]
+    pub async fn new(port: u64) -> Result<Self> {
+        let client = ShellSyncServiceClient::new(connect(port).await?);
+
+        Ok(ShellSyncClient { client })
+    }
+
+    /// Fetch the current state. With `redact_paths`, paths are reported by file name only.
+    pub async fn state(&mut self, redact_paths: bool) -> Result<ShellSyncState> {
+        let req = GetShellSyncStateRequest { redact_paths };
+
+        let resp = self.client.get_shell_sync_state(req).await?;
+
+        Ok(resp.into_inner())
+    }
+}
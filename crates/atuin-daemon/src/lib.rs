@@ -1,3 +1,4 @@
 pub mod client;
 pub mod history;
 pub mod server;
+pub mod shell_sync;
@@ -19,9 +19,14 @@ use tonic::{Request, Response, Status, transport::Server};
 use crate::history::history_server::{History as HistorySvc, HistoryServer};
 
 use crate::history::{EndHistoryReply, EndHistoryRequest, StartHistoryReply, StartHistoryRequest};
+use crate::shell_sync::shell_sync_server::ShellSyncServer;
 
+mod shell_sync;
+mod startup;
 mod sync;
 
+use shell_sync::{SharedShellSync, ShellSyncService};
+
 #[derive(Debug)]
 pub struct HistoryService {
     // A store for WIP history
@@ -162,7 +167,11 @@ async fn shutdown_signal() {
 }
 
 #[cfg(unix)]
-async fn start_server(settings: Settings, history: HistoryService) -> Result<()> {
+async fn start_server(
+    settings: Settings,
+    history: HistoryService,
+    shell_sync: ShellSyncService,
+) -> Result<()> {
     use tokio::net::UnixListener;
     use tokio_stream::wrappers::UnixListenerStream;
 
@@ -213,6 +222,7 @@ async fn start_server(settings: Settings, history: HistoryService) -> Result<()>
 
     Server::builder()
         .add_service(HistoryServer::new(history))
+        .add_service(ShellSyncServer::new(shell_sync))
         .serve_with_incoming_shutdown(
             uds_stream,
             shutdown_signal(cleanup.then_some(socket_path.into())),
@@ -223,7 +233,11 @@ async fn start_server(settings: Settings, history: HistoryService) -> Result<()>
 }
 
 #[cfg(not(unix))]
-async fn start_server(settings: Settings, history: HistoryService) -> Result<()> {
+async fn start_server(
+    settings: Settings,
+    history: HistoryService,
+    shell_sync: ShellSyncService,
+) -> Result<()> {
     use tokio::net::TcpListener;
     use tokio_stream::wrappers::TcpListenerStream;
 
@@ -236,6 +250,7 @@ async fn start_server(settings: Settings, history: HistoryService) -> Result<()>
 
     Server::builder()
         .add_service(HistoryServer::new(history))
+        .add_service(ShellSyncServer::new(shell_sync))
         .serve_with_incoming_shutdown(tcp_stream, shutdown_signal())
         .await?;
     Ok(())
@@ -246,10 +261,13 @@ async fn start_server(settings: Settings, history: HistoryService) -> Result<()>
 /// Listen on a unix socket
 /// Pass the path to the socket
 pub async fn listen(
-    settings: Settings,
+    mut settings: Settings,
     store: SqliteStore,
     history_db: HistoryDatabase,
 ) -> Result<()> {
+    // once fish takes history from Atuin itself, writing its file too would duplicate it
+    atuin_client::fish_sync::disable_if_native(&mut settings);
+
     let encryption_key: [u8; 32] = encryption::load_key(&settings)
         .context("could not load encryption key")?
         .into();
@@ -259,13 +277,17 @@ pub async fn listen(
 
     let history = HistoryService::new(history_store.clone(), history_db.clone());
 
+    let shell_sync_state = SharedShellSync::default();
+    let shell_sync = ShellSyncService::new(settings.clone(), shell_sync_state.clone());
+
     // start services
     tokio::spawn(sync::worker(
         settings.clone(),
         store,
         history_store,
         history_db,
+        shell_sync_state,
     ));
 
-    start_server(settings, history).await
+    start_server(settings, history, shell_sync).await
 }
@@ -0,0 +1,191 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use time::OffsetDateTime;
+use tonic::{Request, Response, Status};
+use tracing::{Level, instrument};
+
+use atuin_client::fish_sync::{
+    self, BootstrapProgress, meta::FishSyncMeta, metrics::ShellSyncMetrics,
+};
+use atuin_client::settings::{FishSyncNotify, FishSyncPrefer, Settings};
+
+use crate::shell_sync::shell_sync_server::ShellSync as ShellSyncSvc;
+use crate::shell_sync::{
+    self as proto, FileState, GetShellSyncStateRequest, ShellSyncError, ShellSyncSettings,
+    ShellSyncState, TargetMetrics,
+};
+
+/// Bump this whenever fields are added to `ShellSyncState`
+pub const STATE_VERSION: u32 = 7;
+
+/// How many failures to remember for status reporting
+const MAX_RECENT_ERRORS: usize = 10;
+
+/// Shell sync state shared between the sync worker and the status service
+#[derive(Debug, Default)]
+pub struct ShellSyncTracker {
+    pub metrics: ShellSyncMetrics,
+
+    /// Batches spawned but not yet finished
+    pub in_flight: u64,
+
+    /// The latest bootstrap's progress, if one has run
+    pub bootstrap: Option<BootstrapProgress>,
+
+    recent_errors: VecDeque<ShellSyncError>,
+}
+
+pub type SharedShellSync = Arc<Mutex<ShellSyncTracker>>;
+
+impl ShellSyncTracker {
+    pub fn record_error(&mut self, target: &str, message: String) {
+        if self.recent_errors.len() == MAX_RECENT_ERRORS {
+            self.recent_errors.pop_front();
+        }
+
+        self.recent_errors.push_back(ShellSyncError {
+            timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+            target: target.to_string(),
+            message,
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct ShellSyncService {
+    settings: Settings,
+    state: SharedShellSync,
+
+    /// The state file as last read, with the size and modified time it had then
+    meta: Mutex<Option<(MetaStamp, FishSyncMeta)>>,
+}
+
+type MetaStamp = Option<(u64, SystemTime)>;
+
+impl ShellSyncService {
+    pub fn new(settings: Settings, state: SharedShellSync) -> Self {
+        Self {
+            settings,
+            state,
+            meta: Mutex::default(),
+        }
+    }
+
+    /// The state file, only read again when it has changed since the last status query
+    fn file_state(&self) -> Option<FileState> {
+        let path = FishSyncMeta::path(&self.settings);
+        let stamp = fs_err::metadata(&path)
+            .and_then(|metadata| Ok((metadata.len(), metadata.modified()?)))
+            .ok();
+
+        let mut cached = self
+            .meta
+            .lock()
+            .expect("fish sync meta cache lock poisoned");
+        let meta = match cached.as_ref() {
+            Some((cached_stamp, meta)) if stamp.is_some() && *cached_stamp == stamp => meta,
+            _ => {
+                let meta = FishSyncMeta::load(&path).ok()?;
+                &cached.insert((stamp, meta)).1
+            }
+        };
+
+        Some(FileState {
+            total_written: meta.total_written,
+            fish_entries: meta.fish_entries,
+            duplicates_removed: meta.duplicates_removed,
+            last_sync: meta.last_sync,
+            last_failure: meta.last_failure,
+            last_error: meta.last_error.clone().unwrap_or_default(),
+            atuin_entries: meta.atuin_entries,
+        })
+    }
+
+    fn settings_snapshot(&self, redact_paths: bool) -> ShellSyncSettings {
+        let fish = &self.settings.fish_sync;
+
+        let history_path = if redact_paths {
+            Path::new(&fish.history_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        } else {
+            fish.history_path.clone()
+        };
+
+        let prefer = match fish.prefer {
+            FishSyncPrefer::Daemon => "daemon",
+            FishSyncPrefer::Client => "client",
+            FishSyncPrefer::Both => "both",
+        };
+
+        let notify = match fish.notify {
+            FishSyncNotify::None => "none",
+            FishSyncNotify::Merge => "merge",
+            FishSyncNotify::Uvar => "uvar",
+        };
+
+        ShellSyncSettings {
+            enabled: fish.enabled,
+            history_path,
+            prefer: prefer.to_string(),
+            notify: notify.to_string(),
+            max_entries: fish.max_entries as u64,
+            rate_limit_per_min: fish.rate_limit_per_min,
+            rate_limit_burst: fish.rate_limit_burst,
+            sync_deletes: fish.sync_deletes,
+            sync_downloaded: fish.sync_downloaded,
+            require_fish: fish.require_fish,
+            fish_installed: fish_sync::fish_installed(),
+        }
+    }
+}
+
+#[tonic::async_trait()]
+impl ShellSyncSvc for ShellSyncService {
+    #[instrument(skip_all, level = Level::DEBUG)]
+    async fn get_shell_sync_state(
+        &self,
+        request: Request<GetShellSyncStateRequest>,
+    ) -> Result<Response<ShellSyncState>, Status> {
+        let req = request.into_inner();
+        let settings = self.settings_snapshot(req.redact_paths);
+        let file = self.file_state();
+
+        let state = self.state.lock().expect("shell sync state lock poisoned");
+
+        let metrics = state
+            .metrics
+            .iter()
+            .map(|(target, m)| TargetMetrics {
+                target: target.to_string(),
+                written: m.written,
+                skipped: m.skipped,
+                deferred: m.deferred,
+                errors: m.errors,
+                last_success: m.last_success.map(OffsetDateTime::unix_timestamp),
+                too_old: m.too_old,
+            })
+            .collect();
+
+        let reply = ShellSyncState {
+            version: STATE_VERSION,
+            settings: Some(settings),
+            metrics,
+            queue_depth: state.in_flight,
+            recent_errors: state.recent_errors.iter().cloned().collect(),
+            bootstrap: state.bootstrap.map(|progress| proto::BootstrapProgress {
+                done: progress.done as u64,
+                total: progress.total as u64,
+                resumed: progress.resumed,
+                summary: progress.to_string(),
+            }),
+            file,
+        };
+
+        Ok(Response::new(reply))
+    }
+}
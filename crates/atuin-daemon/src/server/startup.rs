@@ -0,0 +1,317 @@
+//! Ordering the shell sync work the daemon does when it starts
+//!
+//! The daemon usually starts as the user opens a terminal, which is the worst time for every
+//! piece of startup work to hit the disk at once. So the phases run one after another, in the
+//! order given, urgent ones first. Each has a time budget. A phase that runs over it is never
+//! cut short, as a half finished bootstrap or reconcile helps nobody, but everything after it
+//! waits a while longer once it's done. Phases that can wait, like reconciling, wait that long
+//! anyway before they start.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use eyre::Result;
+use tokio::time;
+
+/// Whether a phase has to run straight away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Run as soon as the phases before it are done
+    High,
+
+    /// Run after every high priority phase, once the startup delay has passed
+    Low,
+}
+
+type PhaseFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+struct Phase {
+    name: &'static str,
+    priority: Priority,
+    budget: Duration,
+    run: PhaseFuture,
+}
+
+/// The phases of startup, and how long to hold back work that can wait
+pub struct Startup {
+    phases: Vec<Phase>,
+    defer: Duration,
+}
+
+/// How one phase went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+
+    /// Finished, but took longer than its budget, so the phases after it were held back
+    OverBudget,
+
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct PhaseReport {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    pub elapsed: Duration,
+
+    /// How long the phase was held back before it started
+    pub deferred: Duration,
+}
+
+/// Every phase of a startup, in the order they ran
+#[derive(Debug, Clone, Default)]
+pub struct StartupReport {
+    pub phases: Vec<PhaseReport>,
+}
+
+impl Startup {
+    /// A startup that holds back low priority work, and the work after a phase that overran, by
+    /// `defer`
+    pub fn new(defer: Duration) -> Self {
+        Self {
+            phases: Vec::new(),
+            defer,
+        }
+    }
+
+    /// Add a phase. High priority phases run in the order they're added, then low priority ones.
+    pub fn phase(
+        mut self,
+        name: &'static str,
+        priority: Priority,
+        budget: Duration,
+        run: impl Future<Output = Result<()>> + Send + 'static,
+    ) -> Self {
+        self.phases.push(Phase {
+            name,
+            priority,
+            budget,
+            run: Box::pin(run),
+        });
+        self
+    }
+
+    /// Run every phase, one at a time
+    ///
+    /// A phase that fails doesn't stop the ones after it, as each only does what it can with
+    /// whatever the earlier ones left.
+    pub async fn run(self) -> StartupReport {
+        let (mut phases, low): (Vec<_>, Vec<_>) = self
+            .phases
+            .into_iter()
+            .partition(|phase| phase.priority == Priority::High);
+        phases.extend(low);
+
+        let mut report = StartupReport::default();
+        let mut hold_back = false;
+        let mut waited_for_low = false;
+
+        for phase in phases {
+            let first_low = phase.priority == Priority::Low && !waited_for_low;
+
+            // one wait covers everything held back so far
+            let deferred = if hold_back || first_low {
+                time::sleep(self.defer).await;
+                self.defer
+            } else {
+                Duration::ZERO
+            };
+            hold_back = false;
+            waited_for_low |= phase.priority == Priority::Low;
+
+            let start = Instant::now();
+            let mut run = phase.run;
+
+            let result = match time::timeout(phase.budget, &mut run).await {
+                Ok(result) => result,
+                Err(_) => {
+                    tracing::debug!(
+                        phase = phase.name,
+                        budget = ?phase.budget,
+                        "shell sync startup phase over budget, holding back the rest"
+                    );
+                    hold_back = true;
+                    run.await
+                }
+            };
+
+            let outcome = match result {
+                Err(e) => Outcome::Failed(e.to_string()),
+                Ok(()) if hold_back => Outcome::OverBudget,
+                Ok(()) => Outcome::Done,
+            };
+
+            if let Outcome::Failed(e) = &outcome {
+                tracing::error!(phase = phase.name, error = %e, "shell sync startup phase failed");
+            }
+
+            report.phases.push(PhaseReport {
+                name: phase.name,
+                outcome,
+                elapsed: start.elapsed(),
+                deferred,
+            });
+        }
+
+        report
+    }
+}
+
+impl fmt::Display for StartupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, phase) in self.phases.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+
+            write!(f, "{} {:.0?}", phase.name, phase.elapsed)?;
+
+            if !phase.deferred.is_zero() {
+                write!(f, " after {:.0?}", phase.deferred)?;
+            }
+
+            match &phase.outcome {
+                Outcome::Done => {}
+                Outcome::OverBudget => f.write_str(" (over budget)")?,
+                Outcome::Failed(e) => write!(f, " (failed: {e})")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    /// A phase that takes `takes`, then records that it finished
+    fn step(
+        log: &Log,
+        name: &'static str,
+        takes: Duration,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let log = log.clone();
+
+        async move {
+            time::sleep(takes).await;
+            log.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    const BUDGET: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn test_phases_run_in_order_urgent_first() {
+        let log = Log::default();
+
+        let report = Startup::new(Duration::from_millis(10))
+            .phase(
+                "detect",
+                Priority::High,
+                BUDGET,
+                step(&log, "detect", Duration::ZERO),
+            )
+            .phase(
+                "reconcile",
+                Priority::Low,
+                BUDGET,
+                step(&log, "reconcile", Duration::ZERO),
+            )
+            .phase(
+                "bootstrap",
+                Priority::High,
+                BUDGET,
+                step(&log, "bootstrap", Duration::from_millis(20)),
+            )
+            .phase("failing", Priority::High, BUDGET, async {
+                Err(eyre::eyre!("no fish"))
+            })
+            .phase(
+                "notify",
+                Priority::Low,
+                BUDGET,
+                step(&log, "notify", Duration::ZERO),
+            )
+            .run()
+            .await;
+
+        // a failure doesn't stop what comes after it
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["detect", "bootstrap", "reconcile", "notify"]
+        );
+
+        let names: Vec<_> = report.phases.iter().map(|phase| phase.name).collect();
+        assert_eq!(
+            names,
+            vec!["detect", "bootstrap", "failing", "reconcile", "notify"]
+        );
+        assert_eq!(
+            report.phases[2].outcome,
+            Outcome::Failed("no fish".to_string())
+        );
+
+        // the phases that can wait were held back, once between them
+        let deferred: Vec<_> = report
+            .phases
+            .iter()
+            .map(|phase| !phase.deferred.is_zero())
+            .collect();
+        assert_eq!(deferred, vec![false, false, false, true, false]);
+
+        let line = report.to_string();
+        assert!(line.starts_with("detect "), "{line}");
+        assert!(line.contains("(failed: no fish), reconcile"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn test_over_budget_defers_the_rest() {
+        let log = Log::default();
+        let defer = Duration::from_millis(50);
+
+        let report = Startup::new(defer)
+            .phase(
+                "bootstrap",
+                Priority::High,
+                Duration::from_millis(10),
+                step(&log, "bootstrap", Duration::from_millis(40)),
+            )
+            .phase(
+                "import",
+                Priority::High,
+                BUDGET,
+                step(&log, "import", Duration::ZERO),
+            )
+            .phase(
+                "reconcile",
+                Priority::Low,
+                BUDGET,
+                step(&log, "reconcile", Duration::ZERO),
+            )
+            .run()
+            .await;
+
+        // the slow phase ran to the end, and so did everything after it
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["bootstrap", "import", "reconcile"]
+        );
+        assert_eq!(report.phases[0].outcome, Outcome::OverBudget);
+        assert!(report.phases[0].elapsed >= Duration::from_millis(40));
+
+        // the next phase waited, though it's urgent
+        assert_eq!(report.phases[1].outcome, Outcome::Done);
+        assert_eq!(report.phases[1].deferred, defer);
+        assert_eq!(report.phases[2].deferred, defer);
+        assert!(report.to_string().contains("(over budget)"));
+    }
+}
@@ -1,10 +1,21 @@
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use ::time::OffsetDateTime;
 use eyre::Result;
 use rand::Rng;
+use tokio::task::JoinHandle;
 use tokio::time::{self, MissedTickBehavior};
 
 use atuin_client::database::Sqlite as HistoryDatabase;
 use atuin_client::{
     encryption,
+    fish_sync::{
+        self, DownloadedHistory, SyncSummary,
+        audit::WriteSource,
+        lock::{LockPolicy, ShellSyncLock},
+        metrics::{FISH_TARGET, TargetMetrics},
+    },
     history::store::HistoryStore,
     record::{sqlite_store::SqliteStore, sync},
     settings::Settings,
@@ -12,11 +23,158 @@ use atuin_client::{
 
 use atuin_dotfiles::store::{AliasStore, var::VarStore};
 
+use super::shell_sync::SharedShellSync;
+use super::startup::{Priority, Startup};
+
+/// How often a bootstrap that made way for downloaded batches checks whether they're done
+const BOOTSTRAP_YIELD_POLL: Duration = Duration::from_millis(50);
+
+/// How long startup phases may take before the ones after them are held back, see [`Startup`]
+const DETECT_BUDGET: Duration = Duration::from_secs(5);
+const BOOTSTRAP_BUDGET: Duration = Duration::from_secs(30);
+const RECONCILE_BUDGET: Duration = Duration::from_secs(30);
+
+/// Seed the fish history file on startup, unless another process is already writing to it
+///
+/// Downloaded batches go first. Whenever one is queued, the bootstrap stops after the batch of its
+/// own it's writing, lets go of the lock until they're all written, then carries on from there.
+async fn bootstrap_fish(
+    settings: &Settings,
+    history_db: &HistoryDatabase,
+    shell_sync: &SharedShellSync,
+) -> Result<()> {
+    let mut policy = LockPolicy::Skip;
+
+    loop {
+        let Some(lock) = acquire_lock(settings, "daemon bootstrap", policy).await? else {
+            tracing::info!("fish history is being written elsewhere, skipping bootstrap");
+            return Ok(());
+        };
+
+        if !fish_sync::bootstrap_pending(settings)? {
+            return Ok(());
+        }
+
+        let mut yielded = false;
+        let written = fish_sync::bootstrap_with_progress(settings, history_db, |progress| {
+            tracing::debug!("fish {progress}");
+            let mut state = shell_sync.lock().expect("shell sync state lock poisoned");
+            state.bootstrap = Some(*progress);
+
+            if state.in_flight > 0 && progress.done < progress.total {
+                yielded = true;
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .await?;
+
+        if !yielded {
+            tracing::info!(written, "seeded fish history");
+            return Ok(());
+        }
+
+        tracing::debug!(written, "fish bootstrap making way for downloaded batches");
+        drop(lock);
+        wait_for_batches(shell_sync).await;
+
+        // a bootstrap that has started waits its turn, rather than being left half done
+        policy = LockPolicy::Wait;
+    }
+}
+
+/// Wait until no downloaded batch is queued or being written
+async fn wait_for_batches(shell_sync: &SharedShellSync) {
+    while shell_sync
+        .lock()
+        .expect("shell sync state lock poisoned")
+        .in_flight
+        > 0
+    {
+        time::sleep(BOOTSTRAP_YIELD_POLL).await;
+    }
+}
+
+/// The shell sync work to do once at startup, most urgent first
+fn shell_sync_startup(
+    settings: &Settings,
+    history_db: &HistoryDatabase,
+    shell_sync: &SharedShellSync,
+) -> Startup {
+    let defer = Duration::from_secs(settings.fish_sync.startup_defer_secs);
+
+    Startup::new(defer)
+        .phase("detect", Priority::High, DETECT_BUDGET, {
+            let settings = settings.clone();
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    fish_sync::ensure_fish(&settings, fish_sync::fish_installed)?;
+                    fish_sync::resolve_writable_history_path(&settings)?;
+                    Ok(())
+                })
+                .await?
+            }
+        })
+        .phase("bootstrap", Priority::High, BOOTSTRAP_BUDGET, {
+            let settings = settings.clone();
+            let history_db = history_db.clone();
+            let shell_sync = shell_sync.clone();
+            async move { bootstrap_fish(&settings, &history_db, &shell_sync).await }
+        })
+        .phase("reconcile", Priority::Low, RECONCILE_BUDGET, {
+            let settings = settings.clone();
+            let history_db = history_db.clone();
+            async move { reconcile_fish(&settings, &history_db).await }
+        })
+        .phase("notify", Priority::Low, DETECT_BUDGET, {
+            let settings = settings.clone();
+            async move {
+                flush_fish_notification(&settings).await;
+                Ok(())
+            }
+        })
+}
+
+/// Remove entries whose history was deleted while the daemon wasn't running, with
+/// `sync_deletes`, unless another process is writing fish history
+async fn reconcile_fish(settings: &Settings, history_db: &HistoryDatabase) -> Result<()> {
+    if !settings.fish_sync.sync_deletes {
+        return Ok(());
+    }
+
+    let Some(_lock) = acquire_lock(settings, "daemon reconcile", LockPolicy::Skip).await? else {
+        tracing::info!("fish history is being written elsewhere, skipping reconcile");
+        return Ok(());
+    };
+
+    let report = fish_sync::gc(settings, history_db, false).await?;
+    tracing::info!(
+        removed = report.removed,
+        checked = report.checked,
+        "reconciled fish history"
+    );
+
+    Ok(())
+}
+
+/// Take the fish sync lock without blocking the runtime while waiting for it
+async fn acquire_lock(
+    settings: &Settings,
+    actor: &'static str,
+    policy: LockPolicy,
+) -> Result<Option<ShellSyncLock>> {
+    let settings = settings.clone();
+
+    tokio::task::spawn_blocking(move || ShellSyncLock::acquire(&settings, actor, policy)).await?
+}
+
 pub async fn worker(
     settings: Settings,
     store: SqliteStore,
     history_store: HistoryStore,
     history_db: HistoryDatabase,
+    shell_sync: SharedShellSync,
 ) -> Result<()> {
     tracing::info!("booting sync worker");
 
@@ -25,6 +183,16 @@ pub async fn worker(
     let alias_store = AliasStore::new(store.clone(), host_id, encryption_key);
     let var_store = VarStore::new(store.clone(), host_id, encryption_key);
 
+    // in the background, so the bootstrap can make way for batches downloaded in the meantime
+    if settings.fish_sync.enabled && fish_sync::daemon_should_write(&settings) {
+        let startup = shell_sync_startup(&settings, &history_db, &shell_sync);
+
+        tokio::task::spawn(async move {
+            let report = startup.run().await;
+            tracing::info!("shell sync startup: {report}");
+        });
+    }
+
     // Don't backoff by more than 30 mins (with a random jitter of up to 1 min)
     let max_interval: f64 = 60.0 * 30.0 + rand::thread_rng().gen_range(0.0..60.0);
 
@@ -38,6 +206,10 @@ pub async fn worker(
         ticker.tick().await;
         tracing::info!("sync worker tick");
 
+        if settings.fish_sync.enabled && fish_sync::daemon_should_write(&settings) {
+            flush_fish_notification(&settings).await;
+        }
+
         if !settings.logged_in() {
             tracing::debug!("not logged in, skipping sync tick");
             continue;
@@ -77,18 +249,9 @@ pub async fn worker(
             var_store.build().await?;
 
             // Sync downloaded remote entries to Fish history after sync completes
-            if settings.fish_sync.enabled {
-                let settings_clone = settings.clone();
-                let history_db_clone = history_db.clone();
-                tokio::task::spawn(async move {
-                    if let Err(e) = atuin_client::fish_sync::sync_downloaded_entries(
-                        &settings_clone,
-                        &history_db_clone,
-                        &downloaded,
-                    ).await {
-                        tracing::error!(error = %e, "failed to sync remote entries to fish history");
-                    }
-                });
+            if fish_sync::syncs_downloaded(&settings) && fish_sync::daemon_should_write(&settings) {
+                let downloaded = DownloadedHistory::resolve(&history_store, &downloaded).await;
+                queue_fish_batch(&settings, &history_db, &shell_sync, downloaded);
             }
 
             // Reset backoff on success
@@ -101,3 +264,181 @@ pub async fn worker(
         }
     }
 }
+
+/// Write a downloaded batch to fish history in the background, counting it as in flight until
+/// it's done
+fn queue_fish_batch(
+    settings: &Settings,
+    history_db: &HistoryDatabase,
+    shell_sync: &SharedShellSync,
+    downloaded: DownloadedHistory,
+) -> JoinHandle<()> {
+    let settings = settings.clone();
+    let history_db = history_db.clone();
+    let shell_sync = shell_sync.clone();
+
+    shell_sync
+        .lock()
+        .expect("shell sync state lock poisoned")
+        .in_flight += 1;
+
+    tokio::task::spawn(async move {
+        write_fish_batch(settings, history_db, shell_sync, downloaded).await
+    })
+}
+
+/// Write a batch [`queue_fish_batch`] counted as in flight, recording how it went
+async fn write_fish_batch(
+    settings: Settings,
+    history_db: HistoryDatabase,
+    shell_sync: SharedShellSync,
+    downloaded: DownloadedHistory,
+) {
+    let result = sync_to_fish(settings, &history_db, &downloaded).await;
+
+    let mut state = shell_sync.lock().expect("shell sync state lock poisoned");
+    state.in_flight -= 1;
+
+    let batch = match result {
+        Ok(summary) => {
+            tracing::info!(
+                history_records = downloaded.history_records(),
+                other_records = downloaded.other,
+                "shell sync batch {}",
+                summary.log_line()
+            );
+            summary.to_metrics(OffsetDateTime::now_utc())
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to sync remote entries to fish history");
+            state.record_error(FISH_TARGET, e.to_string());
+            TargetMetrics {
+                errors: 1,
+                ..Default::default()
+            }
+        }
+    };
+
+    state.metrics.record(FISH_TARGET, &batch);
+
+    for line in state.metrics.summary_lines() {
+        tracing::debug!("shell sync totals {line}");
+    }
+}
+
+/// Send a fish notification that an earlier batch left pending, unless another writer is busy
+async fn flush_fish_notification(settings: &Settings) {
+    let settings = settings.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        if let Some(_lock) = ShellSyncLock::acquire(&settings, "daemon notify", LockPolicy::Skip)? {
+            fish_sync::notify::flush_pending(&settings);
+        }
+
+        Ok::<_, eyre::Report>(())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::warn!(error = %e, "failed to flush fish notification"),
+        Err(e) => tracing::warn!(error = %e, "fish notification task failed"),
+    }
+}
+
+/// Write a downloaded batch to fish history, waiting for any other writer to finish first
+async fn sync_to_fish(
+    settings: Settings,
+    history_db: &HistoryDatabase,
+    downloaded: &DownloadedHistory,
+) -> Result<SyncSummary> {
+    let _lock = acquire_lock(&settings, "daemon sync", LockPolicy::Wait).await?;
+
+    fish_sync::sync_downloaded_entries(&settings, history_db, downloaded, WriteSource::Daemon).await
+}
+
+#[cfg(test)]
+mod tests {
+    use atuin_client::database::Database;
+    use atuin_client::test_support::{HistoryBuilder, fish_settings};
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[allow(clippy::await_holding_lock)] // holding the state is how the test pauses the bootstrap
+    async fn downloaded_batch_is_written_mid_bootstrap() {
+        // SAFETY: only ever set to this value, before anything here reads it
+        unsafe { std::env::set_var(fish_sync::sandbox::SANDBOX_ENV, "1") };
+
+        let dir = tempfile::tempdir().unwrap();
+        let fish_path = dir.path().join("fish_history");
+        let settings = fish_settings(&fish_path);
+        let history_db = HistoryDatabase::new(&settings.db_path, 5.0).await.unwrap();
+
+        let seeded: Vec<_> = (0..fish_sync::BOOTSTRAP_ENTRIES as i64 - 1)
+            .map(|i| {
+                HistoryBuilder::new(format!("seeded {i}"))
+                    .id(format!("{i:032x}"))
+                    .timestamp(1_600_000_000 + i)
+                    .hostname("elsewhere:user")
+                    .build()
+            })
+            .collect();
+        let fresh = HistoryBuilder::new("fresh command")
+            .id(format!("{:032x}", u64::MAX))
+            .timestamp(1_700_000_000)
+            .hostname("elsewhere:user")
+            .build();
+        history_db.save_bulk(&seeded).await.unwrap();
+        history_db.save(&fresh).await.unwrap();
+        let downloaded = DownloadedHistory::from(vec![fresh.id.clone()]);
+
+        let shell_sync = SharedShellSync::default();
+
+        // holding the state stops the bootstrap as it reports its first batch
+        let mut state = shell_sync.lock().unwrap();
+
+        let bootstrap = tokio::task::spawn({
+            let settings = settings.clone();
+            let history_db = history_db.clone();
+            let shell_sync = shell_sync.clone();
+            async move { bootstrap_fish(&settings, &history_db, &shell_sync).await }
+        });
+
+        while fish_sync::count_synced_entries(&fish_path).unwrap() == 0 {
+            assert!(!bootstrap.is_finished(), "bootstrap ended before writing");
+            time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // a download arrives while the bootstrap is part way through
+        state.in_flight += 1;
+        drop(state);
+        write_fish_batch(settings.clone(), history_db, shell_sync.clone(), downloaded).await;
+
+        bootstrap.await.unwrap().unwrap();
+
+        let content = fs_err::read_to_string(&fish_path).unwrap();
+        let fresh_at = content.find("- cmd:fresh command\n").unwrap();
+        let last_seeded_at = content
+            .find(&format!(
+                "- cmd:seeded {}\n",
+                fish_sync::BOOTSTRAP_ENTRIES - 2
+            ))
+            .unwrap();
+        assert!(
+            fresh_at < last_seeded_at,
+            "the download waited for the whole bootstrap"
+        );
+        assert_eq!(
+            fish_sync::count_synced_entries(&fish_path).unwrap(),
+            fish_sync::BOOTSTRAP_ENTRIES
+        );
+
+        let state = shell_sync.lock().unwrap();
+        assert_eq!(state.in_flight, 0);
+        assert_eq!(
+            state.bootstrap.map(|progress| progress.done),
+            Some(fish_sync::BOOTSTRAP_ENTRIES)
+        );
+    }
+}
@@ -0,0 +1 @@
+tonic::include_proto!("shell_sync");
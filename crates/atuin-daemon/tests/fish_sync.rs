@@ -0,0 +1,471 @@
+//! End-to-end tests for the daemon's fish sync path
+//!
+//! Each test boots a real daemon on a temporary socket, with its own databases and fish history
+//! file. Set `ATUIN_E2E_SLOW=1` to also run the larger volume tests.
+#![cfg(unix)]
+
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use atuin_client::database::{Database, Sqlite};
+use atuin_client::encryption;
+use atuin_client::fish_sync;
+use atuin_client::fish_sync::audit::WriteSource;
+use atuin_client::fish_sync::filter::FilterRule;
+use atuin_client::fish_sync::meta::FishSyncMeta;
+use atuin_client::fish_sync::{DownloadedHistory, ShellSyncSession};
+use atuin_client::history::History;
+use atuin_client::history::store::HistoryStore;
+use atuin_client::record::sqlite_store::SqliteStore;
+use atuin_client::record::store::Store;
+use atuin_client::settings::Settings;
+use atuin_client::test_support::{
+    HistoryBuilder, assert_file_parses, count_entries, fish_settings,
+};
+use atuin_common::record::{HostId, RecordId};
+use atuin_common::utils::uuid_v7;
+use atuin_daemon::client::{HistoryClient, ShellSyncClient};
+use atuin_dotfiles::store::AliasStore;
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
+
+const TIMEOUT: f64 = 5.0;
+
+/// The daemon reads its host id from the data dir, so keep that out of the real home directory,
+/// and sandbox fish sync so a test can't write to the real fish history either
+fn isolate_data_dir() {
+    static DATA_DIR: OnceLock<TempDir> = OnceLock::new();
+
+    DATA_DIR.get_or_init(|| {
+        let dir = tempfile::tempdir().unwrap();
+        fs_err::create_dir_all(dir.path().join("atuin")).unwrap();
+        // SAFETY: runs once, before any test in this binary reads the environment
+        unsafe {
+            std::env::set_var("XDG_DATA_HOME", dir.path());
+            std::env::set_var(fish_sync::sandbox::SANDBOX_ENV, "1");
+        }
+        dir
+    });
+}
+
+fn slow_tests_enabled() -> bool {
+    std::env::var("ATUIN_E2E_SLOW").is_ok_and(|v| v == "1")
+}
+
+struct TestDaemon {
+    // held so the directory outlives the daemon
+    _dir: TempDir,
+    settings: Settings,
+    fish_path: PathBuf,
+    store: SqliteStore,
+    history_db: Sqlite,
+    server: JoinHandle<eyre::Result<()>>,
+}
+
+impl TestDaemon {
+    async fn start() -> Self {
+        isolate_data_dir();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+
+        let fish_path = dir.path().join("fish_history");
+
+        let mut settings = fish_settings(&fish_path);
+        settings.db_path = path("history.db");
+        settings.record_store_path = path("records.db");
+        settings.key_path = path("key");
+        settings.session_path = path("session");
+        settings.daemon.socket_path = path("atuin.sock");
+        settings.daemon.sync_frequency = 300;
+        settings.local_timeout = TIMEOUT;
+
+        let store = SqliteStore::new(&settings.record_store_path, TIMEOUT)
+            .await
+            .unwrap();
+        let history_db = Sqlite::new(&settings.db_path, TIMEOUT).await.unwrap();
+
+        let server = tokio::spawn(atuin_daemon::server::listen(
+            settings.clone(),
+            store.clone(),
+            history_db.clone(),
+        ));
+
+        let daemon = Self {
+            _dir: dir,
+            settings,
+            fish_path,
+            store,
+            history_db,
+            server,
+        };
+
+        daemon.wait_for_socket().await;
+        daemon
+    }
+
+    async fn wait_for_socket(&self) {
+        let socket = Path::new(&self.settings.daemon.socket_path);
+
+        for _ in 0..100 {
+            assert!(!self.server.is_finished(), "daemon exited during startup");
+
+            if socket.exists() {
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        panic!("daemon did not create its socket");
+    }
+
+    async fn client(&self) -> HistoryClient {
+        HistoryClient::new(self.settings.daemon.socket_path.clone())
+            .await
+            .unwrap()
+    }
+
+    /// Run a command through the daemon, the way the shell hooks do, returning its history id
+    async fn record(&self, command: &str) -> String {
+        let mut client = self.client().await;
+
+        let history = HistoryBuilder::new(command).build();
+        let id = client.start_history(history).await.unwrap();
+        client.end_history(id.clone(), 1_000, 0).await.unwrap();
+
+        id
+    }
+
+    /// Push a history record as if it had been written by another host and downloaded by sync,
+    /// then run the same post-sync steps the daemon's sync worker does
+    async fn download(&self, histories: Vec<History>) -> fish_sync::SyncSummary {
+        self.download_with(histories, Vec::new()).await
+    }
+
+    /// Like [`Self::download`], with `extra` record ids downloaded alongside
+    async fn download_with(
+        &self,
+        histories: Vec<History>,
+        extra: Vec<RecordId>,
+    ) -> fish_sync::SyncSummary {
+        let key: [u8; 32] = encryption::load_key(&self.settings).unwrap().into();
+        let remote = HistoryStore::new(self.store.clone(), HostId(uuid_v7()), key);
+
+        let mut downloaded = Vec::new();
+        for history in histories {
+            let (id, _) = remote.push(history).await.unwrap();
+            downloaded.push(id);
+        }
+        downloaded.extend(extra);
+
+        remote
+            .incremental_build(&self.history_db, &downloaded)
+            .await
+            .unwrap();
+
+        let downloaded = DownloadedHistory::resolve(&remote, &downloaded).await;
+        fish_sync::sync_downloaded_entries(
+            &self.settings,
+            &self.history_db,
+            &downloaded,
+            WriteSource::Daemon,
+        )
+        .await
+        .unwrap()
+    }
+}
+
+impl Drop for TestDaemon {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+fn remote_history(command: &str, ts: i64) -> History {
+    HistoryBuilder::new(command)
+        .id(uuid_v7().as_simple().to_string())
+        .timestamp(ts)
+        .hostname("remote")
+        .build()
+}
+
+#[tokio::test]
+async fn recorded_commands_are_not_mirrored_to_fish() {
+    let daemon = TestDaemon::start().await;
+
+    let id = daemon.record("cargo build").await;
+
+    let saved = daemon.history_db.load(&id).await.unwrap().unwrap();
+    assert_eq!(saved.command, "cargo build");
+
+    // fish already has its own local commands, only remote ones get mirrored
+    assert_eq!(count_entries(&daemon.fish_path).unwrap(), 0);
+}
+
+#[tokio::test]
+async fn downloaded_entries_reach_fish() {
+    let daemon = TestDaemon::start().await;
+
+    let metrics = daemon
+        .download(vec![
+            remote_history("make deploy", 1_700_000_000),
+            remote_history("echo 'two\nlines'", 1_700_000_001),
+        ])
+        .await;
+
+    assert_eq!(metrics.written, 2);
+    assert_file_parses(&daemon.fish_path);
+
+    let content = fs_err::read_to_string(&daemon.fish_path).unwrap();
+    assert!(content.contains("- cmd:make deploy\n  when:1700000000\n"));
+    assert!(content.contains("- cmd:echo 'two\\nlines'\n  when:1700000001\n"));
+}
+
+#[tokio::test]
+async fn only_history_records_are_synced() {
+    let daemon = TestDaemon::start().await;
+
+    // a sync downloads every kind of record, not just history
+    let key: [u8; 32] = encryption::load_key(&daemon.settings).unwrap().into();
+    let host_id = HostId(uuid_v7());
+    let aliases = AliasStore::new(daemon.store.clone(), host_id, key);
+    aliases.set("ll", "ls -l").await.unwrap();
+    aliases.set("gs", "git status").await.unwrap();
+    let alias_ids: Vec<_> = daemon
+        .store
+        .all_tagged("config-shell-alias")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|record| record.id)
+        .collect();
+    assert_eq!(alias_ids.len(), 2);
+
+    let remote = HistoryStore::new(daemon.store.clone(), host_id, key);
+    let push = |command: &str, ts: i64| {
+        let remote = remote.clone();
+        let history = remote_history(command, ts);
+        async move { remote.push(history).await.unwrap().0 }
+    };
+    let deleted = remote_history("rm -rf build", 1_700_000_001).id;
+
+    // interleaved, as records arrive from the server
+    let downloaded = vec![
+        push("make deploy", 1_700_000_000).await,
+        alias_ids[0],
+        push("make test", 1_700_000_002).await,
+        alias_ids[1],
+        remote.delete(deleted).await.unwrap().0,
+    ];
+    remote
+        .incremental_build(&daemon.history_db, &downloaded)
+        .await
+        .unwrap();
+
+    let resolved = DownloadedHistory::resolve(&remote, &downloaded).await;
+    assert_eq!(resolved.len(), 2);
+    assert_eq!(resolved.history_records(), 3);
+    assert_eq!(
+        (resolved.deletions, resolved.other, resolved.unresolved),
+        (1, 2, 0)
+    );
+
+    let summary = fish_sync::sync_downloaded_entries(
+        &daemon.settings,
+        &daemon.history_db,
+        &resolved,
+        WriteSource::Daemon,
+    )
+    .await
+    .unwrap();
+
+    // nothing else was looked up as history, so nothing was missing
+    assert_eq!(summary.written, 2);
+    assert!(summary.filtered.is_empty(), "{summary}");
+    assert_eq!(count_entries(&daemon.fish_path).unwrap(), 2);
+}
+
+#[tokio::test]
+async fn unresolved_downloads_are_counted() {
+    let daemon = TestDaemon::start().await;
+
+    // a record id the store doesn't have, as a sync interrupted part way might hand over
+    let metrics = daemon
+        .download_with(
+            vec![remote_history("make deploy", 1_700_000_000)],
+            vec![RecordId(uuid_v7())],
+        )
+        .await;
+
+    assert_eq!(metrics.written, 1);
+    assert_eq!(
+        metrics.filtered.iter().collect::<Vec<_>>(),
+        vec![(&FilterRule::unresolved(), 1)]
+    );
+    assert_eq!(count_entries(&daemon.fish_path).unwrap(), 1);
+}
+
+#[tokio::test]
+async fn replayed_downloads_are_not_written_again() {
+    let daemon = TestDaemon::start().await;
+
+    let histories: Vec<_> = (0..20)
+        .map(|i| remote_history(&format!("make target-{i}"), 1_700_000_000 + i))
+        .collect();
+    daemon.history_db.save_bulk(&histories).await.unwrap();
+    let ids: DownloadedHistory = histories
+        .iter()
+        .map(|history| history.id.clone())
+        .collect::<Vec<_>>()
+        .into();
+
+    // a daemon that restarts can hand the same batch over again
+    for replay in 0..3 {
+        let session = ShellSyncSession::new();
+        let summary = fish_sync::sync_downloaded_entries_in_session(
+            &session,
+            &daemon.settings,
+            &daemon.history_db,
+            &ids,
+            WriteSource::Daemon,
+            |_| ControlFlow::Continue(()),
+        )
+        .await
+        .unwrap();
+
+        // the file is read once for the whole batch, not once per entry
+        assert!(session.parses() <= 1, "{}", session.parses());
+        assert_eq!(count_entries(&daemon.fish_path).unwrap(), 20);
+
+        if replay > 0 {
+            assert_eq!(summary.written, 0);
+            assert_eq!(summary.duplicates, 20);
+        }
+    }
+
+    assert_file_parses(&daemon.fish_path);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn daemon_and_cli_writes_do_not_interleave() {
+    let daemon = TestDaemon::start().await;
+
+    let daemon_histories: Vec<_> = (0..200)
+        .map(|i| remote_history(&format!("daemon command {i}"), 1_700_000_000 + i))
+        .collect();
+    daemon
+        .history_db
+        .save_bulk(&daemon_histories)
+        .await
+        .unwrap();
+    let ids: DownloadedHistory = daemon_histories
+        .iter()
+        .map(|history| history.id.clone())
+        .collect::<Vec<_>>()
+        .into();
+
+    let cli_histories: Vec<_> = (0..200)
+        .map(|i| remote_history(&format!("cli command {i}"), 1_700_001_000 + i))
+        .collect();
+
+    // a manual `atuin sync` writing one entry at a time, while the daemon writes its batch
+    let settings = daemon.settings.clone();
+    let cli = std::thread::spawn(move || {
+        for history in &cli_histories {
+            fish_sync::sync_entry(history, &settings).unwrap();
+        }
+    });
+
+    let summary = fish_sync::sync_downloaded_entries(
+        &daemon.settings,
+        &daemon.history_db,
+        &ids,
+        WriteSource::Daemon,
+    )
+    .await
+    .unwrap();
+    cli.join().unwrap();
+
+    assert_eq!(summary.written, 200);
+    assert_file_parses(&daemon.fish_path);
+    assert_eq!(count_entries(&daemon.fish_path).unwrap(), 400);
+
+    // every command is directly followed by its own timestamp
+    let content = fs_err::read_to_string(&daemon.fish_path).unwrap();
+    for i in 0..200 {
+        assert!(content.contains(&format!(
+            "- cmd:daemon command {i}\n  when:{}\n",
+            1_700_000_000 + i
+        )));
+        assert!(content.contains(&format!(
+            "- cmd:cli command {i}\n  when:{}\n",
+            1_700_001_000 + i
+        )));
+    }
+}
+
+#[tokio::test]
+async fn shell_sync_state_round_trips() {
+    let daemon = TestDaemon::start().await;
+
+    let mut client = ShellSyncClient::new(daemon.settings.daemon.socket_path.clone())
+        .await
+        .unwrap();
+
+    let state = client.state(false).await.unwrap();
+    assert_eq!(state.version, 7);
+    assert_eq!(state.queue_depth, 0);
+    assert!(state.metrics.is_empty());
+    assert!(state.recent_errors.is_empty());
+
+    let settings = state.settings.unwrap();
+    assert!(settings.enabled);
+    assert_eq!(
+        settings.history_path,
+        daemon.fish_path.to_string_lossy().to_string()
+    );
+    assert_eq!(settings.prefer, "daemon");
+    assert_eq!(settings.notify, "none");
+    assert_eq!(settings.rate_limit_per_min, 60);
+    assert!(!settings.require_fish);
+    assert!(settings.sync_downloaded);
+
+    let redacted = client.state(true).await.unwrap().settings.unwrap();
+    assert_eq!(redacted.history_path, "fish_history");
+
+    // nothing written yet
+    let file = state.file.unwrap();
+    assert_eq!(file.total_written, 0);
+    assert_eq!(file.last_sync, None);
+
+    // the state file is read again once it changes
+    let meta_path = FishSyncMeta::path(&daemon.settings);
+    let mut meta = FishSyncMeta::default();
+    meta.record_sync(3, "- cmd:ls\n  when:1\n");
+    meta.save(&meta_path).unwrap();
+
+    let file = client.state(false).await.unwrap().file.unwrap();
+    assert_eq!(file.total_written, 3);
+    assert_eq!(file.fish_entries, 1);
+    assert!(file.last_sync.is_some());
+    assert_eq!(file.last_error, "");
+}
+
+#[tokio::test]
+async fn many_recorded_commands() {
+    if !slow_tests_enabled() {
+        return;
+    }
+
+    let daemon = TestDaemon::start().await;
+
+    for i in 0..500 {
+        let id = daemon.record(&format!("echo {i}")).await;
+        assert!(daemon.history_db.load(&id).await.unwrap().is_some());
+    }
+
+    assert_eq!(count_entries(&daemon.fish_path).unwrap(), 0);
+}
@@ -20,6 +20,7 @@ mod daemon;
 mod default_config;
 mod doctor;
 mod dotfiles;
+mod fish_sync;
 mod history;
 mod import;
 mod info;
@@ -72,6 +73,9 @@ pub enum Cmd {
     #[command(subcommand)]
     Scripts(scripts::Cmd),
 
+    /// Inspect and manage syncing history into fish's own history file
+    FishSync(fish_sync::Cmd),
+
     /// Print Atuin's shell init script
     #[command()]
     Init(init::Cmd),
@@ -135,6 +139,7 @@ impl Cmd {
             Self::History(history) => return history.run(&settings).await,
             Self::Init(init) => return init.run(&settings).await,
             Self::Doctor => return doctor::run(&settings).await,
+            Self::FishSync(fish_sync) => return fish_sync.run(&settings).await,
             _ => {}
         }
 
@@ -181,7 +186,7 @@ impl Cmd {
             #[cfg(feature = "daemon")]
             Self::Daemon => daemon::run(settings, sqlite_store, db).await,
 
-            Self::History(_) | Self::Init(_) | Self::Doctor => unreachable!(),
+            Self::History(_) | Self::Init(_) | Self::Doctor | Self::FishSync(_) => unreachable!(),
         }
     }
 }
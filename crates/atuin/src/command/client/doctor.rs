@@ -1,7 +1,9 @@
 use std::process::Command;
 use std::{env, path::PathBuf, str::FromStr};
 
-use atuin_client::database::Sqlite;
+use atuin_client::database::{Database, Sqlite};
+use atuin_client::fish_sync::{self, consistency};
+use atuin_client::record::{sqlite_store::SqliteStore, store::Store};
 use atuin_client::settings::Settings;
 use atuin_common::shell::{Shell, shell_name};
 use atuin_common::utils;
@@ -263,6 +265,7 @@ struct SettingPaths {
     record_store: String,
     key: String,
     session: String,
+    fish_history_issue: Option<String>,
 }
 
 impl SettingPaths {
@@ -272,9 +275,38 @@ impl SettingPaths {
             record_store: settings.record_store_path.clone(),
             key: settings.key_path.clone(),
             session: settings.session_path.clone(),
+            fish_history_issue: Self::fish_history_issue(settings),
         }
     }
 
+    fn fish_history_issue(settings: &Settings) -> Option<String> {
+        if !settings.fish_sync.enabled {
+            return None;
+        }
+
+        let path = fish_sync::resolve_history_path(settings).ok()?;
+
+        if !settings.fish_sync.allow_unsafe_path
+            && let Some(reason) = fish_sync::unsafe_path_reason(settings, &path)
+        {
+            return Some(format!(
+                "[Fish sync] fish_sync.history_path ({}) is unsafe to write to: {reason}. Fish sync will refuse to write until it is fixed, or fish_sync.allow_unsafe_path is set.",
+                path.display()
+            ));
+        }
+
+        if !settings.fish_sync.allow_foreign_owner
+            && let Some(reason) = fish_sync::owner::foreign_owner_reason(&path)
+        {
+            return Some(format!(
+                "[Fish sync] fish_sync.history_path ({}) looks like another user's fish history: {reason}. Fish sync will refuse to write until it is fixed, or fish_sync.allow_foreign_owner is set.",
+                path.display()
+            ));
+        }
+
+        None
+    }
+
     pub fn verify(&self) {
         let paths = vec![
             ("ATUIN_DB_PATH", &self.db),
@@ -290,6 +322,10 @@ impl SettingPaths {
                 );
             }
         }
+
+        if let Some(issue) = &self.fish_history_issue {
+            println!("{}", issue.bold().red());
+        }
     }
 }
 
@@ -337,11 +373,127 @@ impl AtuinInfo {
     }
 }
 
+#[derive(Debug, Serialize)]
+struct FishSyncInfo {
+    pub history_path: String,
+
+    /// The most entries fish keeps, as reported by fish or its default
+    pub fish_history_max: usize,
+
+    pub max_entries: usize,
+
+    /// What atuin actually trims to, once fish's own limit is taken into account
+    pub effective_max_entries: usize,
+
+    /// Set when the history file has grown past the warning thresholds
+    pub growth_warning: Option<String>,
+
+    pub fish_installed: bool,
+
+    /// As reported by `fish --version`
+    pub fish_version: Option<String>,
+
+    pub require_fish: bool,
+
+    /// Why fish sync turns itself off, when fish has its own history API
+    pub native_history: Option<String>,
+
+    /// History counts in the index, the record store and the fish history file
+    pub consistency: Option<String>,
+
+    /// Differences between those counts that fish sync can't explain
+    pub anomalies: Vec<String>,
+
+    /// How many malformed lines the fish history file has
+    pub parse_issue_count: usize,
+
+    /// The first few of them, with where they are
+    pub parse_issues: Vec<String>,
+}
+
+impl FishSyncInfo {
+    pub async fn new(settings: &Settings) -> Option<Self> {
+        if !settings.fish_sync.enabled {
+            return None;
+        }
+
+        let fish_history_max = fish_sync::fish_history_max();
+        let path = fish_sync::resolve_history_path(settings);
+
+        let growth_warning = path.as_ref().ok().and_then(|path| {
+            let entries = fish_sync::count_entries(path).ok()?;
+            let bytes = fs_err::metadata(path).ok()?.len();
+            fish_sync::growth_warning(settings, entries as u64, bytes)
+        });
+
+        let parse_issues = path
+            .as_ref()
+            .ok()
+            .and_then(|path| fish_sync::parse_issues(path).ok())
+            .unwrap_or_default();
+
+        let mut info = Self {
+            history_path: path.map_or_else(|e| e.to_string(), |p| p.display().to_string()),
+            fish_history_max,
+            max_entries: settings.fish_sync.max_entries,
+            effective_max_entries: fish_sync::effective_max_entries(settings, fish_history_max),
+            growth_warning,
+            fish_installed: fish_sync::fish_installed(),
+            fish_version: fish_sync::fish_version().ok(),
+            require_fish: settings.fish_sync.require_fish,
+            native_history: fish_sync::native_history(settings).map(|native| native.to_string()),
+            consistency: None,
+            anomalies: Vec::new(),
+            parse_issue_count: parse_issues.len(),
+            parse_issues: parse_issues
+                .iter()
+                .take(fish_sync::MAX_SHOWN_ISSUES)
+                .map(ToString::to_string)
+                .collect(),
+        };
+
+        if let Some(consistency) = check_consistency(settings).await {
+            info.consistency = Some(consistency.to_string());
+            info.anomalies = consistency
+                .notes()
+                .iter()
+                .filter(|note| note.is_anomaly())
+                .map(ToString::to_string)
+                .collect();
+        }
+
+        Some(info)
+    }
+}
+
+/// Cross-check the history counts, if there are databases to count in. Doctor shouldn't be the one
+/// to create them.
+async fn check_consistency(settings: &Settings) -> Option<consistency::Consistency> {
+    let db_path = PathBuf::from(&settings.db_path);
+    let store_path = PathBuf::from(&settings.record_store_path);
+
+    if !db_path.exists() || !store_path.exists() {
+        return None;
+    }
+
+    let db = Sqlite::new(db_path, settings.local_timeout).await.ok()?;
+    let store = SqliteStore::new(store_path, settings.local_timeout)
+        .await
+        .ok()?;
+
+    #[allow(clippy::cast_sign_loss)]
+    let index = db.history_count(true).await.ok()? as u64;
+    let store = store.len_tag("history").await.ok()?;
+
+    consistency::check(settings, index, store).ok()
+}
+
 #[derive(Debug, Serialize)]
 struct DoctorDump {
     pub atuin: AtuinInfo,
     pub shell: ShellInfo,
     pub system: SystemInfo,
+    pub fish_sync: Option<FishSyncInfo>,
 }
 
 impl DoctorDump {
@@ -350,6 +502,80 @@ impl DoctorDump {
             atuin: AtuinInfo::new(settings).await,
             shell: ShellInfo::new(),
             system: SystemInfo::new(),
+            fish_sync: FishSyncInfo::new(settings).await,
+        }
+    }
+}
+
+fn fish_sync_checks(fish: &FishSyncInfo) {
+    if fish.max_entries > fish.fish_history_max {
+        let consequence = if fish.effective_max_entries > fish.fish_history_max {
+            "Fish will keep discarding entries that Atuin re-adds, as fish_sync.respect_fish_history_max is off."
+        } else {
+            "Atuin only keeps as many as fish does."
+        };
+
+        println!(
+            "{}",
+            format!(
+                "[Fish sync] fish_sync.max_entries ({}) is more than fish keeps ({}). {consequence}",
+                fish.max_entries, fish.fish_history_max
+            )
+            .bold()
+            .red()
+        );
+    }
+
+    if !fish.fish_installed {
+        let message = if fish.require_fish {
+            "[Fish sync] Fish is not installed, and fish_sync.require_fish is set, so fish sync won't write anything.".bold().red()
+        } else {
+            "[Fish sync] Fish is not installed. Fish sync writes the history file anyway, as fish_sync.require_fish is off.".bold().yellow()
+        };
+
+        println!("{message}");
+    }
+
+    if let Some(native) = &fish.native_history {
+        println!(
+            "{}",
+            format!(
+                "[Fish sync] {native}. Atuin stops writing the fish history file, and fish sync stays off until fish_sync.disable_if_native is unset."
+            )
+            .bold()
+            .red()
+        );
+    }
+
+    if let Some(warning) = &fish.growth_warning {
+        println!("{}", format!("[Fish sync] {warning}").bold().yellow());
+    }
+
+    for anomaly in &fish.anomalies {
+        println!(
+            "{}",
+            format!(
+                "[Fish sync] {}: {anomaly}",
+                fish.consistency.as_deref().unwrap_or_default()
+            )
+            .bold()
+            .yellow()
+        );
+    }
+
+    if fish.parse_issue_count > 0 {
+        println!(
+            "{}",
+            format!(
+                "[Fish sync] The fish history file has {} malformed lines, which fish sync skips over. Run `atuin fish-sync verify` to list them.",
+                fish.parse_issue_count
+            )
+            .bold()
+            .yellow()
+        );
+
+        for issue in &fish.parse_issues {
+            println!("  {issue}");
         }
     }
 }
@@ -368,6 +594,10 @@ fn checks(info: &DoctorDump) {
 
     info.atuin.setting_paths.verify();
 
+    if let Some(fish) = &info.fish_sync {
+        fish_sync_checks(fish);
+    }
+
     // Shell
     if info.shell.name == "bash" {
         if !info
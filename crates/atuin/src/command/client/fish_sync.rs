@@ -0,0 +1,239 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Subcommand};
+use eyre::{Result, bail};
+
+use atuin_client::{
+    database::Sqlite,
+    fish_sync::{
+        self, FishSyncer,
+        lock::{LockPolicy, ShellSyncLock, Unlocked},
+    },
+    settings::Settings,
+};
+
+mod dedupe;
+mod diff;
+mod gc;
+mod path;
+mod plan;
+mod stats;
+mod status;
+mod trim;
+mod verify;
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Fail instead of waiting longer than this for another process writing fish history, e.g.
+    /// `30s`
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    max_lock_wait: Option<Duration>,
+}
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Command {
+    /// Print the resolved path of the fish history file that Atuin writes to
+    Path {
+        /// Also ask fish where it reads history from, and fail if the two differ
+        #[arg(long)]
+        verify: bool,
+    },
+
+    /// Remove old entries from the fish history file
+    Trim(trim::Cmd),
+
+    /// Show what trimming to some limits would remove, by host and age, without changing anything
+    Plan(plan::Cmd),
+
+    /// Remove entries Atuin wrote to the fish history file whose history has since been deleted
+    Gc(gc::Cmd),
+
+    /// Show what's in Atuin's database but not the fish history file, and the other way round
+    Diff(diff::Cmd),
+
+    /// Remove every entry in the fish history file but the newest of each command
+    Dedupe(dedupe::Cmd),
+
+    /// Check the fish history file for copies, malformed entries and ids on the wrong command
+    Verify(verify::Cmd),
+
+    /// Remove the comments Atuin adds to fish history entries, leaving everything else alone
+    Clean,
+
+    /// Write Atuin's history to the fish history file now, from the local database
+    Run,
+
+    /// Remove every entry Atuin wrote from the fish history file, leaving fish's own alone
+    Clear,
+
+    /// Write the fish history file again from Atuin's database, replacing everything Atuin wrote
+    Rebuild {
+        /// Keep the entries fish wrote itself. The default
+        #[arg(long, conflicts_with = "drop_native")]
+        keep_native: bool,
+
+        /// Drop the entries fish wrote itself, and write this machine's history from the database
+        /// too
+        #[arg(long)]
+        drop_native: bool,
+    },
+
+    /// Save the commands fish wrote to its history file itself to Atuin's database
+    Import,
+
+    /// Show how much Atuin has written to the fish history file
+    Stats(stats::Cmd),
+
+    /// Show the state of fish sync, from the daemon if it's running
+    Status(status::Cmd),
+
+    /// Show which process holds the fish sync lock, and remove it if that process has exited
+    Unlock {
+        /// Remove the lock, once it's confirmed that its holder is no longer running
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Quick health check for shell init. Exits 0 if healthy, 1 if disabled, 2 if unhealthy
+    Ok {
+        /// Print one line explaining the status
+        #[arg(long)]
+        explain: bool,
+    },
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        self.command
+            .run(settings, LockPolicy::wait(self.max_lock_wait))
+            .await
+    }
+}
+
+impl Command {
+    async fn run(self, settings: &Settings, policy: LockPolicy) -> Result<()> {
+        match self {
+            Self::Path { verify } => path::run(settings, verify),
+            Self::Trim(trim) => trim.run(settings, policy),
+            Self::Plan(plan) => plan.run(settings).await,
+            Self::Gc(gc) => gc.run(settings, policy).await,
+            Self::Diff(diff) => diff.run(settings).await,
+            Self::Verify(verify) => verify.run(settings, policy).await,
+            Self::Stats(stats) => stats.run(settings).await,
+            Self::Status(status) => status.run(settings).await,
+            Self::Dedupe(dedupe) => dedupe.run(settings, policy),
+            Self::Clean => {
+                let _lock = ShellSyncLock::acquire(settings, "fish-sync clean", policy)?;
+                let removed = writable_syncer(settings)?.strip_metadata()?;
+                println!("Removed {removed} Atuin comments");
+                Ok(())
+            }
+            Self::Run => {
+                if !settings.fish_sync.enabled {
+                    bail!("fish sync is disabled. Set fish_sync.enabled = true to turn it on");
+                }
+                refuse_if_native(settings)?;
+
+                let db =
+                    Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+                let _lock = ShellSyncLock::acquire(settings, "fish-sync run", policy)?;
+                let written = fish_sync::sync_local(settings, &db).await?;
+                println!("Wrote {written} entries to fish history");
+                Ok(())
+            }
+            Self::Clear => {
+                let _lock = ShellSyncLock::acquire(settings, "fish-sync clear", policy)?;
+                let report = fish_sync::clear(settings)?;
+                println!(
+                    "Removed {} of {} entries, the ones written by Atuin",
+                    report.removed, report.checked
+                );
+                Ok(())
+            }
+            Self::Rebuild { drop_native, .. } => {
+                refuse_if_native(settings)?;
+
+                let db =
+                    Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+                let _lock = ShellSyncLock::acquire(settings, "fish-sync rebuild", policy)?;
+                let report = fish_sync::rebuild(settings, &db, !drop_native).await?;
+                println!(
+                    "Kept {} fish entries, dropped {} and wrote {} from Atuin",
+                    report.kept, report.dropped, report.written
+                );
+                Ok(())
+            }
+            Self::Import => {
+                let db =
+                    Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+                let _lock = ShellSyncLock::acquire(settings, "fish-sync import", policy)?;
+                let report = fish_sync::import_native_entries(settings, &db).await?;
+                println!(
+                    "Imported {} of {} entries fish wrote itself, {} were already in Atuin",
+                    report.imported, report.checked, report.existing
+                );
+                if report.skipped > 0 {
+                    println!("Skipped {} entries without a timestamp", report.skipped);
+                }
+                Ok(())
+            }
+            Self::Unlock { force } => unlock(settings, force),
+            Self::Ok { explain } => {
+                let health = fish_sync::health(settings);
+
+                if explain {
+                    println!("{}", health.explanation());
+                }
+
+                match health.exit_code() {
+                    0 => Ok(()),
+                    code => std::process::exit(code),
+                }
+            }
+        }
+    }
+}
+
+/// Fail if fish has its own history API, which fish sync leaves writing history to
+fn refuse_if_native(settings: &Settings) -> Result<()> {
+    if let Some(native) = fish_sync::native_history(settings) {
+        bail!("{native}. Unset fish_sync.disable_if_native to write it anyway");
+    }
+
+    Ok(())
+}
+
+fn unlock(settings: &Settings, force: bool) -> Result<()> {
+    if force {
+        match ShellSyncLock::force_unlock(settings)? {
+            Unlocked::NotHeld => println!("The fish sync lock isn't held"),
+            Unlocked::Removed(holder) => println!("Removed the fish sync lock held by {holder}"),
+        }
+
+        return Ok(());
+    }
+
+    // probe with a lock of our own, so a free lock isn't reported from a stale holder record
+    match ShellSyncLock::acquire(settings, "fish-sync unlock", LockPolicy::Skip)? {
+        Some(_) => println!("The fish sync lock isn't held"),
+        None => match ShellSyncLock::holder(settings) {
+            Some(holder) if holder.is_alive() => println!("Held by {holder}"),
+            Some(holder) => println!(
+                "Held by {holder}, which has exited. Run `atuin fish-sync unlock --force` to remove it"
+            ),
+            None => println!("Held by a process that didn't record itself"),
+        },
+    }
+
+    Ok(())
+}
+
+fn writable_syncer(settings: &Settings) -> Result<FishSyncer> {
+    let path = fish_sync::resolve_writable_history_path(settings)?;
+    FishSyncer::open(path, fish_sync::journal_options(settings))
+}
@@ -0,0 +1,40 @@
+use clap::Args;
+use eyre::Result;
+
+use atuin_client::{
+    fish_sync::{
+        self,
+        lock::{LockPolicy, ShellSyncLock},
+    },
+    settings::Settings,
+};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// Report how many entries would be removed, without changing the file
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Treat commands that differ only in whitespace as the same command
+    #[arg(long)]
+    ignore_whitespace: bool,
+}
+
+impl Cmd {
+    pub fn run(self, settings: &Settings, policy: LockPolicy) -> Result<()> {
+        let _lock = ShellSyncLock::acquire(settings, "dedupe", policy)?;
+        let report = fish_sync::dedupe_commands(settings, self.ignore_whitespace, self.dry_run)?;
+
+        let verb = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+        println!(
+            "{verb} {} of {} entries, keeping the newest of each command",
+            report.removed, report.checked
+        );
+
+        Ok(())
+    }
+}
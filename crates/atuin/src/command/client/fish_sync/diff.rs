@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::Result;
+
+use atuin_client::{
+    database::Sqlite,
+    fish_sync::{self, DiffGroup},
+    settings::Settings,
+};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Show at most this many entries of each group. The counts are of all of them
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let db = Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+        let mut report = fish_sync::diff(settings, &db).await?;
+
+        if let Some(limit) = self.limit {
+            report.truncate(limit);
+        }
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+
+        if report.is_empty() {
+            println!("The fish history file and Atuin's database agree");
+            return Ok(());
+        }
+
+        print_group("In Atuin, missing from fish", &report.missing_from_fish);
+        print_group("In fish, deleted from Atuin", &report.deleted_from_atuin);
+        print_group(
+            "Written by fish, missing from Atuin",
+            &report.missing_from_atuin,
+        );
+
+        Ok(())
+    }
+}
+
+fn print_group(title: &str, group: &DiffGroup) {
+    println!("{title}: {}", group.count);
+
+    for entry in &group.entries {
+        let when = entry
+            .when
+            .map_or_else(|| "-".to_string(), |when| when.to_string());
+        match &entry.id {
+            Some(id) => println!("  {when:>10}  {id}  {:?}", entry.command),
+            None => println!("  {when:>10}  {:?}", entry.command),
+        }
+    }
+
+    if group.entries.len() < group.count {
+        println!("  and {} more", group.count - group.entries.len());
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::Result;
+
+use atuin_client::{
+    database::Sqlite,
+    fish_sync::{
+        self,
+        lock::{LockPolicy, ShellSyncLock},
+    },
+    settings::Settings,
+};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// Report what would be removed, without changing the file
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Also put the whole file in timestamp order, oldest first
+    #[arg(long)]
+    sort: bool,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings, policy: LockPolicy) -> Result<()> {
+        let db = Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+
+        let _lock = ShellSyncLock::acquire(settings, "gc", policy)?;
+        let report = fish_sync::gc(settings, &db, self.dry_run).await?;
+
+        let verb = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+        println!(
+            "{verb} {} of {} entries written by Atuin",
+            report.removed, report.checked
+        );
+
+        if report.unannotated > 0 {
+            let verb = if self.dry_run {
+                "Would drop"
+            } else {
+                "Dropped"
+            };
+            println!(
+                "{verb} Atuin's comments from {} entries that don't match their history",
+                report.unannotated
+            );
+        }
+
+        if self.sort {
+            let moved = fish_sync::sort(settings, self.dry_run)?;
+            let verb = if self.dry_run { "Would move" } else { "Moved" };
+            println!("{verb} {moved} entries to put the file in timestamp order");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+use eyre::Result;
+
+use atuin_client::{fish_sync, settings::Settings};
+
+pub fn run(settings: &Settings, verify: bool) -> Result<()> {
+    let path = fish_sync::resolve_history_path(settings)?;
+
+    println!("{}", path.display());
+
+    if !verify {
+        return Ok(());
+    }
+
+    let fish_path = match fish_sync::query_fish_history_path() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Could not ask fish for its history location: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if fish_sync::is_same_file(&path, &fish_path) {
+        println!("fish reads history from the same file");
+        Ok(())
+    } else {
+        eprintln!("Mismatch: fish reads history from {}", fish_path.display());
+        eprintln!("Set fish_sync.history_path to that file so fish picks up synced entries");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, ValueEnum};
+use eyre::Result;
+use time::OffsetDateTime;
+
+use atuin_client::{
+    database::Sqlite,
+    fish_sync::{self, TrimLimits},
+    settings::Settings,
+};
+
+use super::trim::parse_size;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Args, Debug)]
+#[command(group(
+    clap::ArgGroup::new("limit")
+        .required(true)
+        .multiple(true)
+        .args(["max_entries", "max_age", "max_size"]),
+))]
+pub struct Cmd {
+    /// Keep at most this many entries
+    #[arg(long)]
+    max_entries: Option<usize>,
+
+    /// Remove entries older than this, e.g. 90d or 12weeks
+    #[arg(long, value_parser = humantime::parse_duration)]
+    max_age: Option<Duration>,
+
+    /// Shrink the file to at most this size, e.g. 10mb or 512kb
+    #[arg(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// How to print the result
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let limits = TrimLimits {
+            max_entries: self.max_entries,
+            max_age: self.max_age,
+            max_bytes: self.max_size,
+            ..TrimLimits::default()
+        };
+
+        let db = Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+        let simulation =
+            fish_sync::simulate_trim(settings, &db, &limits, OffsetDateTime::now_utc()).await?;
+
+        if self.format == Format::Json {
+            println!("{}", serde_json::to_string_pretty(&simulation)?);
+            return Ok(());
+        }
+
+        println!(
+            "Would remove {} of {} entries from {}, shrinking it from {} to {} bytes",
+            simulation.entries_removed,
+            simulation.entries_before,
+            simulation.path.display(),
+            simulation.bytes_before,
+            simulation.bytes_after
+        );
+
+        if simulation.entries_removed == 0 {
+            return Ok(());
+        }
+
+        println!();
+        println!("By host:");
+        print_counts(&simulation.by_host);
+
+        println!();
+        println!("By age:");
+        print_counts(&simulation.by_age);
+
+        Ok(())
+    }
+}
+
+fn print_counts(counts: &[(String, usize)]) {
+    let width = counts
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default();
+
+    for (name, count) in counts {
+        println!("  {name:<width$}  {count}");
+    }
+}
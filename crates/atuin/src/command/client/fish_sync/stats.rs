@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::Result;
+
+use atuin_client::{
+    database::{Database, Sqlite},
+    fish_sync::{self, meta::FishSyncMeta},
+    settings::Settings,
+};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// Break writes down by the code path that made them, as recorded with audit on
+    #[arg(long, conflicts_with = "by_host")]
+    by_source: bool,
+
+    /// Count the entries in the fish history file by the host their history came from
+    #[arg(long)]
+    by_host: bool,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        if self.by_host {
+            let db = Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+            let by_host = fish_sync::entries_by_host(settings, &db).await?;
+
+            if by_host.is_empty() {
+                println!("The fish history file is empty");
+            }
+
+            print_counts(&by_host);
+
+            return Ok(());
+        }
+
+        if !self.by_source {
+            let meta = FishSyncMeta::load(&FishSyncMeta::path(settings))?;
+
+            println!("commands mirrored to fish: {}", meta.total_written);
+            println!("entries in fish history: {}", meta.fish_entries);
+            println!("duplicates cleaned: {}", meta.duplicates_removed);
+
+            return Ok(());
+        }
+
+        let db = Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+        let by_source = db.fish_audit_by_source().await?;
+
+        if by_source.is_empty() {
+            if settings.fish_sync.audit {
+                println!("No writes recorded yet");
+            } else {
+                println!("No writes recorded. Set fish_sync.audit = true to record them");
+            }
+
+            return Ok(());
+        }
+
+        print_counts(&by_source);
+
+        Ok(())
+    }
+}
+
+fn print_counts(counts: &[(String, impl std::fmt::Display)]) {
+    let width = counts
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default();
+
+    for (name, count) in counts {
+        println!("{name:<width$}  {count}");
+    }
+}
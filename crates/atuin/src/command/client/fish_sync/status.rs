@@ -0,0 +1,199 @@
+use clap::Args;
+use eyre::Result;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use atuin_client::{
+    fish_sync::{self, meta::FishSyncMeta},
+    settings::Settings,
+};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// Read the state files directly, even if the daemon is running
+    #[arg(long)]
+    no_daemon: bool,
+}
+
+/// What the fish sync state file says, however it was read
+struct FileState {
+    total_written: u64,
+    fish_entries: u64,
+    atuin_entries: Option<u64>,
+    duplicates_removed: u64,
+    last_sync: Option<i64>,
+    last_failure: Option<i64>,
+    last_error: Option<String>,
+}
+
+impl From<FishSyncMeta> for FileState {
+    fn from(meta: FishSyncMeta) -> Self {
+        Self {
+            total_written: meta.total_written,
+            fish_entries: meta.fish_entries,
+            atuin_entries: meta.atuin_entries,
+            duplicates_removed: meta.duplicates_removed,
+            last_sync: meta.last_sync,
+            last_failure: meta.last_failure,
+            last_error: meta.last_error,
+        }
+    }
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        #[cfg(feature = "daemon")]
+        if !self.no_daemon && settings.daemon.enabled {
+            match daemon::state(settings).await {
+                Ok(state) => {
+                    daemon::print(&state);
+                    return Ok(());
+                }
+                Err(e) => tracing::debug!("couldn't ask the daemon for fish sync status: {e}"),
+            }
+        }
+
+        direct(settings)
+    }
+}
+
+/// Read the state file ourselves, without the daemon
+fn direct(settings: &Settings) -> Result<()> {
+    let meta = FishSyncMeta::load(&FishSyncMeta::path(settings))?;
+
+    println!("source: direct");
+    println!("enabled: {}", settings.fish_sync.enabled);
+    println!("sync downloaded: {}", settings.fish_sync.sync_downloaded);
+    println!("require fish: {}", settings.fish_sync.require_fish);
+    println!("fish installed: {}", fish_sync::fish_installed());
+    match fish_sync::resolve_history_path(settings) {
+        Ok(path) => println!("history file: {}", path.display()),
+        Err(e) => println!("history file: unknown ({e})"),
+    }
+    print_file_state(&meta.into());
+
+    Ok(())
+}
+
+fn print_file_state(file: &FileState) {
+    println!("commands mirrored to fish: {}", file.total_written);
+    match file.atuin_entries {
+        Some(atuin) => println!(
+            "entries in fish history: {} ({atuin} from Atuin, {} from fish)",
+            file.fish_entries,
+            file.fish_entries.saturating_sub(atuin)
+        ),
+        None => println!("entries in fish history: {}", file.fish_entries),
+    }
+    println!("duplicates cleaned: {}", file.duplicates_removed);
+    println!(
+        "last sync: {}",
+        file.last_sync
+            .map_or_else(|| "never".to_string(), format_time)
+    );
+
+    if let Some(error) = &file.last_error {
+        let when = file.last_failure.map(format_time).unwrap_or_default();
+        println!("last error: {error} ({when})");
+    }
+}
+
+fn format_time(ts: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(ts)
+        .ok()
+        .and_then(|t| t.format(&Rfc3339).ok())
+        .unwrap_or_else(|| ts.to_string())
+}
+
+#[cfg(feature = "daemon")]
+mod daemon {
+    use std::time::Duration;
+
+    use eyre::{Result, eyre};
+
+    use atuin_client::settings::Settings;
+    use atuin_daemon::{client::ShellSyncClient, shell_sync::ShellSyncState};
+
+    use super::{FileState, format_time, print_file_state};
+
+    /// A daemon that doesn't answer by then is treated as unreachable
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    pub async fn state(settings: &Settings) -> Result<ShellSyncState> {
+        let ask = async {
+            ShellSyncClient::new(
+                #[cfg(not(unix))]
+                settings.daemon.tcp_port,
+                #[cfg(unix)]
+                settings.daemon.socket_path.clone(),
+            )
+            .await?
+            .state(false)
+            .await
+        };
+
+        tokio::time::timeout(TIMEOUT, ask)
+            .await
+            .map_err(|_| eyre!("no answer within {}s", TIMEOUT.as_secs()))?
+    }
+
+    pub fn print(state: &ShellSyncState) {
+        println!("source: daemon");
+
+        if let Some(settings) = &state.settings {
+            println!("enabled: {}", settings.enabled);
+            if state.version >= 6 {
+                println!("sync downloaded: {}", settings.sync_downloaded);
+            }
+            if state.version >= 4 {
+                println!("require fish: {}", settings.require_fish);
+                println!("fish installed: {}", settings.fish_installed);
+            }
+            println!("history file: {}", settings.history_path);
+        }
+
+        match &state.file {
+            Some(file) => print_file_state(&FileState {
+                total_written: file.total_written,
+                fish_entries: file.fish_entries,
+                atuin_entries: file.atuin_entries,
+                duplicates_removed: file.duplicates_removed,
+                last_sync: file.last_sync,
+                last_failure: file.last_failure,
+                last_error: Some(file.last_error.clone()).filter(|error| !error.is_empty()),
+            }),
+            None if state.version < 5 => {
+                println!(
+                    "this daemon is too old to report the state file, restart it or use --no-daemon"
+                );
+            }
+            None => println!("the daemon couldn't read the state file"),
+        }
+
+        println!("queue depth: {}", state.queue_depth);
+
+        for metrics in &state.metrics {
+            println!(
+                "{} since the daemon started: written={} skipped={} (too old={}) deferred={} errors={}",
+                metrics.target,
+                metrics.written,
+                metrics.skipped,
+                metrics.too_old,
+                metrics.deferred,
+                metrics.errors
+            );
+        }
+
+        if let Some(bootstrap) = &state.bootstrap {
+            println!("{}", bootstrap.summary);
+        }
+
+        for error in &state.recent_errors {
+            println!(
+                "error at {}: {}: {}",
+                format_time(error.timestamp),
+                error.target,
+                error.message
+            );
+        }
+    }
+}
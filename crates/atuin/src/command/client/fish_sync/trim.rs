@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use clap::Args;
+use eyre::{Result, bail};
+use time::OffsetDateTime;
+
+use atuin_client::{
+    fish_sync::{
+        self, FishSyncer, TrimLimits, TrimRefused,
+        lock::{LockPolicy, ShellSyncLock},
+    },
+    settings::Settings,
+};
+
+/// Refuse to remove more than this fraction of the file without --force
+const MAX_FRACTION_WITHOUT_FORCE: f64 = 0.9;
+
+#[derive(Args, Debug)]
+#[command(group(
+    clap::ArgGroup::new("limit")
+        .required(true)
+        .multiple(true)
+        .args(["max_entries", "max_age", "max_size"]),
+))]
+pub struct Cmd {
+    /// Keep at most this many entries
+    #[arg(long)]
+    max_entries: Option<usize>,
+
+    /// Remove entries older than this, e.g. 90d or 12weeks
+    #[arg(long, value_parser = humantime::parse_duration)]
+    max_age: Option<Duration>,
+
+    /// Shrink the file to at most this size, e.g. 10mb or 512kb
+    #[arg(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Report what would be removed, without changing the file
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Allow removing more than 90% of the entries
+    #[arg(long)]
+    force: bool,
+}
+
+impl Cmd {
+    pub fn run(self, settings: &Settings, policy: LockPolicy) -> Result<()> {
+        let _lock = ShellSyncLock::acquire(settings, "trim", policy)?;
+        let path = fish_sync::resolve_writable_history_path(settings)?;
+        let syncer = FishSyncer::open(path, fish_sync::journal_options(settings))?;
+
+        let limits = TrimLimits {
+            max_entries: self.max_entries,
+            max_age: self.max_age,
+            max_bytes: self.max_size,
+            max_fraction: (!self.force).then_some(MAX_FRACTION_WITHOUT_FORCE),
+        };
+
+        let report = match syncer.trim_with(&limits, OffsetDateTime::now_utc(), self.dry_run) {
+            Ok(report) => report,
+            Err(e) => match e.downcast_ref::<TrimRefused>() {
+                Some(refused) => bail!(
+                    "this would remove {} of {} entries; pass --force if that's really what you want",
+                    refused.entries_removed,
+                    refused.entries_before
+                ),
+                None => return Err(e),
+            },
+        };
+
+        let verb = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+        println!(
+            "{verb} {} of {} entries, reclaiming {} bytes",
+            report.entries_removed,
+            report.entries_before,
+            report.bytes_reclaimed()
+        );
+
+        Ok(())
+    }
+}
+
+/// Parse a size like `10mb`, `512kb` or `4096`. Units are powers of 1024.
+pub(super) fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim().to_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {value}"))?;
+
+    let multiplier: u64 = match unit.trim() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        other => return Err(format!("unknown size unit: {other}")),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size too large: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_size;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512kb"), Ok(512 * 1024));
+        assert_eq!(parse_size("10MB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1 gib"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("mb").is_err());
+        assert!(parse_size("10 parsecs").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::Result;
+
+use atuin_client::{
+    database::Sqlite,
+    fish_sync::{
+        self,
+        lock::{LockPolicy, ShellSyncLock},
+    },
+    settings::Settings,
+};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// Fix what's found: entries get their history's command back, or lose an id another entry has
+    #[arg(long)]
+    repair: bool,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings, policy: LockPolicy) -> Result<()> {
+        let db = Sqlite::new(PathBuf::from(&settings.db_path), settings.local_timeout).await?;
+
+        let _lock = ShellSyncLock::acquire(settings, "verify", policy)?;
+        let report = fish_sync::verify(settings, &db, self.repair).await?;
+        let problems = report.problems(self.repair);
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            self.print(&report);
+        }
+
+        // so scripts can tell a clean file from one that needs looking at
+        if problems > 0 {
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    fn print(&self, report: &fish_sync::VerifyReport) {
+        for collision in &report.collisions {
+            println!("{}: history is {:?}", collision.uuid, collision.command);
+            for found in &collision.found {
+                println!("  also on {found:?}");
+            }
+        }
+
+        for issue in report.issues.iter().take(fish_sync::MAX_SHOWN_ISSUES) {
+            println!("malformed entry at {issue}");
+        }
+
+        if report.issues.len() > fish_sync::MAX_SHOWN_ISSUES {
+            println!(
+                "  and {} more",
+                report.issues.len() - fish_sync::MAX_SHOWN_ISSUES
+            );
+        }
+
+        for copy in &report.duplicate_ids {
+            let uuid = copy.uuid.as_deref().unwrap_or_default();
+            println!("{uuid}: on {} entries of {:?}", copy.copies, copy.command);
+        }
+
+        for copy in &report.duplicate_commands {
+            match copy.when {
+                Some(when) => println!("{:?} at {when}: {} copies", copy.command, copy.copies),
+                None => println!("{:?}: {} copies", copy.command, copy.copies),
+            }
+        }
+
+        for uuid in &report.missing_history {
+            println!("{uuid}: not in the database");
+        }
+
+        println!(
+            "Checked {} entries, {} written by Atuin, {} ids on the wrong command",
+            report.entries,
+            report.checked,
+            report.collisions.len()
+        );
+
+        if !report.duplicate_ids.is_empty() || !report.duplicate_commands.is_empty() {
+            println!(
+                "Found {} ids and {} commands more than once, run `atuin fish-sync dedupe` to remove the copies",
+                report.duplicate_ids.len(),
+                report.duplicate_commands.len()
+            );
+        }
+
+        if report.missing_when > 0 {
+            println!("Found {} entries without a timestamp", report.missing_when);
+        }
+
+        if report.out_of_order > 0 {
+            println!(
+                "Found {} entries older than the one before them, run `atuin fish-sync rebuild` to fix the order",
+                report.out_of_order
+            );
+        }
+
+        if !report.missing_history.is_empty() {
+            println!(
+                "Found {} ids whose history isn't in the database, run `atuin fish-sync gc` to remove them",
+                report.missing_history.len()
+            );
+        }
+
+        if !report.issues.is_empty() {
+            println!(
+                "Found {} malformed lines, which fish sync skips over",
+                report.issues.len()
+            );
+        }
+
+        if self.repair {
+            println!(
+                "Gave {} entries their history's command back, and dropped Atuin's comments from {}",
+                report.rewritten, report.unannotated
+            );
+        } else if !report.collisions.is_empty() {
+            println!("Run with --repair to fix them");
+        }
+    }
+}
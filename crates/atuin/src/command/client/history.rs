@@ -12,7 +12,7 @@ use runtime_format::{FormatKey, FormatKeyError, ParseSegment, ParsedFmt};
 
 use atuin_client::{
     database::{Database, Sqlite, current_context},
-    encryption,
+    encryption, fish_sync,
     history::{History, store::HistoryStore},
     record::sqlite_store::SqliteStore,
     settings::{
@@ -462,6 +462,23 @@ impl Cmd {
                     Settings::save_sync_time()?;
 
                     crate::sync::build(settings, &store, db, Some(&downloaded)).await?;
+
+                    // If the daemon is also running it will write these itself
+                    if fish_sync::syncs_downloaded(settings)
+                        && fish_sync::client_should_write(settings, || {
+                            fish_sync::daemon_is_running(settings)
+                        })
+                        && let Err(e) = fish_sync::sync_downloaded_entries(
+                            settings,
+                            db,
+                            &fish_sync::DownloadedHistory::resolve(&history_store, &downloaded)
+                                .await,
+                            fish_sync::audit::WriteSource::Cli,
+                        )
+                        .await
+                    {
+                        warn!("failed to sync remote entries to fish history: {e}");
+                    }
                 } else {
                     debug!("running periodic background sync");
                     sync::sync(settings, false, db).await?;
@@ -566,6 +583,8 @@ impl Cmd {
             n => println!("Found {n} entries to prune."),
         }
 
+        let ids: Vec<String> = matches.iter().map(|h| h.id.0.clone()).collect();
+
         if dry_run {
             print_list(
                 &matches,
@@ -592,6 +611,18 @@ impl Cmd {
                 }
             }
         }
+
+        if settings.fish_sync.enabled && settings.fish_sync.sync_deletes {
+            let removed = fish_sync::remove_entries_by_uuid(settings, &ids, dry_run)?;
+
+            match (dry_run, removed) {
+                (true, 1) => println!("Would also remove 1 entry from fish history."),
+                (true, n) => println!("Would also remove {n} entries from fish history."),
+                (false, 1) => println!("Also removed 1 entry from fish history."),
+                (false, n) => println!("Also removed {n} entries from fish history."),
+            }
+        }
+
         Ok(())
     }
 
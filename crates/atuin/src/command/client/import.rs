@@ -32,7 +32,12 @@ pub enum Cmd {
     /// Import history from the resh history file
     Resh,
     /// Import history from the fish history file
-    Fish,
+    Fish {
+        /// Import as history from this host, in the form `host:user`, rather than this machine.
+        /// Machines importing one shared fish history file under the same host don't duplicate it
+        #[arg(long)]
+        hostname: Option<String>,
+    },
     /// Import history from the nu history file
     Nu,
     /// Import history from the nu history file
@@ -125,7 +130,14 @@ impl Cmd {
             Self::Bash => import::<Bash, DB>(db).await,
             Self::Replxx => import::<Replxx, DB>(db).await,
             Self::Resh => import::<Resh, DB>(db).await,
-            Self::Fish => import::<Fish, DB>(db).await,
+            Self::Fish { hostname } => {
+                let mut fish = Fish::new().await?;
+                if let Some(hostname) = hostname {
+                    fish = fish.with_hostname(hostname);
+                }
+
+                import_from(fish, db).await
+            }
             Self::Nu => import::<Nu, DB>(db).await,
             Self::NuHistDb => import::<NuHistDb, DB>(db).await,
             Self::Xonsh => import::<Xonsh, DB>(db).await,
@@ -173,9 +185,12 @@ impl<DB: Database> Loader for HistoryImporter<'_, DB> {
 }
 
 async fn import<I: Importer + Send, DB: Database>(db: &DB) -> Result<()> {
+    import_from(I::new().await?, db).await
+}
+
+async fn import_from<I: Importer + Send, DB: Database>(mut importer: I, db: &DB) -> Result<()> {
     println!("Importing history from {}", I::NAME);
 
-    let mut importer = I::new().await?;
     let len = importer.entries().await.unwrap();
     let mut loader = HistoryImporter::new(db, len);
     importer.load(&mut loader).await?;
@@ -12,6 +12,7 @@ mod xonsh;
 mod zsh;
 
 #[derive(Parser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Cmd {
     shell: Shell,
 
@@ -22,6 +23,14 @@ pub struct Cmd {
     /// Disable the binding of the Up Arrow key to atuin
     #[clap(long)]
     disable_up_arrow: bool,
+
+    /// Include the fish sync hooks, even if fish sync is disabled in the config
+    #[clap(long, overrides_with = "no_fish_sync")]
+    fish_sync: bool,
+
+    /// Leave out the fish sync hooks, even if fish sync is enabled in the config
+    #[clap(long, overrides_with = "fish_sync")]
+    no_fish_sync: bool,
 }
 
 #[derive(Clone, Copy, ValueEnum, Debug)]
@@ -189,6 +198,18 @@ $env.config = (
             self.static_init();
         }
 
+        let fish_sync = if self.fish_sync || self.no_fish_sync {
+            self.fish_sync
+        } else {
+            settings.fish_sync.enabled
+        };
+
+        if matches!(self.shell, Shell::Fish)
+            && let Some(hooks) = fish::fish_sync_hooks(settings, fish_sync)
+        {
+            println!("{hooks}");
+        }
+
         Ok(())
     }
 }
@@ -1,3 +1,7 @@
+use atuin_client::{
+    fish_sync::notify::DIRTY_VAR,
+    settings::{FishSyncNotify, Settings},
+};
 use atuin_dotfiles::store::{AliasStore, var::VarStore};
 use eyre::Result;
 
@@ -92,3 +96,109 @@ pub async fn init(
 
     Ok(())
 }
+
+/// Syncs once when a session starts, so fish has the latest history to suggest from
+///
+/// The guard keeps a session that sources the init script again from syncing again.
+const STARTUP_SYNC: &str = "if not set -q _atuin_fish_sync_started
+    set -g _atuin_fish_sync_started 1
+    atuin sync >/dev/null 2>&1 &
+    disown
+end";
+
+/// Hooks for fish sync, or `None` when there's nothing to add
+///
+/// Without the daemon, a session runs a sync in the background when it starts. The daemon syncs
+/// on its own schedule, so sessions leave that to it. With `notify = "uvar"`, atuin bumps a
+/// universal variable after each batch it writes, whether from the CLI or the daemon, and a hook
+/// merges the history file whenever that variable changes.
+///
+/// Every hook is a function definition or guarded, so sourcing the output again is harmless.
+pub fn fish_sync_hooks(settings: &Settings, enabled: bool) -> Option<String> {
+    if !enabled {
+        return None;
+    }
+
+    let mut hooks = Vec::new();
+
+    if !settings.daemon.enabled {
+        hooks.push(STARTUP_SYNC.to_string());
+    }
+
+    if settings.fish_sync.notify == FishSyncNotify::Uvar {
+        hooks.push(format!(
+            "function _atuin_history_merge --on-variable {DIRTY_VAR}\n    history merge\nend"
+        ));
+    }
+
+    if hooks.is_empty() {
+        None
+    } else {
+        Some(hooks.join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atuin_client::settings::{Daemon, FishSync, Settings};
+
+    use super::*;
+
+    fn settings(daemon: bool, notify: FishSyncNotify) -> Settings {
+        Settings {
+            fish_sync: FishSync {
+                enabled: true,
+                notify,
+                ..FishSync::default()
+            },
+            daemon: Daemon {
+                enabled: daemon,
+                ..Daemon::default()
+            },
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let merge = "function _atuin_history_merge --on-variable _atuin_history_dirty
+    history merge
+end";
+        let startup = "if not set -q _atuin_fish_sync_started
+    set -g _atuin_fish_sync_started 1
+    atuin sync >/dev/null 2>&1 &
+    disown
+end";
+
+        let cases = [
+            (false, FishSyncNotify::None, Some(startup.to_string())),
+            (false, FishSyncNotify::Merge, Some(startup.to_string())),
+            (
+                false,
+                FishSyncNotify::Uvar,
+                Some(format!("{startup}\n\n{merge}")),
+            ),
+            (true, FishSyncNotify::None, None),
+            (true, FishSyncNotify::Merge, None),
+            (true, FishSyncNotify::Uvar, Some(merge.to_string())),
+        ];
+
+        for (daemon, notify, expected) in cases {
+            assert_eq!(
+                fish_sync_hooks(&settings(daemon, notify), true),
+                expected,
+                "daemon: {daemon}, notify: {notify:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_no_hooks_when_disabled() {
+        // whether fish sync is on comes from the caller, after `--fish-sync` and `--no-fish-sync`
+        let mut settings = settings(false, FishSyncNotify::Uvar);
+        assert_eq!(fish_sync_hooks(&settings, false), None);
+
+        settings.fish_sync.enabled = false;
+        assert!(fish_sync_hooks(&settings, true).is_some());
+    }
+}
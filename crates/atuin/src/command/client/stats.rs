@@ -5,12 +5,15 @@ use time::{Duration, OffsetDateTime, Time};
 
 use atuin_client::{
     database::{Database, current_context},
+    fish_sync::meta::FishSyncMeta,
     settings::Settings,
     theme::Theme,
 };
 
 use atuin_history::stats::{compute, pretty_print};
 
+use super::search::format_duration;
+
 fn parse_ngram_size(s: &str) -> Result<usize, String> {
     let value = s
         .parse::<usize>()
@@ -80,6 +83,38 @@ impl Cmd {
             pretty_print(stats, self.ngram_size, theme);
         }
 
+        if settings.fish_sync.enabled {
+            print_fish_sync(settings);
+        }
+
         Ok(())
     }
 }
+
+fn print_fish_sync(settings: &Settings) {
+    // Only reads the small meta file, never the fish history itself
+    let meta = match FishSyncMeta::load(&FishSyncMeta::path(settings)) {
+        Ok(meta) => meta,
+        Err(e) => {
+            log::warn!("failed to load fish sync meta: {e}");
+            return;
+        }
+    };
+
+    let last_sync = meta.last_sync().map_or_else(
+        || "never".to_string(),
+        |last| {
+            let ago = (OffsetDateTime::now_utc() - last).unsigned_abs();
+            format!("{} ago", format_duration(ago))
+        },
+    );
+
+    println!();
+    println!("Fish sync:");
+    println!(
+        "  commands mirrored to fish: {} (last sync {last_sync})",
+        meta.total_written
+    );
+    println!("  entries in fish history: {}", meta.fish_entries);
+    println!("  duplicates cleaned: {}", meta.duplicates_removed);
+}
@@ -1,14 +1,30 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
 use clap::Subcommand;
 use eyre::{Result, WrapErr};
+use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 
 use atuin_client::{
+    api_client,
     database::{Database, Sqlite},
     encryption,
-    fish_sync,
+    fish_sync::{
+        self, DownloadProgress, DownloadedHistory, ShellSyncSession,
+        audit::WriteSource,
+        lock::{LockPolicy, ShellSyncLock},
+        meta::FishSyncMeta,
+    },
     history::store::HistoryStore,
     record::{sqlite_store::SqliteStore, store::Store, sync},
     settings::Settings,
 };
+use atuin_common::record::RecordId;
+use time::OffsetDateTime;
 
 mod status;
 
@@ -22,6 +38,16 @@ pub enum Cmd {
         /// Force re-download everything
         #[arg(long, short)]
         force: bool,
+
+        /// Quick sync meant for shell init: skips if another sync is running or one ran
+        /// recently, and seeds fish history if fish sync is enabled
+        #[arg(long, conflicts_with = "force")]
+        startup: bool,
+
+        /// Skip the server and only update local shell history, as when the server can't be
+        /// reached
+        #[arg(long, conflicts_with_all = ["force", "startup"])]
+        offline: bool,
     },
 
     /// Login to the configured server
@@ -45,18 +71,28 @@ pub enum Cmd {
 }
 
 impl Cmd {
-    pub async fn run(
-        self,
-        settings: Settings,
-        db: &Sqlite,
-        store: SqliteStore,
-    ) -> Result<()> {
+    pub async fn run(self, mut settings: Settings, db: &Sqlite, store: SqliteStore) -> Result<()> {
+        if matches!(self, Self::Sync { .. }) {
+            fish_sync::disable_if_native(&mut settings);
+        }
+
         match self {
-            Self::Sync { force } => run(&settings, force, db, store).await,
+            Self::Sync { startup: true, .. } => run_startup(settings, db, store).await,
+            Self::Sync { force, offline, .. } => {
+                let _lock = sync::SyncLock::acquire(&settings)?;
+
+                if let Some(reason) = offline_reason(&settings, offline).await {
+                    println!("Offline ({reason}), skipping the server");
+                    run_offline(&settings, db).await?;
+                    std::process::exit(OFFLINE_EXIT_CODE);
+                }
+
+                run(&settings, force, db, store).await
+            }
             Self::Login(l) => l.run(&settings, &store).await,
             Self::Logout => account::logout::run(&settings),
             Self::Register(r) => r.run(&settings).await,
-            Self::Status => status::run(&settings, db).await,
+            Self::Status => status::run(&settings, db, &store).await,
             Self::Key { base64 } => {
                 use atuin_client::encryption::{encode_key, load_key};
                 let key = load_key(&settings).wrap_err("could not load encryption key")?;
@@ -75,12 +111,121 @@ impl Cmd {
     }
 }
 
-async fn run(
-    settings: &Settings,
-    force: bool,
-    db: &Sqlite,
-    store: SqliteStore,
-) -> Result<()> {
+/// Exit code for a sync that skipped the server but finished the local steps, so scripts can tell
+/// it apart from a full sync. `EX_TEMPFAIL`: trying again later may well work.
+const OFFLINE_EXIT_CODE: i32 = 75;
+
+/// Why this sync should skip the server, if it should
+async fn offline_reason(settings: &Settings, offline: bool) -> Option<String> {
+    if offline {
+        return Some("--offline".to_string());
+    }
+
+    let timeout_ms = settings.sync.offline_check_timeout_ms;
+    if timeout_ms == 0 {
+        return None;
+    }
+
+    let reachable =
+        api_client::server_reachable(&settings.sync_address, Duration::from_millis(timeout_ms))
+            .await;
+
+    (!reachable).then(|| {
+        format!(
+            "couldn't reach {} within {timeout_ms}ms",
+            settings.sync_address
+        )
+    })
+}
+
+/// The parts of a sync that don't need the server
+async fn run_offline(settings: &Settings, db: &Sqlite) -> Result<()> {
+    if settings.fish_sync.enabled {
+        let _lock = ShellSyncLock::acquire(settings, "offline sync", LockPolicy::Wait)?;
+        let written = fish_sync::sync_local(settings, db).await?;
+        println!("Wrote {written} local entries to Fish history");
+    }
+
+    println!("Local-only sync complete");
+
+    Ok(())
+}
+
+/// Network timeout for `--startup`, in seconds, so a slow link can't hold up shell init for long
+const STARTUP_NETWORK_TIMEOUT: u64 = 5;
+
+async fn run_startup(mut settings: Settings, db: &Sqlite, store: SqliteStore) -> Result<()> {
+    let meta_path = FishSyncMeta::path(&settings);
+    let fish_path = fish_sync::resolve_history_path(&settings)?;
+    let mut meta = FishSyncMeta::load_or_rebuild(&meta_path, &fish_path)?;
+    let now = OffsetDateTime::now_utc();
+
+    if !meta.startup_sync_due(settings.fish_sync.startup_interval_mins, now) {
+        return Ok(());
+    }
+
+    // Another sync already has this covered
+    let Some(_lock) = sync::SyncLock::try_acquire(&settings)? else {
+        return Ok(());
+    };
+
+    settings.network_timeout = settings.network_timeout.min(STARTUP_NETWORK_TIMEOUT);
+    settings.network_connect_timeout = settings
+        .network_connect_timeout
+        .min(STARTUP_NETWORK_TIMEOUT);
+
+    // one view of the fish history file for both steps below
+    let session = ShellSyncSession::new();
+
+    if settings.logged_in() && settings.sync.records {
+        let (_, downloaded) = sync::sync(&settings, &store).await?;
+        crate::sync::build(&settings, &store, db, Some(&downloaded)).await?;
+
+        if fish_sync::syncs_downloaded(&settings) {
+            let encryption_key: [u8; 32] = encryption::load_key(&settings)
+                .context("could not load encryption key")?
+                .into();
+            let host_id = Settings::host_id().expect("failed to get host_id");
+            let history_store = HistoryStore::new(store.clone(), host_id, encryption_key);
+            let downloaded = DownloadedHistory::resolve(&history_store, &downloaded).await;
+
+            let _lock = ShellSyncLock::acquire(&settings, "startup sync", LockPolicy::Wait)?;
+            fish_sync::sync_downloaded_entries_in_session(
+                &session,
+                &settings,
+                db,
+                &downloaded,
+                WriteSource::Cli,
+                |_| ControlFlow::Continue(()),
+            )
+            .await?;
+        }
+    }
+
+    if settings.fish_sync.enabled
+        && let Some(_lock) =
+            ShellSyncLock::acquire(&settings, "startup bootstrap", LockPolicy::Skip)?
+        && fish_sync::bootstrap_pending(&settings)?
+    {
+        let written = fish_sync::bootstrap_in_session(&session, &settings, db).await?;
+        println!("Seeded fish history with {written} entries");
+    }
+
+    if settings.fish_sync.enabled {
+        let _lock = ShellSyncLock::acquire(&settings, "startup trim", LockPolicy::Wait)?;
+        session.finish()?;
+    }
+
+    // The other counters may have moved on while we synced
+    meta = FishSyncMeta::load_or_rebuild(&meta_path, &fish_path)?;
+    meta.record_startup_sync(now);
+    meta.save(&meta_path)
+}
+
+async fn run(settings: &Settings, force: bool, db: &Sqlite, store: SqliteStore) -> Result<()> {
+    // one view of the fish history file for every fish step in this run
+    let session = ShellSyncSession::new();
+
     if settings.sync.records {
         let encryption_key: [u8; 32] = encryption::load_key(settings)
             .context("could not load encryption key")?
@@ -119,25 +264,22 @@ async fn run(
             println!("{uploaded}/{} up/down to record store", downloaded.len());
 
             // Sync downloaded remote entries to Fish history after second sync
-            if !downloaded.is_empty() && settings.fish_sync.enabled {
-                println!("Syncing {} remote entries to Fish history...", downloaded.len());
-                if let Err(e) = fish_sync::sync_downloaded_entries(settings, db, &downloaded).await {
-                    eprintln!("Failed to sync to fish history: {}", e);
-                }
-            }
+            sync_to_fish(settings, db, &history_store, &downloaded, &session).await?;
         } else {
             // Sync downloaded remote entries to Fish history after first sync
-            if !downloaded.is_empty() && settings.fish_sync.enabled {
-                println!("Syncing {} remote entries to Fish history...", downloaded.len());
-                if let Err(e) = fish_sync::sync_downloaded_entries(settings, db, &downloaded).await {
-                    eprintln!("Failed to sync to fish history: {}", e);
-                }
-            }
+            sync_to_fish(settings, db, &history_store, &downloaded, &session).await?;
         }
     } else {
         atuin_client::sync::sync(settings, force, db).await?;
     }
 
+    if settings.fish_sync.enabled {
+        let _lock = ShellSyncLock::acquire(settings, "sync trim", LockPolicy::Wait)?;
+        if let Err(e) = session.finish() {
+            eprintln!("Failed to trim fish history: {e}");
+        }
+    }
+
     println!(
         "Sync complete! {} items in history database, force: {}",
         db.history_count(true).await?,
@@ -146,3 +288,145 @@ async fn run(
 
     Ok(())
 }
+
+async fn sync_to_fish(
+    settings: &Settings,
+    db: &Sqlite,
+    history_store: &HistoryStore,
+    downloaded: &[RecordId],
+    session: &ShellSyncSession,
+) -> Result<()> {
+    if !fish_sync::syncs_downloaded(settings) {
+        return Ok(());
+    }
+
+    let downloaded = DownloadedHistory::resolve(history_store, downloaded).await;
+
+    // keep the daemon from writing, or bootstrapping, in between our batches
+    let _lock = ShellSyncLock::acquire(settings, "sync", LockPolicy::Wait)?;
+
+    if !downloaded.is_empty() {
+        println!(
+            "Syncing {} remote entries to Fish history...",
+            downloaded.len()
+        );
+    }
+
+    // Ctrl-C stops after the batch being written, leaving the rest for the next sync
+    let stop = Arc::new(AtomicBool::new(false));
+    let ctrl_c = tokio::spawn({
+        let stop = stop.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                stop.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+
+    let mut report = ProgressReport::new();
+    let result = fish_sync::sync_downloaded_entries_in_session(
+        session,
+        settings,
+        db,
+        &downloaded,
+        WriteSource::Cli,
+        |progress| {
+            report.update(progress);
+
+            if stop.load(Ordering::Relaxed) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        },
+    )
+    .await;
+
+    ctrl_c.abort();
+    let last = report.finish();
+
+    match result {
+        Ok(summary) => {
+            if downloaded.history_records() > 0 || summary.written > 0 {
+                println!(
+                    "{} history records downloaded, {} synced to fish",
+                    downloaded.history_records(),
+                    summary.written
+                );
+            }
+
+            if let Some(last) = last {
+                println!("{summary}");
+
+                if last.loaded < last.total {
+                    println!(
+                        "Stopped, {} entries will be written by the next sync",
+                        last.total - last.loaded
+                    );
+                }
+            }
+        }
+        Err(e) => eprintln!("Failed to sync to fish history: {e}"),
+    }
+
+    Ok(())
+}
+
+/// How often progress is logged when stdout isn't a terminal
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shows how far writing to fish history has got: a progress bar on a terminal, a line every
+/// [`PROGRESS_LOG_INTERVAL`] otherwise
+struct ProgressReport {
+    bar: Option<ProgressBar>,
+    tty: bool,
+    last: Option<DownloadProgress>,
+    last_logged: Duration,
+}
+
+impl ProgressReport {
+    fn new() -> Self {
+        Self {
+            bar: None,
+            tty: std::io::stdout().is_terminal(),
+            last: None,
+            last_logged: Duration::ZERO,
+        }
+    }
+
+    fn update(&mut self, progress: &DownloadProgress) {
+        self.last = Some(*progress);
+
+        if self.tty {
+            let bar = self.bar.get_or_insert_with(|| {
+                let bar = ProgressBar::new(progress.total as u64);
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} ({eta}) {msg}",
+                    )
+                    .unwrap()
+                    .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+                        write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap();
+                    })
+                    .progress_chars("#>-"),
+                );
+                bar
+            });
+
+            bar.set_position(progress.loaded as u64);
+            bar.set_message(format!("{} written", progress.written));
+        } else if progress.elapsed >= self.last_logged + PROGRESS_LOG_INTERVAL {
+            self.last_logged = progress.elapsed;
+            println!("Fish history: {progress}");
+        }
+    }
+
+    /// Clear the bar, returning the last progress reported, if there was any
+    fn finish(self) -> Option<DownloadProgress> {
+        if let Some(bar) = self.bar {
+            bar.finish_and_clear();
+        }
+
+        self.last
+    }
+}
@@ -1,11 +1,17 @@
 use std::path::PathBuf;
 
 use crate::{SHA, VERSION};
-use atuin_client::{api_client, database::Database, settings::Settings};
+use atuin_client::{
+    api_client,
+    database::Database,
+    fish_sync::consistency,
+    record::{sqlite_store::SqliteStore, store::Store},
+    settings::Settings,
+};
 use colored::Colorize;
 use eyre::{Result, bail};
 
-pub async fn run(settings: &Settings, db: &impl Database) -> Result<()> {
+pub async fn run(settings: &Settings, db: &impl Database, store: &SqliteStore) -> Result<()> {
     let session_path = settings.session_path.as_str();
 
     if !PathBuf::from(session_path).exists() {
@@ -31,7 +37,9 @@ pub async fn run(settings: &Settings, db: &impl Database) -> Result<()> {
         println!("Last sync: {}", last_sync.to_offset(settings.timezone.0));
     }
 
-    if !settings.sync.records {
+    if settings.sync.records {
+        print_consistency(settings, db, store).await?;
+    } else {
         let local_count = db.history_count(false).await?;
         let deleted_count = db.history_count(true).await? - local_count;
 
@@ -47,3 +55,33 @@ pub async fn run(settings: &Settings, db: &impl Database) -> Result<()> {
 
     Ok(())
 }
+
+/// Compare the history index, the record store and the fish history file
+pub async fn print_consistency(
+    settings: &Settings,
+    db: &impl Database,
+    store: &SqliteStore,
+) -> Result<()> {
+    #[allow(clippy::cast_sign_loss)]
+    let index = db.history_count(true).await? as u64;
+    let store = store.len_tag("history").await?;
+
+    match consistency::check(settings, index, store) {
+        Ok(consistency) => {
+            println!("Consistency: {consistency}");
+
+            for note in consistency.notes() {
+                if note.is_anomaly() {
+                    println!("  {}", note.to_string().yellow());
+                } else {
+                    println!("  {note}");
+                }
+            }
+        }
+        Err(e) => println!("Consistency: couldn't check the fish history file ({e})"),
+    }
+
+    println!();
+
+    Ok(())
+}